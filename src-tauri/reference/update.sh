@@ -0,0 +1,13 @@
+#!/bin/sh
+# Refresh the commune and EPCI reference shipped with the application from the
+# geo.api.gouv.fr API. Run from the src-tauri folder, then commit the updated files.
+set -e
+
+curl -fsS -G "https://geo.api.gouv.fr/communes" \
+    --data-urlencode "fields=code,nom,codesPostaux,codeDepartement,codeRegion,codeEpci" \
+    --data-urlencode "format=json" \
+    -o reference/communes.json
+curl -fsS -G "https://geo.api.gouv.fr/epcis" \
+    --data-urlencode "fields=code,nom" \
+    --data-urlencode "format=json" \
+    -o reference/epcis.json
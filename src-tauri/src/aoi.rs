@@ -0,0 +1,173 @@
+use crate::qgis_api_wrapper::{get_buffered_extent_wgs84, prepare_clip_layer};
+use crate::utils::{get_departement_crs, DEPARTEMENTS};
+use crate::web_request::{download_commune_contour, get_departement_at};
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The number of points sampled along each side of the area of interest to find the
+/// departments it crosses.
+const SAMPLES_PER_SIDE: usize = 5;
+
+/// The area a project is restricted to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AreaOfInterest {
+    /// A rectangle in the coordinates of the project CRS.
+    BoundingBox {
+        xmin: f64,
+        ymin: f64,
+        xmax: f64,
+        ymax: f64,
+    },
+    /// A commune, by INSEE code or by name.
+    Commune { query: String },
+    /// A polygon file readable by QGIS, in any CRS.
+    PolygonFile { path: String },
+}
+
+/// An area of interest and the buffer, in metres, added around it before clipping.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClipArea {
+    pub area: AreaOfInterest,
+    #[serde(default)]
+    pub buffer: f64,
+}
+
+/// Get the path of the clip layer of a project.
+pub fn clip_layer_path(project_name: &str) -> String {
+    format!("resources/QGIS/{}/aoi.gpkg", project_name)
+}
+
+/// Build the clip layer of a project: the area of interest, buffered and in the project CRS.
+///
+/// The clip layer is built again on every call, replacing the one of a previous area.
+/// # Parameters
+/// - `project_name`: A string slice that holds the name of the project.
+/// - `clip_area`: The area of interest and its buffer.
+/// - `crs`: A string slice that holds the project CRS, like "EPSG:2154".
+/// # Returns
+/// - The path of the clip layer or an error message.
+pub async fn prepare_clip_area(
+    project_name: &str,
+    clip_area: &ClipArea,
+    crs: &str,
+) -> Result<String, Box<dyn Error>> {
+    let output_path = clip_layer_path(project_name);
+    if Path::new(&output_path).exists() {
+        fs::remove_file(&output_path)?;
+    }
+
+    let source_path = materialize_area(
+        &clip_area.area,
+        &format!("resources/QGIS/{}/aoi_source.geojson", project_name),
+        crs,
+    )
+    .await?;
+
+    prepare_clip_layer(&source_path, &output_path, clip_area.buffer, crs)?;
+    Ok(output_path)
+}
+
+/// Get a file holding the area of interest, writing it to `path` if it is not a file already.
+/// A bounding box is written with `crs` as its CRS.
+async fn materialize_area(
+    area: &AreaOfInterest,
+    path: &str,
+    crs: &str,
+) -> Result<String, Box<dyn Error>> {
+    match area {
+        AreaOfInterest::BoundingBox {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        } => {
+            fs::write(
+                path,
+                bounding_box_geojson(*xmin, *ymin, *xmax, *ymax, crs).to_string(),
+            )?;
+            Ok(path.to_string())
+        }
+        AreaOfInterest::Commune { query } => {
+            download_commune_contour(query, path).await?;
+            Ok(path.to_string())
+        }
+        AreaOfInterest::PolygonFile { path } => Ok(path.clone()),
+    }
+}
+
+/// Suggest the departments an area of interest crosses, besides the chosen one.
+///
+/// The buffered extent of the area is sampled on a grid and each point is looked up,
+/// so a department only touched between two samples can be missed.
+/// # Parameters
+/// - `code`: A string slice that holds the code of the chosen department.
+/// - `clip_area`: The area of interest and its buffer.
+/// # Returns
+/// - The sorted codes of the other departments found in `DEPARTEMENTS`, or an error message.
+pub async fn suggest_departements(
+    code: &str,
+    clip_area: &ClipArea,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let crs = get_departement_crs(code).epsg;
+    let source_path = materialize_area(&clip_area.area, "tmp/aoi_suggestion.geojson", crs).await?;
+    let (xmin, ymin, xmax, ymax) = get_buffered_extent_wgs84(&source_path, clip_area.buffer, crs)?;
+
+    let step =
+        |min: f64, max: f64, i: usize| min + (max - min) * i as f64 / (SAMPLES_PER_SIDE - 1) as f64;
+    let lookups = (0..SAMPLES_PER_SIDE).flat_map(|i| {
+        (0..SAMPLES_PER_SIDE).map(move |j| {
+            let lookup = get_departement_at(step(xmin, xmax, i), step(ymin, ymax, j));
+            async move { lookup.await.ok().flatten() }
+        })
+    });
+
+    let mut codes: Vec<String> = join_all(lookups)
+        .await
+        .into_iter()
+        .flatten()
+        .filter(|found| found != code && DEPARTEMENTS.contains_key(found))
+        .collect();
+    codes.sort();
+    codes.dedup();
+    Ok(codes)
+}
+
+/// Build a GeoJSON feature collection holding a rectangle.
+/// # Parameters
+/// - `xmin`, `ymin`, `xmax`, `ymax`: The bounds of the rectangle, in `crs` coordinates.
+/// - `crs`: A string slice that holds the CRS of the bounds, like "EPSG:2154".
+pub fn bounding_box_geojson(
+    xmin: f64,
+    ymin: f64,
+    xmax: f64,
+    ymax: f64,
+    crs: &str,
+) -> serde_json::Value {
+    let crs_urn = format!("urn:ogc:def:crs:EPSG::{}", crs.trim_start_matches("EPSG:"));
+    json!({
+        "type": "FeatureCollection",
+        "crs": {
+            "type": "name",
+            "properties": { "name": crs_urn }
+        },
+        "features": [{
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [[
+                    [xmin, ymin],
+                    [xmax, ymin],
+                    [xmax, ymax],
+                    [xmin, ymax],
+                    [xmin, ymin]
+                ]]
+            }
+        }]
+    })
+}
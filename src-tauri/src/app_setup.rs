@@ -1,4 +1,6 @@
 use crate::dependency::{check_dependencies, DependencyError};
+use crate::manifest::install_default_manifest;
+use crate::styles::install_default_styles;
 use crate::utils::create_directory_if_not_exists;
 use std::fmt;
 
@@ -9,6 +11,8 @@ use std::fmt;
 pub fn setup_check() -> Result<(), String> {
     create_directory_if_not_exists("resources/QGIS").map_err(|e| e.to_string())?;
     create_directory_if_not_exists("tmp").map_err(|e| e.to_string())?;
+    install_default_manifest().map_err(|e| e.to_string())?;
+    install_default_styles().map_err(|e| e.to_string())?;
     check_dependencies().map_err(|e| e.to_string())?;
     Ok(())
 }
@@ -0,0 +1,278 @@
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::export::Extent;
+use crate::pipeline::PipelineState;
+use crate::presets::{export_preset, with_preset_project, ExportPreset};
+use crate::qgis_api_wrapper::get_feature_extents;
+use crate::utils::create_directory_if_not_exists;
+use crate::web_request::download_commune_contour;
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The name of the summary of a batch, written in its output folder.
+pub const BATCH_REPORT_FILE: &str = "report.json";
+/// The folder holding the folders the commune contours of the batches are downloaded to.
+const CONTOURS_FOLDER: &str = "tmp/batch_contours";
+/// The number of commune contours downloaded at the same time.
+const CONTOUR_DOWNLOADS: usize = 4;
+
+/// The number of batches located by this process, naming their contour folders.
+static LOCATED_BATCHES: AtomicUsize = AtomicUsize::new(0);
+
+/// The areas of interest of a batch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BatchAreas {
+    /// Every polygon of a file readable by QGIS, in any CRS.
+    PolygonFile {
+        path: String,
+        /// The field naming the areas, the feature ID if None.
+        #[serde(default)]
+        name_field: Option<String>,
+    },
+    /// Communes by INSEE code, like "2A004".
+    Communes { codes: Vec<String> },
+}
+
+/// The same preset exported over many areas of a project.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchExport {
+    pub areas: BatchAreas,
+    /// The name of the export preset.
+    pub preset: String,
+    /// The distance added on every side of the areas, in metres.
+    #[serde(default)]
+    pub buffer: f64,
+    /// The folder holding one folder per area and the report.
+    pub output_folder: String,
+}
+
+/// An area of a batch, or the reason it could not be located.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchArea {
+    pub name: String,
+    pub extent: Result<Extent, String>,
+}
+
+/// The outcome of the export of an area.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AreaReport {
+    pub name: String,
+    /// The output folder of the area, relative to the batch output folder.
+    pub folder: String,
+    pub extent: Option<Extent>,
+    /// The number of images written.
+    pub files: usize,
+    pub error: Option<String>,
+    pub seconds: f64,
+}
+
+/// The summary of a batch, written as `report.json`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BatchReport {
+    pub project: String,
+    pub preset: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub seconds: f64,
+    pub areas: Vec<AreaReport>,
+}
+
+impl BatchReport {
+    fn new(project: &str, preset: &str, areas: Vec<AreaReport>, seconds: f64) -> Self {
+        let failed = areas.iter().filter(|area| area.error.is_some()).count();
+        BatchReport {
+            project: project.to_string(),
+            preset: preset.to_string(),
+            succeeded: areas.len() - failed,
+            failed,
+            seconds,
+            areas,
+        }
+    }
+}
+
+/// Name the output folder of an area, unique among `taken`.
+/// # Parameters
+/// - `name`: A string slice that holds the name of the area.
+/// - `taken`: The folder names already given.
+pub fn area_folder_name(name: &str, taken: &[String]) -> String {
+    let base: String = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let base = if base.is_empty() {
+        "area".to_string()
+    } else {
+        base
+    };
+    let mut folder = base.clone();
+    let mut suffix = 2;
+    while taken.contains(&folder) {
+        folder = format!("{}_{}", base, suffix);
+        suffix += 1;
+    }
+    folder
+}
+
+/// Locate the areas of a batch, in the project CRS and grown by the buffer.
+///
+/// The commune contours are downloaded `CONTOUR_DOWNLOADS` at a time into a folder of
+/// their own, removed once the areas are located. A commune not found is kept with its
+/// error, so the other areas are still exported.
+/// # Parameters
+/// - `areas`: The areas of the batch.
+/// - `crs`: A string slice that holds the project CRS.
+/// - `buffer`: The distance added on every side of the areas, in metres.
+/// # Returns
+/// - The areas in the order given, or an error message if the polygon file is unreadable.
+pub async fn resolve_areas(
+    areas: &BatchAreas,
+    crs: &str,
+    buffer: f64,
+) -> Result<Vec<BatchArea>, Box<dyn Error>> {
+    match areas {
+        BatchAreas::PolygonFile { path, name_field } => {
+            Ok(
+                get_feature_extents(path, crs, name_field.as_deref(), buffer)?
+                    .into_iter()
+                    .map(|(name, extent)| BatchArea {
+                        name,
+                        extent: Ok(extent),
+                    })
+                    .collect(),
+            )
+        }
+        BatchAreas::Communes { codes } => {
+            let folder = format!(
+                "{}/{}-{}",
+                CONTOURS_FOLDER,
+                std::process::id(),
+                LOCATED_BATCHES.fetch_add(1, Ordering::Relaxed)
+            );
+            create_directory_if_not_exists(&folder)?;
+            let downloads: Vec<(String, Result<String, String>)> = stream::iter(codes.clone())
+                .map(|code| {
+                    let path = format!("{}/{}.geojson", folder, code.trim());
+                    async move {
+                        let result = download_commune_contour(&code, &path)
+                            .await
+                            .map(|_| path)
+                            .map_err(|e| format!("Contour of commune '{}' not found: {}", code, e));
+                        (code, result)
+                    }
+                })
+                .buffered(CONTOUR_DOWNLOADS)
+                .collect()
+                .await;
+
+            let located = downloads
+                .into_iter()
+                .map(|(code, path)| {
+                    let extent = path.and_then(|path| {
+                        get_feature_extents(&path, crs, None, buffer)
+                            .map_err(|e| e.to_string())?
+                            .into_iter()
+                            .next()
+                            .map(|(_, extent)| extent)
+                            .ok_or_else(|| format!("Commune '{}' has no contour", code))
+                    });
+                    BatchArea { name: code, extent }
+                })
+                .collect();
+            fs::remove_dir_all(&folder)?;
+            Ok(located)
+        }
+    }
+}
+
+/// Export a preset over every area of a batch, one folder per area, and write the report.
+///
+/// The areas are exported one after the other: QGIS holds a single project, so the
+/// renderings of a process cannot overlap. A failed area is recorded in the report and
+/// the batch goes on.
+/// # Parameters
+/// - `state`: The state of the pipeline of the project, left unchanged, the areas being
+///   rendered from a copy styled with the preset palette, see `with_preset_project`.
+/// - `preset`: The preset.
+/// - `areas`: The located areas, see `resolve_areas`.
+/// - `batch`: The output folder of the batch.
+/// - `token`: The cancellation token checked before each area.
+/// - `report`: Called after each area with the number of areas done and the total.
+/// # Returns
+/// - The report of the batch or an error message.
+pub fn run_batch<F>(
+    state: &PipelineState,
+    preset: &ExportPreset,
+    areas: &[BatchArea],
+    batch: &BatchExport,
+    token: &CancellationToken,
+    report: F,
+) -> Result<BatchReport, Box<dyn Error>>
+where
+    F: Fn(usize, usize),
+{
+    let started = Instant::now();
+    create_directory_if_not_exists(&batch.output_folder)?;
+    let mut folders: Vec<String> = Vec::with_capacity(areas.len());
+    for area in areas {
+        let folder = area_folder_name(&area.name, &folders);
+        folders.push(folder);
+    }
+
+    let area_reports = with_preset_project(state, preset, |project_file| {
+        let mut area_reports = Vec::with_capacity(areas.len());
+        for (index, (area, folder)) in areas.iter().zip(&folders).enumerate() {
+            let area_started = Instant::now();
+            let exported = match (&area.extent, token.check()) {
+                (_, Err(cancelled)) => Err(cancelled.to_string()),
+                (Err(e), _) => Err(e.clone()),
+                (Ok(extent), Ok(())) => export_preset(
+                    state,
+                    project_file,
+                    preset,
+                    *extent,
+                    &format!("{}/{}", batch.output_folder, folder),
+                    token,
+                    |_, _| {},
+                )
+                .map_err(|e| e.to_string()),
+            };
+            area_reports.push(AreaReport {
+                name: area.name.clone(),
+                folder: folder.clone(),
+                extent: area.extent.as_ref().ok().copied(),
+                files: exported.as_ref().map_or(0, |manifest| manifest.files.len()),
+                error: exported.err(),
+                seconds: area_started.elapsed().as_secs_f64(),
+            });
+            report(index + 1, areas.len());
+        }
+        Ok(area_reports)
+    })?;
+    let batch_report = BatchReport::new(
+        &state.name,
+        &preset.name,
+        area_reports,
+        started.elapsed().as_secs_f64(),
+    );
+    fs::write(
+        format!("{}/{}", batch.output_folder, BATCH_REPORT_FILE),
+        serde_json::to_string_pretty(&batch_report)?,
+    )?;
+
+    if token.is_cancelled() {
+        return Err(Box::new(Cancelled));
+    }
+    Ok(batch_report)
+}
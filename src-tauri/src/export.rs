@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// The largest width or height of an exported image, in pixels.
+pub const MAX_IMAGE_SIZE: u32 = 32767;
+/// The DPI of the exports that do not give one.
+pub const DEFAULT_DPI: f64 = 96.0;
+/// The number of metres in an inch, to convert a scale and a DPI to a ground resolution.
+const METRES_PER_INCH: f64 = 0.0254;
+
+/// The image formats a map can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Tiff,
+    /// A TIFF embedding the CRS and the geotransform of the map, its pixels indexed on a
+    /// palette of the rendered colours. The map must hold at most 256 colours.
+    GeoTiff,
+}
+
+impl ImageFormat {
+    /// Get the name of the format for the Qt image writer.
+    /// A GeoTIFF is rendered to a PNG first, then converted by GDAL.
+    pub fn qt_format(&self) -> &'static str {
+        match self {
+            ImageFormat::Png | ImageFormat::GeoTiff => "PNG",
+            ImageFormat::Jpeg => "JPG",
+            ImageFormat::Tiff => "TIFF",
+        }
+    }
+
+    /// Get the usual file extension of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Tiff | ImageFormat::GeoTiff => "tif",
+        }
+    }
+
+    /// Get the extension of the world file of the format, like "pgw" for PNG.
+    pub fn world_file_extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "pgw",
+            ImageFormat::Jpeg => "jgw",
+            ImageFormat::Tiff | ImageFormat::GeoTiff => "tfw",
+        }
+    }
+}
+
+/// A rectangle in the coordinates of a CRS.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Extent {
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+}
+
+impl Extent {
+    pub fn width(&self) -> f64 {
+        self.xmax - self.xmin
+    }
+
+    pub fn height(&self) -> f64 {
+        self.ymax - self.ymin
+    }
+}
+
+/// How finely the map is rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    /// The ground size of a pixel, in metres.
+    MetresPerPixel(f64),
+    /// The scale denominator, like 25000 for 1:25000, printed at the DPI of the export.
+    Scale(f64),
+}
+
+/// What to export from a project and how.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapExport {
+    /// The exported area, in the coordinates of `crs`.
+    pub extent: Extent,
+    /// The CRS the map is rendered in. Its unit is expected to be the metre.
+    pub crs: String,
+    pub resolution: Resolution,
+    /// The resolution the symbol sizes in millimetres are converted with.
+    #[serde(default = "default_dpi")]
+    pub dpi: f64,
+    /// The layers and groups rendered, nested groups separated by '/'.
+    /// The visible layers of the project if empty.
+    #[serde(default)]
+    pub layers: Vec<String>,
+    pub format: ImageFormat,
+    pub output_path: String,
+    /// Smooth the edges of the features. Without it, the image only holds the colours of
+    /// the symbols, as needed to classify its pixels or to index them on a palette.
+    /// Defaults to true, except for the GeoTIFF format, see `MapExport::antialiasing`.
+    #[serde(default)]
+    pub antialiasing: Option<bool>,
+    /// Also write a world file and a .aux.xml holding the CRS next to the image, for the
+    /// formats that do not embed their georeferencing.
+    #[serde(default)]
+    pub world_file: bool,
+}
+
+/// An image written by an export, and the area it covers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RenderedMap {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    /// The extent actually rendered, see `MapExport::pixel_extent`.
+    pub extent: Extent,
+    pub crs: String,
+}
+
+/// The reasons an export fails.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportError {
+    /// The extent is empty or its bounds are swapped.
+    InvalidExtent,
+    /// The ground resolution, the scale or the DPI is not strictly positive.
+    InvalidResolution,
+    /// The image would be wider or higher than `MAX_IMAGE_SIZE`.
+    TooLarge { width: u64, height: u64 },
+    /// Layers or groups of the export are not in the project.
+    LayersNotFound(Vec<String>),
+    /// QGIS failed to render or to write the image.
+    Qgis(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::InvalidExtent => write!(f, "The export extent is empty"),
+            ExportError::InvalidResolution => {
+                write!(f, "The export resolution must be strictly positive")
+            }
+            ExportError::TooLarge { width, height } => write!(
+                f,
+                "The exported image would be {}x{} pixels, more than {} pixels wide or high",
+                width, height, MAX_IMAGE_SIZE
+            ),
+            ExportError::LayersNotFound(names) => {
+                write!(f, "Layers not found in the project: {}", names.join(", "))
+            }
+            ExportError::Qgis(message) => write!(f, "QGIS export failed: {}", message),
+        }
+    }
+}
+
+impl Error for ExportError {}
+
+fn default_dpi() -> f64 {
+    DEFAULT_DPI
+}
+
+impl MapExport {
+    /// Check whether the edges of the features are smoothed: when asked, and by default
+    /// for every format but GeoTIFF, whose palette only holds the colours of the symbols.
+    pub fn antialiasing(&self) -> bool {
+        self.antialiasing
+            .unwrap_or(self.format != ImageFormat::GeoTiff)
+    }
+
+    /// Get the ground size of a pixel, in metres.
+    pub fn metres_per_pixel(&self) -> Result<f64, ExportError> {
+        let metres_per_pixel = match self.resolution {
+            Resolution::MetresPerPixel(metres_per_pixel) => metres_per_pixel,
+            Resolution::Scale(scale) => scale * METRES_PER_INCH / self.dpi,
+        };
+        if !(metres_per_pixel > 0.0 && self.dpi > 0.0 && metres_per_pixel.is_finite()) {
+            return Err(ExportError::InvalidResolution);
+        }
+        Ok(metres_per_pixel)
+    }
+
+    /// Get the size of the exported image, the partial pixels on the right and bottom
+    /// sides being rounded up.
+    /// # Returns
+    /// - The width and height in pixels, or the reason the export is not possible.
+    pub fn pixel_size(&self) -> Result<(u32, u32), ExportError> {
+        let extent = &self.extent;
+        if !(extent.width() > 0.0 && extent.height() > 0.0) {
+            return Err(ExportError::InvalidExtent);
+        }
+        let metres_per_pixel = self.metres_per_pixel()?;
+        // Drop the floating point noise so an extent of exactly N pixels is not rounded up.
+        let width = (extent.width() / metres_per_pixel - 1e-6).ceil().max(1.0) as u64;
+        let height = (extent.height() / metres_per_pixel - 1e-6).ceil().max(1.0) as u64;
+        if width > MAX_IMAGE_SIZE as u64 || height > MAX_IMAGE_SIZE as u64 {
+            return Err(ExportError::TooLarge { width, height });
+        }
+        Ok((width as u32, height as u32))
+    }
+
+    /// Get the extent covered by the exported image: the requested extent, anchored on its
+    /// top left corner and grown to a whole number of pixels.
+    pub fn pixel_extent(&self) -> Result<Extent, ExportError> {
+        let (width, height) = self.pixel_size()?;
+        let metres_per_pixel = self.metres_per_pixel()?;
+        Ok(Extent {
+            xmin: self.extent.xmin,
+            ymin: self.extent.ymax - height as f64 * metres_per_pixel,
+            xmax: self.extent.xmin + width as f64 * metres_per_pixel,
+            ymax: self.extent.ymax,
+        })
+    }
+}
+
+impl RenderedMap {
+    /// Get the GDAL geotransform of the image: the top left corner and the pixel size.
+    pub fn geotransform(&self) -> [f64; 6] {
+        let pixel_width = self.extent.width() / self.width as f64;
+        let pixel_height = self.extent.height() / self.height as f64;
+        [
+            self.extent.xmin,
+            pixel_width,
+            0.0,
+            self.extent.ymax,
+            0.0,
+            -pixel_height,
+        ]
+    }
+
+    /// Get the content of the world file of the image, which locates the centre of the
+    /// top left pixel.
+    pub fn world_file(&self) -> String {
+        let [xmin, pixel_width, _, ymax, _, pixel_height] = self.geotransform();
+        format!(
+            "{}\n0\n0\n{}\n{}\n{}\n",
+            pixel_width,
+            pixel_height,
+            xmin + pixel_width / 2.0,
+            ymax + pixel_height / 2.0
+        )
+    }
+}
+
+/// Get the path of the world file of an image, like "map.pgw" for "map.png".
+pub fn world_file_path(image_path: &str, format: ImageFormat) -> String {
+    Path::new(image_path)
+        .with_extension(format.world_file_extension())
+        .to_string_lossy()
+        .to_string()
+}
@@ -0,0 +1,247 @@
+use crate::export::Extent;
+use crate::pipeline::ArchiveState;
+use serde::{Deserialize, Serialize};
+
+/// The resolution of the PNG layouts that do not give one, fit for printing.
+pub const DEFAULT_LAYOUT_DPI: f64 = 300.0;
+/// The title of the legend of the layouts that do not give one.
+pub const DEFAULT_LEGEND_TITLE: &str = "Légende";
+/// The blank border around the page content, in millimetres.
+const PAGE_MARGIN: f64 = 10.0;
+/// The height of the title band, in millimetres.
+const TITLE_HEIGHT: f64 = 12.0;
+/// The height of the band below the map holding the scale bar and the attribution.
+const FOOTER_HEIGHT: f64 = 12.0;
+/// The space between the page items, in millimetres.
+const GAP: f64 = 4.0;
+/// The narrowest legend column, in millimetres.
+const MIN_LEGEND_WIDTH: f64 = 45.0;
+/// The width and height of the north arrow, in millimetres.
+const NORTH_ARROW_SIZE: f64 = 15.0;
+/// The width of the scale bar area, in millimetres.
+const SCALE_BAR_WIDTH: f64 = 70.0;
+
+/// The ISO 216 paper sizes of a layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PageSize {
+    A0,
+    A1,
+    A2,
+    A3,
+    #[default]
+    A4,
+}
+
+impl PageSize {
+    /// Get the width and height of the page in portrait, in millimetres.
+    pub fn portrait_size(&self) -> (f64, f64) {
+        match self {
+            PageSize::A0 => (841.0, 1189.0),
+            PageSize::A1 => (594.0, 841.0),
+            PageSize::A2 => (420.0, 594.0),
+            PageSize::A3 => (297.0, 420.0),
+            PageSize::A4 => (210.0, 297.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Portrait,
+    #[default]
+    Landscape,
+}
+
+/// The file formats a layout can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutFormat {
+    Pdf,
+    Png,
+}
+
+/// A printable map of a project and its decorations.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayoutExport {
+    pub title: String,
+    /// The area shown, in the coordinates of `crs`, grown to the shape of the map frame.
+    pub extent: Extent,
+    pub crs: String,
+    /// The layers and groups shown and listed in the legend, nested groups separated by
+    /// '/'. The visible layers of the project if empty.
+    #[serde(default)]
+    pub layers: Vec<String>,
+    #[serde(default)]
+    pub page_size: PageSize,
+    #[serde(default)]
+    pub orientation: Orientation,
+    pub format: LayoutFormat,
+    /// The resolution of the PNG layouts and of the rasters embedded in the PDF layouts.
+    #[serde(default = "default_layout_dpi")]
+    pub dpi: f64,
+    /// The title printed above the legend.
+    #[serde(default = "default_legend_title")]
+    pub legend_title: String,
+    /// The source text printed below the map, the IGN editions of the project if None.
+    #[serde(default)]
+    pub attribution: Option<String>,
+    pub output_path: String,
+}
+
+/// A rectangle of a page, in millimetres from its top left corner.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Frame {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Where the items of a layout are placed on its page.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayoutFrames {
+    pub title: Frame,
+    pub map: Frame,
+    pub legend: Frame,
+    pub north_arrow: Frame,
+    pub scale_bar: Frame,
+    pub attribution: Frame,
+}
+
+/// The edition of an IGN product, read from the name of its archive.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceEdition {
+    /// The product, like "BDTOPO".
+    pub product: String,
+    /// The version of the product, like "3.4".
+    pub version: String,
+    /// The publication date, like "2024-06-15".
+    pub date: String,
+}
+
+fn default_layout_dpi() -> f64 {
+    DEFAULT_LAYOUT_DPI
+}
+
+fn default_legend_title() -> String {
+    DEFAULT_LEGEND_TITLE.to_string()
+}
+
+impl LayoutExport {
+    /// Get the width and height of the page, in millimetres.
+    pub fn page_dimensions(&self) -> (f64, f64) {
+        let (short, long) = self.page_size.portrait_size();
+        match self.orientation {
+            Orientation::Portrait => (short, long),
+            Orientation::Landscape => (long, short),
+        }
+    }
+
+    /// Get the frames of the layout items: the title above the map, the legend in a column
+    /// on its right, the north arrow in its top right corner, and the scale bar and the
+    /// attribution below it.
+    pub fn frames(&self) -> LayoutFrames {
+        let (width, height) = self.page_dimensions();
+        let content_width = width - 2.0 * PAGE_MARGIN;
+        let legend_width = (content_width * 0.22).max(MIN_LEGEND_WIDTH);
+        let map = Frame {
+            x: PAGE_MARGIN,
+            y: PAGE_MARGIN + TITLE_HEIGHT + GAP,
+            width: content_width - legend_width - GAP,
+            height: height - 2.0 * PAGE_MARGIN - TITLE_HEIGHT - FOOTER_HEIGHT - 2.0 * GAP,
+        };
+        let footer_y = map.y + map.height + GAP;
+        LayoutFrames {
+            title: Frame {
+                x: PAGE_MARGIN,
+                y: PAGE_MARGIN,
+                width: content_width,
+                height: TITLE_HEIGHT,
+            },
+            map,
+            legend: Frame {
+                x: map.x + map.width + GAP,
+                y: map.y,
+                width: legend_width,
+                height: map.height,
+            },
+            north_arrow: Frame {
+                x: map.x + map.width - NORTH_ARROW_SIZE - GAP,
+                y: map.y + GAP,
+                width: NORTH_ARROW_SIZE,
+                height: NORTH_ARROW_SIZE,
+            },
+            scale_bar: Frame {
+                x: map.x,
+                y: footer_y,
+                width: SCALE_BAR_WIDTH,
+                height: FOOTER_HEIGHT,
+            },
+            attribution: Frame {
+                x: map.x + SCALE_BAR_WIDTH + GAP,
+                y: footer_y,
+                width: content_width - SCALE_BAR_WIDTH - GAP,
+                height: FOOTER_HEIGHT,
+            },
+        }
+    }
+}
+
+/// Read the edition of an IGN product from the URL of its archive, like
+/// `.../BDTOPO_3-4_TOUSTHEMES_SHP_LAMB93_D02A_2024-06-15.7z`.
+/// # Parameters
+/// - `url`: A string slice that holds the URL of the archive.
+/// # Returns
+/// - The edition, None if the archive name does not follow the IGN naming.
+pub fn source_edition(url: &str) -> Option<SourceEdition> {
+    let file_name = url.rsplit('/').next()?;
+    let stem = file_name.split('.').next()?;
+    let parts: Vec<&str> = stem.split('_').collect();
+    let (product, version, date) = (parts.first()?, parts.get(1)?, parts.last()?);
+    let is_date = date.len() == 10
+        && date.chars().enumerate().all(|(i, c)| {
+            if i == 4 || i == 7 {
+                c == '-'
+            } else {
+                c.is_ascii_digit()
+            }
+        });
+    if product.is_empty() || parts.len() < 3 || !is_date {
+        return None;
+    }
+    Some(SourceEdition {
+        product: product.to_string(),
+        version: version.replace('-', "."),
+        date: date.to_string(),
+    })
+}
+
+/// Get the source text of the maps of a project, listing the distinct IGN editions of its
+/// archives, like "Sources: IGN BDTOPO 3.4 (2024-06-15), IGN BDFORET 2.0 (2017-05-10)".
+/// # Parameters
+/// - `archives`: The archives the project was built from.
+pub fn attribution(archives: &[ArchiveState]) -> String {
+    let mut editions: Vec<SourceEdition> = Vec::new();
+    for edition in archives
+        .iter()
+        .filter_map(|archive| source_edition(&archive.url))
+    {
+        if !editions.contains(&edition) {
+            editions.push(edition);
+        }
+    }
+    if editions.is_empty() {
+        return "Source: IGN".to_string();
+    }
+    let sources: Vec<String> = editions
+        .iter()
+        .map(|edition| {
+            format!(
+                "IGN {} {} ({})",
+                edition.product, edition.version, edition.date
+            )
+        })
+        .collect();
+    format!("Sources: {}", sources.join(", "))
+}
@@ -4,4 +4,20 @@ pub mod slicing;
 pub mod web_request;
 pub mod qgis_api_wrapper;
 pub mod dependency;
-pub mod app_setup;
\ No newline at end of file
+pub mod app_setup;
+pub mod aoi;
+pub mod batch;
+pub mod manifest;
+pub mod cancellation;
+pub mod pipeline;
+pub mod export;
+pub mod layout;
+pub mod masks;
+pub mod palette;
+pub mod presets;
+pub mod progress;
+pub mod rasterize;
+pub mod styles;
+pub mod symbology;
+pub mod territory;
+pub mod tiles;
\ No newline at end of file
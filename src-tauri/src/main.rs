@@ -1,249 +1,822 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use eve_mapmaker::aoi::{self, ClipArea};
 use eve_mapmaker::app_setup::setup_check;
-use eve_mapmaker::utils::{get_departement_list, get_previous_projects, layer_full_extraction};
-use eve_mapmaker::web_request::{download_shp_file, get_departement_shp_file_url};
-use eve_mapmaker::{qgis_api_wrapper::*, utils};
+use eve_mapmaker::batch::{self, BatchExport, BatchReport};
+use eve_mapmaker::cancellation::CancellationToken;
+use eve_mapmaker::export::{Extent, MapExport, RenderedMap};
+use eve_mapmaker::layout::{self, LayoutExport};
+use eve_mapmaker::manifest::{self, LayerManifest, DEFAULT_PROFILE};
+use eve_mapmaker::masks::{self, MaskExport, MaskRaster};
+use eve_mapmaker::palette::{self, Palette};
+use eve_mapmaker::pipeline::{self, PipelineState};
+use eve_mapmaker::presets::{self, ExportManifest, ExportPreset};
+use eve_mapmaker::progress::PipelineSummary;
+use eve_mapmaker::qgis_api_wrapper;
+use eve_mapmaker::rasterize::{self, RasterizeExport};
+use eve_mapmaker::slicing::{self, OverlapRule, SliceExport, TileIndex};
+use eve_mapmaker::symbology::{CategoryUpdate, LayerRenderer};
+use eve_mapmaker::territory::{self, AdminReference, CommuneMatch, Region};
+use eve_mapmaker::tiles::{self, TilePyramid};
+use eve_mapmaker::utils;
+use eve_mapmaker::utils::{get_departement_list, get_previous_projects};
 use std::collections::HashMap;
-use std::path::Path;
+use std::sync::Mutex;
+use tauri::http::ResponseBuilder;
 use tauri::Manager;
 
-//---------------------------------------------------------tauri commands---------------------------------------------------------
+/// Holds the cancellation tokens of the project creations currently running, by project name.
+#[derive(Default)]
+struct CreationState(Mutex<HashMap<String, CancellationToken>>);
+
+/// Holds the cancellation tokens of the batch exports currently running, by project name.
+#[derive(Default)]
+struct BatchState(Mutex<HashMap<String, CancellationToken>>);
+
+/// Register the cancellation token of a run on a project, refusing a second concurrent
+/// run on the same project.
+/// # Parameters
+/// - `tokens`: The tokens of the runs in progress, by project name.
+/// - `name`: A string slice that holds the name of the project.
+/// - `what`: A string slice that names the kind of run, for the error message.
+/// # Returns
+/// - Result<CancellationToken, String> : The token of the new run.
+fn register_run(
+    tokens: &Mutex<HashMap<String, CancellationToken>>,
+    name: &str,
+    what: &str,
+) -> Result<CancellationToken, String> {
+    let mut tokens = tokens.lock().unwrap();
+    if tokens.contains_key(name) {
+        return Err(format!(
+            "A {} is already running on project '{}'",
+            what, name
+        ));
+    }
+    let token = CancellationToken::new();
+    tokens.insert(name.to_string(), token.clone());
+    Ok(token)
+}
 
-//TODO : CHANGE THE STEPS ORDER IN THE FRONTEND
+/// Cancel the run in progress on a project, if any.
+fn cancel_run(tokens: &Mutex<HashMap<String, CancellationToken>>, name: &str) {
+    if let Some(token) = tokens.lock().unwrap().get(name) {
+        token.cancel();
+    }
+}
+
+//---------------------------------------------------------tauri commands---------------------------------------------------------
 
 #[tauri::command]
-/// Create a new project with the given code and name.
+/// Create a new project with the given department codes and name.
+///
+/// If the project already has a pipeline state for the same departments, the creation
+/// resumes from its first incomplete step.
+/// Projects spanning several departments get one merged dataset per layer.
+///
+/// Every step emits `ProgressEvent`s on `progress-update`, and a `PipelineSummary` is
+/// emitted on `progress-summary` once the pipeline stops.
+/// If the creation is cancelled with `cancel_project_creation`, the partial outputs
+/// are removed and a `cancelled` event is emitted with the project name.
 ///
 /// # Parameters
-/// - `code`: A string slice that holds the code of the department.
+/// - `codes`: The codes of the departments.
 /// - `name`: A string slice that holds the name of the project.
+/// - `profile`: The manifest profile listing the layers, the default profile if None.
+/// - `palette`: The palette the layers are styled with, the project palette if None.
+/// - `crs`: The CRS every layer is reprojected to, the official CRS of the first department if None.
+/// - `aoi`: The area of interest every layer is clipped to, the whole departments if None.
 ///
 /// # Returns
 /// - Result<(), String>
 async fn open_new_project(
     app_handle: tauri::AppHandle,
-    code: String,
+    state: tauri::State<'_, CreationState>,
+    codes: Vec<String>,
     name: String,
+    profile: Option<String>,
+    palette: Option<String>,
+    crs: Option<String>,
+    aoi: Option<ClipArea>,
 ) -> Result<(), String> {
-    app_handle
-        .emit_all("progress-update", "Recherche des fichiers")
-        .map_err(|e| format!("Error emitting progress update: {:?}", e))?;
+    if codes.is_empty() {
+        return Err("No departement selected".to_string());
+    }
+
+    let token = register_run(&state.0, &name, "project creation")?;
+    let result = create_project(
+        &app_handle,
+        &token,
+        &codes,
+        &name,
+        profile,
+        palette,
+        crs,
+        aoi,
+    )
+    .await;
+    state.0.lock().unwrap().remove(&name);
+    result
+}
 
-    let urls = get_shp_file_urls(&code).await?;
+/// Run the pipeline of a project creation registered in the `CreationState`.
+#[allow(clippy::too_many_arguments)]
+async fn create_project(
+    app_handle: &tauri::AppHandle,
+    token: &CancellationToken,
+    codes: &[String],
+    name: &str,
+    profile: Option<String>,
+    palette: Option<String>,
+    crs: Option<String>,
+    aoi: Option<ClipArea>,
+) -> Result<(), String> {
+    let layer_manifest = LayerManifest::load(profile.as_deref().unwrap_or(DEFAULT_PROFILE))
+        .map_err(|e| format!("Error loading manifest: {:?}", e))?;
+    let mut pipeline_state = PipelineState::load_or_new(
+        name,
+        codes,
+        &layer_manifest,
+        crs.as_deref().filter(|crs| !crs.is_empty()),
+        aoi,
+    )
+    .map_err(|e| format!("Error loading pipeline state: {:?}", e))?;
+    if let Some(palette) = palette {
+        pipeline_state.palette = palette;
+    }
 
-    println!("urls: {:?}", urls);
+    let result = pipeline::run_pipeline(&mut pipeline_state, token, |event| {
+        app_handle
+            .emit_all("progress-update", event)
+            .map_err(|e| format!("Error emitting progress update: {:?}", e))
+    })
+    .await;
+
+    if token.is_cancelled() {
+        let cleaned = pipeline::clean_cancelled_run(&mut pipeline_state);
+        app_handle
+            .emit_all("cancelled", name)
+            .map_err(|e| format!("Error emitting cancellation: {:?}", e))?;
+        cleaned?;
+        return Err("Project creation cancelled".to_string());
+    }
 
     app_handle
-        .emit_all("progress-update", "Téléchargement des données")
-        .map_err(|e| format!("Error emitting progress update: {:?}", e))?;
+        .emit_all(
+            "progress-summary",
+            PipelineSummary::from_state(&pipeline_state),
+        )
+        .map_err(|e| format!("Error emitting progress summary: {:?}", e))?;
 
-    download_shp_files(&urls, &code).await?;
-
-    println!("files downloaded");
+    result
+}
 
-    app_handle
-        .emit_all("progress-update", "Initialisation du projet")
-        .map_err(|e| format!("Error emitting progress update: {:?}", e))?;
+#[tauri::command]
+/// Cancel the creation of a project, if it is running.
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+fn cancel_project_creation(state: tauri::State<'_, CreationState>, name: String) {
+    cancel_run(&state.0, &name);
+}
 
-    pyo3::prepare_freethreaded_python();
+#[tauri::command]
+/// Get the pipeline state of a project, to show its steps and failed layers.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+///
+/// # Returns
+/// - Result<Option<PipelineState>, String> : The state, None if the project has no state file.
+fn get_pipeline_state(name: String) -> Result<Option<PipelineState>, String> {
+    PipelineState::load(&name).map_err(|e| format!("Error loading pipeline state: {:?}", e))
+}
 
-    initialize_qgis_app_path().map_err(|e| format!("Error initializing QGIS app path: {:?}", e))?;
+#[tauri::command]
+/// Run the stages of a single failed layer of a project again.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `layer`: A string slice that holds the name of the layer in the project.
+///
+/// # Returns
+/// - Result<PipelineState, String> : The updated state of the pipeline.
+async fn retry_layer(name: String, layer: String) -> Result<PipelineState, String> {
+    let mut pipeline_state = PipelineState::load(&name)
+        .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+        .ok_or_else(|| format!("Project '{}' has no pipeline state", name))?;
 
-    create_blank_project(&name).map_err(|e| format!("Error creating QGIS project: {:?}", e))?;
+    pipeline::retry_layer(&mut pipeline_state, &layer, &CancellationToken::new())?;
 
-    println!("project created");
+    Ok(pipeline_state)
+}
 
-    app_handle
-        .emit_all("progress-update", "Preparation des Couches")
-        .map_err(|e| format!("Error emitting progress update: {:?}", e))?;
+#[tauri::command]
+/// Replace the attribute filter of a layer of a project.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `layer`: A string slice that holds the name of the layer in the project.
+/// - `filter`: The QGIS expression selecting the features to show, None to show them all.
+///
+/// # Returns
+/// - Result<PipelineState, String> : The updated state of the pipeline.
+fn edit_layer_filter(
+    name: String,
+    layer: String,
+    filter: Option<String>,
+) -> Result<PipelineState, String> {
+    let mut pipeline_state = PipelineState::load(&name)
+        .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+        .ok_or_else(|| format!("Project '{}' has no pipeline state", name))?;
 
-    prepare_layers(&name, &code)?;
+    pipeline::edit_layer_filter(&mut pipeline_state, &layer, filter)?;
 
-    println!("layers prepared");
+    Ok(pipeline_state)
+}
 
-    app_handle
-        .emit_all("progress-update", "Finalisation")
-        .map_err(|e| format!("Error emitting progress update: {:?}", e))?;
+#[tauri::command]
+/// Get the current renderer of a layer of a project, with its categories if categorized.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `layer`: A string slice that holds the name of the layer in the project.
+///
+/// # Returns
+/// - Result<LayerRenderer, String> : The renderer or an error message.
+fn get_layer_renderer(name: String, layer: String) -> Result<LayerRenderer, String> {
+    pipeline::initialize_qgis()?;
+    qgis_api_wrapper::get_layer_renderer(&pipeline::project_file_path(&name), &layer)
+        .map_err(|e| format!("Error reading renderer of layer '{}': {:?}", layer, e))
+}
 
-    println!("done");
+#[tauri::command]
+/// Update several categories of a layer categorized by `field`, the others untouched.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `layer`: A string slice that holds the name of the layer in the project.
+/// - `field`: A string slice that holds the attribute the layer is categorized by.
+/// - `updates`: The changes to apply, one per category.
+///
+/// # Returns
+/// - Result<LayerRenderer, String> : The updated renderer or an error message.
+fn update_layer_categories(
+    name: String,
+    layer: String,
+    field: String,
+    updates: Vec<CategoryUpdate>,
+) -> Result<LayerRenderer, String> {
+    pipeline::initialize_qgis()?;
+    let project_file = pipeline::project_file_path(&name);
+    qgis_api_wrapper::update_layer_categories(&project_file, &layer, &field, &updates)
+        .map_err(|e| format!("Error updating categories of layer '{}': {:?}", layer, e))?;
+    qgis_api_wrapper::get_layer_renderer(&project_file, &layer)
+        .map_err(|e| format!("Error reading renderer of layer '{}': {:?}", layer, e))
+}
 
+#[tauri::command]
+/// Load a .qml or .sld style file onto a layer of a project.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `layer`: A string slice that holds the name of the layer in the project.
+/// - `path`: A string slice that holds the path of the style file.
+///
+/// # Returns
+/// - Result<(), String>
+fn load_layer_style(name: String, layer: String, path: String) -> Result<(), String> {
+    pipeline::initialize_qgis()?;
+    qgis_api_wrapper::load_layer_style(&pipeline::project_file_path(&name), &layer, &path)
+        .map_err(|e| format!("Error loading style of layer '{}': {:?}", layer, e))?;
     Ok(())
 }
 
 #[tauri::command]
-/// Get the list of departments.
+/// Save the current style of a layer of a project to a .qml or .sld file.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `layer`: A string slice that holds the name of the layer in the project.
+/// - `path`: A string slice that holds the path of the style file.
 ///
 /// # Returns
-/// - HashMap<String, String> : A hashmap containing the code and name of the departments.
-fn get_dpts_list() -> HashMap<String, String> {
-    return get_departement_list();
+/// - Result<(), String>
+fn save_layer_style(name: String, layer: String, path: String) -> Result<(), String> {
+    pipeline::initialize_qgis()?;
+    qgis_api_wrapper::save_layer_style(&pipeline::project_file_path(&name), &layer, &path)
+        .map_err(|e| format!("Error saving style of layer '{}': {:?}", layer, e))?;
+    Ok(())
 }
 
 #[tauri::command]
-fn get_projects() -> HashMap<String, Vec<String>> {
-    match get_previous_projects() {
-        Ok(projects) => projects,
-        Err(_) => HashMap::new(),
-    }
+/// Export layers of a project to an image.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `export`: The extent, resolution, layers, format and output path of the image.
+///
+/// # Returns
+/// - Result<RenderedMap, String> : The written image and the area it covers.
+fn export_map(name: String, export: MapExport) -> Result<RenderedMap, String> {
+    pipeline::initialize_qgis()?;
+    qgis_api_wrapper::export_map(&pipeline::project_file_path(&name), &export)
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn get_os() -> String {
-    return utils::get_operating_system().to_string();
+/// Export a printable layout of a project to PDF or PNG, with a title, a legend, a scale
+/// bar, a north arrow and the sources of the data.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `export`: The title, extent, layers, page and output of the layout.
+///
+/// # Returns
+/// - Result<String, String> : The path of the written layout.
+async fn export_layout(name: String, export: LayoutExport) -> Result<String, String> {
+    let attribution = match &export.attribution {
+        Some(attribution) => attribution.clone(),
+        None => {
+            let archives = PipelineState::load(&name)
+                .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+                .map(|pipeline_state| pipeline_state.archives)
+                .unwrap_or_default();
+            layout::attribution(&archives)
+        }
+    };
+
+    pipeline::initialize_qgis()?;
+    qgis_api_wrapper::export_layout(&pipeline::project_file_path(&name), &export, &attribution)
+        .map_err(|e| format!("Error exporting layout of project '{}': {}", name, e))
 }
 
-//---------------------------------------------------------main---------------------------------------------------------
+#[tauri::command]
+/// Export a project as a grid of georeferenced tiles, with an index file describing the grid.
+///
+/// A `(done, total)` pair is emitted on `slicing-progress` after each tile.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `export`: The grid, layers, format and output folder of the tiles.
+///
+/// # Returns
+/// - Result<TileIndex, String> : The index of the grid.
+async fn slice_map(
+    app_handle: tauri::AppHandle,
+    name: String,
+    export: SliceExport,
+) -> Result<TileIndex, String> {
+    pipeline::initialize_qgis()?;
+    slicing::slice_map(
+        &pipeline::project_file_path(&name),
+        &export,
+        &CancellationToken::new(),
+        |done, total| {
+            let _ = app_handle.emit_all("slicing-progress", (done, total));
+        },
+    )
+    .map_err(|e| format!("Error slicing project '{}': {}", name, e))
+}
 
-fn main() {
-    setup_check().expect("Setup check failed");
+#[tauri::command]
+/// Assemble the tiles of a sliced map into one GeoTIFF.
+///
+/// # Parameters
+/// - `index_path`: A string slice that holds the path of the index file of the tiles.
+/// - `tiles_folder`: The folder of the tile images, the folder of the index file if None.
+/// - `output_path`: A string slice that holds the path of the GeoTIFF.
+/// - `rule`: How the overlaps of the tiles are resolved, split in their middle if None.
+///
+/// # Returns
+/// - Result<RenderedMap, String> : The written mosaic and the area it covers.
+async fn mosaic_tiles(
+    index_path: String,
+    tiles_folder: Option<String>,
+    output_path: String,
+    rule: Option<OverlapRule>,
+) -> Result<RenderedMap, String> {
+    pipeline::initialize_qgis()?;
+    slicing::mosaic(
+        &index_path,
+        tiles_folder.as_deref(),
+        &output_path,
+        rule.unwrap_or_default(),
+    )
+    .map_err(|e| format!("Error assembling tiles of '{}': {}", index_path, e))
+}
 
-    tauri::Builder::default()
-        .invoke_handler(tauri::generate_handler![
-            open_new_project,
-            get_dpts_list,
-            get_projects,
-            get_os
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+#[tauri::command]
+/// Render a project into a Web Mercator tile pyramid, served on `tiles://{name}/{z}/{x}/{y}.png`.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `pyramid`: The zoom range, storage and extent of the pyramid.
+///
+/// # Returns
+/// - Result<String, String> : The path of the folder or file of the tiles.
+async fn generate_tile_pyramid(name: String, pyramid: TilePyramid) -> Result<String, String> {
+    pipeline::initialize_qgis()?;
+    tiles::generate_pyramid(&name, &pipeline::project_file_path(&name), &pyramid)
+        .map_err(|e| format!("Error generating tiles of project '{}': {}", name, e))
 }
 
-//---------------------------------------------------------functions---------------------------------------------------------
+#[tauri::command]
+/// Export each layer or group of a project into its own aligned class raster.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `export`: The extent, resolution, targets, encoding and output folder of the masks.
+///
+/// # Returns
+/// - Result<Vec<MaskRaster>, String> : The written masks.
+async fn export_masks(name: String, export: MaskExport) -> Result<Vec<MaskRaster>, String> {
+    let palette_name = PipelineState::load(&name)
+        .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+        .map(|pipeline_state| pipeline_state.palette)
+        .unwrap_or_else(|| palette::DEFAULT_PALETTE.to_string());
+    let palette = Palette::load(&palette_name)
+        .map_err(|e| format!("Error loading palette '{}': {:?}", palette_name, e))?;
+
+    pipeline::initialize_qgis()?;
+    masks::export_masks(&pipeline::project_file_path(&name), &export, &palette)
+        .map_err(|e| format!("Error exporting masks of project '{}': {}", name, e))
+}
 
-/// Get the urls of the shp files for the given department code.
+#[tauri::command]
+/// Burn the layers of a project into a class raster, straight from their datasets.
+///
 /// # Parameters
-/// - `code`: A string slice that holds the code of the department.
+/// - `name`: A string slice that holds the name of the project.
+/// - `export`: The extent, resolution and output path of the raster.
+///
 /// # Returns
-/// - Result<Vec<String>, String> : A vector containing the urls of the shp files.
-async fn get_shp_file_urls(code: &str) -> Result<Vec<String>, String> {
-    let url1 = get_departement_shp_file_url(
-        code,
-        "https://geoservices.ign.fr/bdtopo#telechargementgpkgreg",
-    )
-    .await
-    .map_err(|e| format!("Error getting shp file url1: {:?}", e))?;
+/// - Result<RenderedMap, String> : The written GeoTIFF and the area it covers.
+async fn rasterize_project(name: String, export: RasterizeExport) -> Result<RenderedMap, String> {
+    let pipeline_state = PipelineState::load(&name)
+        .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+        .ok_or_else(|| format!("Project '{}' has no pipeline state", name))?;
+
+    pipeline::initialize_qgis()?;
+    rasterize::rasterize_project(&pipeline_state, &export)
+        .map_err(|e| format!("Error rasterizing project '{}': {}", name, e))
+}
 
-    let url2 =
-        get_departement_shp_file_url(code, "https://geoservices.ign.fr/bdforet#telechargementv2")
-            .await
-            .map_err(|e| format!("Error getting shp file url2: {:?}", e))?;
+#[tauri::command]
+/// Get the available export presets, the saved ones then the bundled ones.
+fn get_export_presets() -> Result<Vec<ExportPreset>, String> {
+    presets::get_presets().map_err(|e| format!("Error listing export presets: {:?}", e))
+}
 
-    Ok(vec![url1, url2])
+#[tauri::command]
+/// Save an export preset, replacing the saved preset of the same name.
+///
+/// # Parameters
+/// - `preset`: The preset to save.
+///
+/// # Returns
+/// - Result<(), String>
+fn save_export_preset(preset: ExportPreset) -> Result<(), String> {
+    presets::save_preset(&preset).map_err(|e| format!("Error saving export preset: {:?}", e))
 }
 
-/// Download the shp file from the given url.
+#[tauri::command]
+/// Delete a saved export preset.
+///
 /// # Parameters
-/// - `url`: A string slice that holds the url of the shp file.
-/// - `code`: A string slice that holds the code of the department.
+/// - `preset`: A string slice that holds the name of the preset.
+///
 /// # Returns
-/// - Result<(), String> : An empty result or an error message.
-async fn download_shp_files(urls: &[String], code: &str) -> Result<(), String> {
-    println!("downloading shp files");
-    if Path::new(format!("tmp/BDTOPO_{}.7z", code).as_str()).exists()
-        && Path::new(format!("tmp/BDFORET_{}.7z", code).as_str()).exists()
-    {
-        return Ok(());
-    }
+/// - Result<(), String>
+fn delete_export_preset(preset: String) -> Result<(), String> {
+    presets::delete_preset(&preset).map_err(|e| format!("Error deleting export preset: {:?}", e))
+}
 
-    for url in urls {
-        download_shp_file(url, code)
-            .await
-            .map_err(|e| format!("Error downloading shp file from {}: {:?}", url, e))?;
-        println!("downloaded shp file from {}", url);
-    }
-    println!("done downloading shp files");
-    Ok(())
+#[tauri::command]
+/// Run an export preset against a project, writing its images and a manifest in a folder.
+///
+/// A `(done, total)` pair is emitted on `preset-progress` after each image.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `preset`: A string slice that holds the name of the preset.
+/// - `extent`: The exported area, in the project CRS, the area of interest if None.
+/// - `output_folder`: A string slice that holds the path of the output folder.
+///
+/// # Returns
+/// - Result<ExportManifest, String> : The manifest of the output folder.
+async fn run_export_preset(
+    app_handle: tauri::AppHandle,
+    name: String,
+    preset: String,
+    extent: Option<Extent>,
+    output_folder: String,
+) -> Result<ExportManifest, String> {
+    let preset = presets::get_preset(&preset).map_err(|e| e.to_string())?;
+    let pipeline_state = PipelineState::load(&name)
+        .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+        .ok_or_else(|| format!("Project '{}' has no pipeline state", name))?;
+
+    pipeline::initialize_qgis()?;
+    presets::run_preset(
+        &pipeline_state,
+        &preset,
+        extent,
+        &output_folder,
+        &CancellationToken::new(),
+        |done, total| {
+            let _ = app_handle.emit_all("preset-progress", (done, total));
+        },
+    )
+    .map_err(|e| {
+        format!(
+            "Error running preset '{}' on project '{}': {}",
+            preset.name, name, e
+        )
+    })
 }
 
-//TODO : FIX THIS
-/// Prepare the layers for the given project.
+#[tauri::command]
+/// Export a preset over many areas of a project, one output folder per area, and write a
+/// report of the batch.
+///
+/// A `(done, total)` pair is emitted on `batch-progress` after each area.
+///
 /// # Parameters
 /// - `name`: A string slice that holds the name of the project.
-/// - `code`: A string slice that holds the code of the department.
+/// - `batch`: The areas, preset and output folder of the batch.
+///
 /// # Returns
-/// - Result<(), String> : An empty result or an error message.
-fn prepare_layers(name: &str, code: &str) -> Result<(), String> {
-    create_tree_group(&format!("resources/QGIS/{}/{}.qgz", name, name))
-        .map_err(|e| format!("Error creating tree group: {:?}", e))?;
+/// - Result<BatchReport, String> : The outcome of every area.
+async fn batch_export(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<'_, BatchState>,
+    name: String,
+    batch: BatchExport,
+) -> Result<BatchReport, String> {
+    let token = register_run(&state.0, &name, "batch export")?;
+    let result = run_batch_export(&app_handle, &token, &name, &batch).await;
+    state.0.lock().unwrap().remove(&name);
+    result
+}
 
-    layer_full_extraction(
-        "BDFORET",
-        code,
-        "FORMATION_VEGETALE",
-        &format!("{}/Vegetation", name),
-        None,
-    )
-    .map_err(|e| format!("Error extracting layer1: {:?}", e))?;
-
-    load_vector_layer_to_project(
-        &format!("resources/QGIS/{}/{}.qgz", name, name),
-        &format!(
-            "resources/QGIS/{}/Vegetation/FORMATION_VEGETALE/FORMATION_VEGETALE.shp",
-            name
-        ),
-        "BDFORET",
+/// Run a batch export registered in the `BatchState`.
+async fn run_batch_export(
+    app_handle: &tauri::AppHandle,
+    token: &CancellationToken,
+    name: &str,
+    batch: &BatchExport,
+) -> Result<BatchReport, String> {
+    let preset = presets::get_preset(&batch.preset).map_err(|e| e.to_string())?;
+    let pipeline_state = PipelineState::load(name)
+        .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+        .ok_or_else(|| format!("Project '{}' has no pipeline state", name))?;
+
+    pipeline::initialize_qgis()?;
+    let areas = batch::resolve_areas(&batch.areas, &pipeline_state.crs, batch.buffer)
+        .await
+        .map_err(|e| format!("Error locating the areas of the batch: {}", e))?;
+    batch::run_batch(
+        &pipeline_state,
+        &preset,
+        &areas,
+        batch,
+        token,
+        |done, total| {
+            let _ = app_handle.emit_all("batch-progress", (done, total));
+        },
     )
-    .map_err(|e| format!("Error loading layer to project: {:?}", e))?;
-
-    //TODO : add parcelles layer and search for sea layer
-
-    let _ = setup_basic_veg_layer(&format!("resources/QGIS/{}/{}.qgz", name, name), "BDFORET");
-
-    let topo_layers = [
-        "TERRAIN_DE_SPORT",
-        "RESERVOIR",
-        "CONSTRUCTION_SURFACIQUE",
-        "BATIMENT",
-        "PLAN_D_EAU",
-        "COURS_D_EAU",
-        "ZONE_D_HABITATION",
-        "TRONCON_DE_ROUTE",
-        "TRONCON_DE_VOIE_FERREE",
-        "PISTE_D_AERODROME",
-        "ZONE_D_ESTRAN",
-        "EQUIPEMENT_DE_TRANSPORT",
-        "AERODROME",
-    ];
-
-    for layer in topo_layers.iter() {
-        match layer_full_extraction(
-            "BDTOPO",
-            code,
-            layer,
-            &format!("{}/Topographie", name),
-            Some(layer),
-        ) {
-            Ok(_) => println!("Layer '{}' extracted successfully.", layer),
-            Err(e) => {
-                println!("Error extracting layer '{}': {:?}", layer, e);
-                continue;
-            }
-        }
+    .map_err(|e| format!("Error running batch on project '{}': {}", name, e))
+}
 
-        match load_vector_layer_to_project(
-            &format!("resources/QGIS/{}/{}.qgz", name, name),
-            &format!(
-                "resources/QGIS/{}/Topographie/{}/{}.shp",
-                name, layer, layer
-            ),
-            layer,
-        ) {
-            Ok(_) => println!("Layer '{}' loaded successfully.", layer),
-            Err(e) => {
-                println!("Error loading layer '{}': {:?}", layer, e);
-                continue;
-            }
-        }
+#[tauri::command]
+/// Cancel the batch export running on a project, if any. The areas not exported yet are
+/// reported as cancelled.
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+fn cancel_batch_export(state: tauri::State<'_, BatchState>, name: String) {
+    cancel_run(&state.0, &name);
+}
 
-        match setup_basic_topo_layer(&format!("resources/QGIS/{}/{}.qgz", name, name), layer) {
-            Ok(_) => println!("Layer '{}' setup successfully.", layer),
-            Err(e) => println!("Error setting up layer '{}': {:?}", layer, e),
-        }
+#[tauri::command]
+/// Switch the palette of a project and style its layers again.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the project.
+/// - `palette`: A string slice that holds the name of the palette.
+///
+/// # Returns
+/// - Result<PipelineState, String> : The updated state of the pipeline.
+async fn set_project_palette(name: String, palette: String) -> Result<PipelineState, String> {
+    let mut pipeline_state = PipelineState::load(&name)
+        .map_err(|e| format!("Error loading pipeline state: {:?}", e))?
+        .ok_or_else(|| format!("Project '{}' has no pipeline state", name))?;
+
+    pipeline::set_project_palette(&mut pipeline_state, &palette)?;
+
+    Ok(pipeline_state)
+}
+
+#[tauri::command]
+/// Get the names of the available palettes.
+fn get_palettes() -> Result<Vec<String>, String> {
+    palette::get_palettes().map_err(|e| format!("Error listing palettes: {:?}", e))
+}
+
+#[tauri::command]
+/// Get a palette by name.
+///
+/// # Parameters
+/// - `name`: A string slice that holds the name of the palette.
+///
+/// # Returns
+/// - Result<Palette, String> : The palette or an error message.
+fn get_palette(name: String) -> Result<Palette, String> {
+    Palette::load(&name).map_err(|e| format!("Error loading palette: {:?}", e))
+}
+
+#[tauri::command]
+/// Save a palette under its name, replacing the palette if it exists.
+///
+/// # Parameters
+/// - `palette`: The palette to save.
+///
+/// # Returns
+/// - Result<(), String>
+fn save_palette(palette: Palette) -> Result<(), String> {
+    palette
+        .save()
+        .map_err(|e| format!("Error saving palette: {:?}", e))
+}
+
+#[tauri::command]
+/// Get the names of the available manifest profiles.
+fn get_manifest_profiles() -> Result<Vec<String>, String> {
+    manifest::get_manifest_profiles().map_err(|e| format!("Error listing manifests: {:?}", e))
+}
+
+#[tauri::command]
+/// Get the manifest of a profile.
+///
+/// # Parameters
+/// - `profile`: A string slice that holds the name of the profile.
+///
+/// # Returns
+/// - Result<LayerManifest, String> : The manifest or an error message.
+fn get_manifest(profile: String) -> Result<LayerManifest, String> {
+    LayerManifest::load(&profile).map_err(|e| format!("Error loading manifest: {:?}", e))
+}
+
+#[tauri::command]
+/// Save a manifest as a profile, replacing the profile if it exists.
+///
+/// # Parameters
+/// - `profile`: A string slice that holds the name of the profile.
+/// - `manifest`: The manifest to save.
+///
+/// # Returns
+/// - Result<(), String>
+fn save_manifest(profile: String, manifest: LayerManifest) -> Result<(), String> {
+    manifest
+        .save(&profile)
+        .map_err(|e| format!("Error saving manifest: {:?}", e))
+}
+
+#[tauri::command]
+/// Suggest the departments an area of interest crosses, besides the chosen one.
+///
+/// # Parameters
+/// - `code`: A string slice that holds the code of the chosen department.
+/// - `aoi`: The area of interest and its buffer.
+///
+/// # Returns
+/// - Result<HashMap<String, String>, String> : The code and name of the suggested departments.
+async fn suggest_departements(
+    code: String,
+    aoi: ClipArea,
+) -> Result<HashMap<String, String>, String> {
+    pipeline::initialize_qgis()?;
+    let codes = aoi::suggest_departements(&code, &aoi)
+        .await
+        .map_err(|e| format!("Error suggesting departements: {:?}", e))?;
+
+    Ok(codes
+        .into_iter()
+        .filter_map(|code| utils::get_departement_name(&code).map(|name| (code, name)))
+        .collect())
+}
+
+#[tauri::command]
+/// Search the communes by name, INSEE code or postal code.
+///
+/// The commune reference is loaded on the first search.
+///
+/// # Parameters
+/// - `query`: A string slice that holds the searched name or code.
+///
+/// # Returns
+/// - Result<Vec<CommuneMatch>, String> : The matching communes, with their department and default area of interest.
+async fn search_communes(query: String) -> Result<Vec<CommuneMatch>, String> {
+    let reference = AdminReference::shared()
+        .await
+        .map_err(|e| format!("Error loading commune reference: {:?}", e))?;
+
+    Ok(reference
+        .search_communes(&query, territory::SEARCH_LIMIT)
+        .into_iter()
+        .map(|commune| reference.resolve_commune(commune))
+        .collect())
+}
+
+#[tauri::command]
+/// Get the regions and their departments.
+///
+/// # Returns
+/// - Vec<Region> : The regions of the embedded reference.
+fn get_regions() -> Vec<Region> {
+    territory::get_regions()
+}
+
+#[tauri::command]
+/// Get the list of departments.
+///
+/// # Returns
+/// - HashMap<String, String> : A hashmap containing the code and name of the departments.
+fn get_dpts_list() -> HashMap<String, String> {
+    return get_departement_list();
+}
+
+#[tauri::command]
+fn get_projects() -> HashMap<String, Vec<String>> {
+    match get_previous_projects() {
+        Ok(projects) => projects,
+        Err(_) => HashMap::new(),
     }
+}
 
-    //TODO : add the satellite layer
+#[tauri::command]
+fn get_os() -> String {
+    return utils::get_operating_system().to_string();
+}
 
-    Ok(())
+//---------------------------------------------------------main---------------------------------------------------------
+
+fn main() {
+    setup_check().expect("Setup check failed");
+
+    tauri::Builder::default()
+        .manage(CreationState::default())
+        .manage(BatchState::default())
+        .register_uri_scheme_protocol(tiles::TILES_SCHEME, |_app, request| {
+            let tile = match tiles::parse_tile_uri(request.uri()) {
+                Some(tile) => tiles::read_tile(&tile)?,
+                None => None,
+            };
+            match tile {
+                Some(png) => ResponseBuilder::new()
+                    .mimetype("image/png")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(png),
+                None => ResponseBuilder::new().status(404).body(Vec::new()),
+            }
+        })
+        .invoke_handler(tauri::generate_handler![
+            open_new_project,
+            cancel_project_creation,
+            get_pipeline_state,
+            retry_layer,
+            edit_layer_filter,
+            get_layer_renderer,
+            update_layer_categories,
+            load_layer_style,
+            save_layer_style,
+            export_map,
+            export_layout,
+            slice_map,
+            mosaic_tiles,
+            generate_tile_pyramid,
+            export_masks,
+            rasterize_project,
+            get_export_presets,
+            save_export_preset,
+            delete_export_preset,
+            run_export_preset,
+            batch_export,
+            cancel_batch_export,
+            set_project_palette,
+            get_palettes,
+            get_palette,
+            save_palette,
+            get_manifest_profiles,
+            get_manifest,
+            save_manifest,
+            get_dpts_list,
+            suggest_departements,
+            search_communes,
+            get_regions,
+            get_projects,
+            get_os
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
 }
@@ -0,0 +1,201 @@
+use crate::symbology::Symbology;
+use crate::utils::create_directory_if_not_exists;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_PROFILE: &str = "default";
+/// The palette entry of the topographic layers without colour.
+pub const DEFAULT_TOPO_COLOR: &str = "road";
+//TODO : add parcelles layer, sea layer and the satellite layer to the bundled manifest
+const DEFAULT_MANIFEST: &str = include_str!("../manifests/default.json");
+const MANIFESTS_FOLDER: &str = "resources/manifests";
+/// The layer of the bundled manifest whose rules style the `Vegetation` layers.
+const VEGETATION_LAYER: &str = "FORMATION_VEGETALE";
+
+/// The styling applied to a layer once it is loaded in the project.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerStyle {
+    /// Categorized by `ESSENCE` with the rules of the bundled manifest, see
+    /// `LayerManifest::vegetation_symbology`.
+    Vegetation,
+    /// A single symbol fitting the geometry of the layer, from the bundled style file if
+    /// any, coloured with the `color` of the layer, see `setup_basic_topo_layer`.
+    Topographic,
+    /// Ordered rules on the layer attributes, coloured from a palette.
+    Rules(Symbology),
+    /// A .qml or .sld style file.
+    File(String),
+    /// Keep the QGIS default style.
+    None,
+}
+
+/// How the filter of a layer is applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    /// Set as the QGIS subset string of the layer, the dataset keeps every feature.
+    #[default]
+    Subset,
+    /// Only the matching features are copied into the project dataset at extraction.
+    Copy,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestLayer {
+    /// The name of the layer files in the archive.
+    pub name: String,
+    /// The name of the layer in the QGIS project, defaults to `name`.
+    #[serde(default)]
+    pub project_layer: Option<String>,
+    /// An optional QGIS expression selecting the features to keep.
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub filter_mode: FilterMode,
+    pub style: LayerStyle,
+    /// The palette entry of a topographic layer, `road` if None.
+    #[serde(default)]
+    pub color: Option<String>,
+    /// The line width or marker size of a topographic layer, in millimetres.
+    #[serde(default)]
+    pub width: Option<f64>,
+    /// The layers of higher order are drawn above the others, and so are their groups.
+    #[serde(default)]
+    pub draw_order: i32,
+}
+
+impl ManifestLayer {
+    pub fn project_layer(&self) -> &str {
+        self.project_layer.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn color(&self) -> &str {
+        self.color.as_deref().unwrap_or(DEFAULT_TOPO_COLOR)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestSource {
+    /// The IGN database name, also used to name the downloaded archive.
+    pub source: String,
+    /// The IGN page listing the department archives of the database.
+    pub catalogue_url: String,
+    /// The folder of the project the layers are extracted into.
+    pub folder: String,
+    /// The layer tree group of the layers, nested groups separated by '/'.
+    pub group: String,
+    pub layers: Vec<ManifestLayer>,
+}
+
+/// The declarative list of the layers a project is built from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerManifest {
+    pub sources: Vec<ManifestSource>,
+}
+
+impl LayerManifest {
+    /// Get the manifest bundled with the application.
+    pub fn bundled() -> Self {
+        serde_json::from_str(DEFAULT_MANIFEST).expect("Bundled manifest is invalid")
+    }
+
+    /// Get the symbology of the BD Forêt layers, the rules of the vegetation layer of the
+    /// bundled manifest: broadleaves, unclassified areas and everything else, by `ESSENCE`.
+    pub fn vegetation_symbology() -> Symbology {
+        Self::bundled()
+            .sources
+            .into_iter()
+            .flat_map(|source| source.layers)
+            .find_map(|layer| match layer.style {
+                LayerStyle::Rules(symbology) if layer.name == VEGETATION_LAYER => Some(symbology),
+                _ => None,
+            })
+            .expect("Bundled manifest has no vegetation rules")
+    }
+
+    /// Load a manifest profile.
+    /// # Parameters
+    /// - `profile`: A string slice that holds the name of the profile.
+    /// # Returns
+    /// - The manifest or an error message.
+    pub fn load(profile: &str) -> Result<Self, Box<dyn Error>> {
+        let path = manifest_path(profile)?;
+        if !Path::new(&path).exists() {
+            if profile == DEFAULT_PROFILE {
+                return Ok(Self::bundled());
+            }
+            return Err(format!("Manifest profile '{}' not found", profile).into());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Save the manifest as a profile, replacing the profile if it exists.
+    /// # Parameters
+    /// - `profile`: A string slice that holds the name of the profile.
+    /// # Returns
+    /// - An empty result or an error message.
+    pub fn save(&self, profile: &str) -> Result<(), Box<dyn Error>> {
+        let path = manifest_path(profile)?;
+        create_directory_if_not_exists(MANIFESTS_FOLDER)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Get the layer tree groups used by the manifest, in declaration order.
+    pub fn groups(&self) -> Vec<String> {
+        let mut groups: Vec<String> = Vec::new();
+        for source in &self.sources {
+            if !groups.contains(&source.group) {
+                groups.push(source.group.clone());
+            }
+        }
+        groups
+    }
+}
+
+fn manifest_path(profile: &str) -> Result<String, Box<dyn Error>> {
+    let is_valid = !profile.is_empty()
+        && profile
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        return Err(format!("Invalid manifest profile name '{}'", profile).into());
+    }
+    Ok(format!("{}/{}.json", MANIFESTS_FOLDER, profile))
+}
+
+/// Write the bundled manifest as the default profile if it does not exist yet.
+/// # Returns
+/// - An empty result or an error message.
+pub fn install_default_manifest() -> Result<(), Box<dyn Error>> {
+    if !Path::new(&manifest_path(DEFAULT_PROFILE)?).exists() {
+        LayerManifest::bundled().save(DEFAULT_PROFILE)?;
+    }
+    Ok(())
+}
+
+/// Get the names of the available manifest profiles.
+/// # Returns
+/// - The sorted profile names or an error message.
+pub fn get_manifest_profiles() -> Result<Vec<String>, Box<dyn Error>> {
+    let mut profiles = vec![DEFAULT_PROFILE.to_string()];
+    if Path::new(MANIFESTS_FOLDER).exists() {
+        for entry in fs::read_dir(MANIFESTS_FOLDER)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(profile) = path.file_stem().and_then(|stem| stem.to_str()) {
+                if !profiles.iter().any(|p| p == profile) {
+                    profiles.push(profile.to_string());
+                }
+            }
+        }
+    }
+    profiles.sort();
+    Ok(profiles)
+}
@@ -0,0 +1,222 @@
+use crate::export::{Extent, ImageFormat, MapExport, RenderedMap, Resolution, DEFAULT_DPI};
+use crate::palette::Palette;
+use crate::processing::euclidean_distance;
+use crate::qgis_api_wrapper::{export_map, get_group_children, write_class_geotiff};
+use crate::utils::create_directory_if_not_exists;
+use image::{GrayImage, Luma, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+/// The colour of the pixels no feature is drawn on, the background of the exports.
+pub const BACKGROUND_COLOR: (u8, u8, u8) = (255, 255, 255);
+/// The name of the lookup table of the classes, written in the output folder of the masks.
+pub const CLASSES_FILE: &str = "classes.json";
+
+/// How the pixels of a mask are encoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskEncoding {
+    /// 1 where a feature is drawn, 0 elsewhere.
+    Binary,
+    /// The ID of the palette entry of the pixel colour, 0 where nothing is drawn.
+    #[default]
+    Classes,
+}
+
+/// A value of the masks and what it stands for.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaskClass {
+    pub id: u8,
+    pub name: String,
+    /// The colour the value is displayed with, and matched against for `MaskEncoding::Classes`.
+    pub color: (u8, u8, u8),
+}
+
+/// What to export as masks from a project and where.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaskExport {
+    /// The area of the masks, in the coordinates of `crs`.
+    pub extent: Extent,
+    pub crs: String,
+    /// The ground size of a pixel, in metres.
+    pub metres_per_pixel: f64,
+    /// The group whose children get a mask each.
+    #[serde(default = "default_root_group")]
+    pub root_group: String,
+    /// The layers and groups getting a mask, every child of `root_group` if empty.
+    #[serde(default)]
+    pub targets: Vec<String>,
+    #[serde(default)]
+    pub encoding: MaskEncoding,
+    pub output_folder: String,
+}
+
+/// A mask written by an export.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MaskRaster {
+    /// The layer or group rendered in the mask.
+    pub target: String,
+    pub map: RenderedMap,
+}
+
+fn default_root_group() -> String {
+    "combustion".to_string()
+}
+
+/// Get the classes of an encoding.
+/// # Parameters
+/// - `encoding`: The encoding of the masks.
+/// - `palette`: The palette the class colours come from, the entries sharing a colour
+///   being merged into the first one.
+pub fn mask_classes(encoding: MaskEncoding, palette: &Palette) -> Vec<MaskClass> {
+    let mut classes = vec![MaskClass {
+        id: 0,
+        name: "none".to_string(),
+        color: BACKGROUND_COLOR,
+    }];
+    match encoding {
+        MaskEncoding::Binary => classes.push(MaskClass {
+            id: 1,
+            name: "present".to_string(),
+            color: (0, 0, 0),
+        }),
+        MaskEncoding::Classes => {
+            for entry in &palette.entries {
+                if classes.iter().any(|class| class.color == entry.color) || classes.len() > 255 {
+                    continue;
+                }
+                classes.push(MaskClass {
+                    id: classes.len() as u8,
+                    name: entry.name.clone(),
+                    color: entry.color,
+                });
+            }
+        }
+    }
+    classes
+}
+
+/// Turn a rendered image into class IDs.
+///
+/// The background is class 0. With `MaskEncoding::Classes`, a colour not in the classes
+/// gets the class of the closest colour.
+/// # Parameters
+/// - `image`: The rendered image.
+/// - `encoding`: The encoding of the mask.
+/// - `classes`: The classes of the encoding, see `mask_classes`.
+/// # Returns
+/// - The image of the class IDs.
+pub fn classify_image(
+    image: &RgbImage,
+    encoding: MaskEncoding,
+    classes: &[MaskClass],
+) -> GrayImage {
+    let mut known: HashMap<(u8, u8, u8), u8> = classes
+        .iter()
+        .map(|class| (class.color, class.id))
+        .collect();
+    let mut mask = GrayImage::new(image.width(), image.height());
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let color = (pixel[0], pixel[1], pixel[2]);
+        let id = match encoding {
+            MaskEncoding::Binary => u8::from(color != BACKGROUND_COLOR),
+            MaskEncoding::Classes => *known.entry(color).or_insert_with(|| {
+                classes
+                    .iter()
+                    .min_by(|a, b| {
+                        euclidean_distance(color, a.color)
+                            .partial_cmp(&euclidean_distance(color, b.color))
+                            .unwrap()
+                    })
+                    .map(|class| class.id)
+                    .unwrap_or(0)
+            }),
+        };
+        mask.put_pixel(x, y, Luma([id]));
+    }
+    mask
+}
+
+/// Name the mask of a layer or group, like "combustion_BDTOPO.tif" for "combustion/BDTOPO".
+pub fn mask_file_name(target: &str) -> String {
+    let name: String = target
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}.tif", name)
+}
+
+/// Export each layer or group of a project into its own single band GeoTIFF, every mask
+/// sharing the same extent and pixel grid.
+///
+/// The classes are written to `classes.json` and as the colour table of every mask.
+/// # Parameters
+/// - `project_file`: A string slice that holds the path of the project file.
+/// - `export`: The extent, resolution, targets, encoding and output folder of the masks.
+/// - `palette`: The palette the layers are styled with.
+/// # Returns
+/// - The written masks or an error message.
+pub fn export_masks(
+    project_file: &str,
+    export: &MaskExport,
+    palette: &Palette,
+) -> Result<Vec<MaskRaster>, Box<dyn Error>> {
+    let targets = if export.targets.is_empty() {
+        get_group_children(project_file, &export.root_group)?
+    } else {
+        export.targets.clone()
+    };
+    if targets.is_empty() {
+        return Err(format!("Group '{}' has no layer to export", export.root_group).into());
+    }
+
+    create_directory_if_not_exists(&export.output_folder)?;
+    let classes = mask_classes(export.encoding, palette);
+    fs::write(
+        format!("{}/{}", export.output_folder, CLASSES_FILE),
+        serde_json::to_string_pretty(&classes)?,
+    )?;
+
+    let mut masks = Vec::with_capacity(targets.len());
+    for target in targets {
+        let output_path = format!("{}/{}", export.output_folder, mask_file_name(&target));
+        let render_path = format!("{}.render.png", output_path);
+        let class_path = format!("{}.classes.png", output_path);
+
+        let rendered = export_map(
+            project_file,
+            &MapExport {
+                extent: export.extent,
+                crs: export.crs.clone(),
+                resolution: Resolution::MetresPerPixel(export.metres_per_pixel),
+                dpi: DEFAULT_DPI,
+                layers: vec![target.clone()],
+                format: ImageFormat::Png,
+                output_path: render_path.clone(),
+                antialiasing: Some(false),
+                world_file: false,
+            },
+        )?;
+        let image = image::open(&render_path)?.to_rgb8();
+        classify_image(&image, export.encoding, &classes).save(&class_path)?;
+        fs::remove_file(&render_path)?;
+
+        let map = RenderedMap {
+            path: output_path,
+            ..rendered
+        };
+        let result = write_class_geotiff(&class_path, &map, &classes);
+        fs::remove_file(&class_path)?;
+        result?;
+        masks.push(MaskRaster { target, map });
+    }
+    Ok(masks)
+}
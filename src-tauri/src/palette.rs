@@ -0,0 +1,150 @@
+use crate::utils::{create_directory_if_not_exists, Parasite_Colors, Vulcain_Colors};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_PALETTE: &str = "vulcain";
+const PALETTES_FOLDER: &str = "resources/palettes";
+const VULCAIN_NAMES: [&str; 5] = [
+    "non-combustible",
+    "eau",
+    "non-classe",
+    "resineux",
+    "feuillus",
+];
+const PARASITE_NAMES: [&str; 3] = ["blanc", "gris", "export"];
+
+/// A named colour of a palette.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaletteEntry {
+    pub name: String,
+    pub color: (u8, u8, u8),
+}
+
+/// A set of named colours the symbologies, the exports and the pixel classification refer to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub entries: Vec<PaletteEntry>,
+    /// The colours left by the export (background, labels, antialiasing) that the pixel
+    /// classification replaces by the surrounding entries.
+    #[serde(default)]
+    pub parasites: Vec<PaletteEntry>,
+}
+
+impl Palette {
+    /// Get the palette of the simulator fuel categories, from `Vulcain_Colors` and
+    /// `Parasite_Colors`.
+    pub fn vulcain() -> Self {
+        let mut entries: Vec<PaletteEntry> = VULCAIN_NAMES
+            .iter()
+            .zip(Vulcain_Colors.iter())
+            .map(|(&name, &color)| PaletteEntry {
+                name: name.to_string(),
+                color,
+            })
+            .collect();
+        entries.push(PaletteEntry {
+            name: "road".to_string(),
+            color: Vulcain_Colors[0],
+        });
+
+        Palette {
+            name: DEFAULT_PALETTE.to_string(),
+            entries,
+            parasites: PARASITE_NAMES
+                .iter()
+                .zip(Parasite_Colors.iter())
+                .map(|(&name, &color)| PaletteEntry {
+                    name: name.to_string(),
+                    color,
+                })
+                .collect(),
+        }
+    }
+
+    /// Load a palette, the bundled palettes being available without a file.
+    /// # Parameters
+    /// - `name`: A string slice that holds the name of the palette.
+    /// # Returns
+    /// - The palette or an error message.
+    pub fn load(name: &str) -> Result<Self, Box<dyn Error>> {
+        let path = palette_path(name)?;
+        if !Path::new(&path).exists() {
+            if name == DEFAULT_PALETTE {
+                return Ok(Self::vulcain());
+            }
+            return Err(format!("Palette '{}' not found", name).into());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Save the palette under its name, replacing the palette if it exists.
+    /// # Returns
+    /// - An empty result or an error message.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = palette_path(&self.name)?;
+        create_directory_if_not_exists(PALETTES_FOLDER)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Get the colour of an entry.
+    pub fn color(&self, name: &str) -> Option<(u8, u8, u8)> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.color)
+    }
+
+    /// Get the distinct colours of the entries.
+    pub fn colors(&self) -> Vec<(u8, u8, u8)> {
+        let mut colors: Vec<(u8, u8, u8)> = Vec::with_capacity(self.entries.len());
+        for entry in &self.entries {
+            if !colors.contains(&entry.color) {
+                colors.push(entry.color);
+            }
+        }
+        colors
+    }
+
+    /// Get the colours of the parasites.
+    pub fn parasite_colors(&self) -> Vec<(u8, u8, u8)> {
+        self.parasites.iter().map(|entry| entry.color).collect()
+    }
+}
+
+fn palette_path(name: &str) -> Result<String, Box<dyn Error>> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+    if !is_valid {
+        return Err(format!("Invalid palette name '{}'", name).into());
+    }
+    Ok(format!("{}/{}.json", PALETTES_FOLDER, name))
+}
+
+/// Get the names of the available palettes.
+/// # Returns
+/// - The sorted palette names or an error message.
+pub fn get_palettes() -> Result<Vec<String>, Box<dyn Error>> {
+    let mut palettes = vec![DEFAULT_PALETTE.to_string()];
+    if Path::new(PALETTES_FOLDER).exists() {
+        for entry in fs::read_dir(PALETTES_FOLDER)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                if !palettes.iter().any(|p| p == name) {
+                    palettes.push(name.to_string());
+                }
+            }
+        }
+    }
+    palettes.sort();
+    Ok(palettes)
+}
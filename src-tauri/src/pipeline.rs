@@ -0,0 +1,1139 @@
+use crate::aoi::{clip_layer_path, prepare_clip_area, ClipArea};
+use crate::cancellation::CancellationToken;
+use crate::export::{Extent, ImageFormat, MapExport, Resolution, DEFAULT_DPI};
+use crate::manifest::{
+    FilterMode, LayerManifest, LayerStyle, ManifestLayer, ManifestSource, DEFAULT_TOPO_COLOR,
+};
+use crate::palette::{Palette, DEFAULT_PALETTE};
+use crate::progress::{ProgressEvent, Severity};
+use crate::qgis_api_wrapper::*;
+use crate::styles::default_style_path;
+use crate::symbology::SimpleSymbol;
+use crate::utils::{
+    create_directory_if_not_exists, find_filepath_in_archive, get_departement_crs,
+    layer_full_extraction, LAMBERT_93,
+};
+use crate::web_request::{download_shp_file, get_departement_shp_file_url};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The size of the longest side of the preview image of a project, in pixels.
+pub const PREVIEW_SIZE: u32 = 512;
+
+/// The number of palette copies of projects made by this process, naming the copies.
+static PROJECT_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+/// The named steps of a project creation, in execution order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineStep {
+    Discover,
+    Download,
+    Extract,
+    CreateProject,
+    LoadLayers,
+    Style,
+    Export,
+}
+
+impl PipelineStep {
+    pub const ALL: [PipelineStep; 7] = [
+        PipelineStep::Discover,
+        PipelineStep::Download,
+        PipelineStep::Extract,
+        PipelineStep::CreateProject,
+        PipelineStep::LoadLayers,
+        PipelineStep::Style,
+        PipelineStep::Export,
+    ];
+
+    /// Get the position of the step in the pipeline.
+    pub fn index(&self) -> usize {
+        PipelineStep::ALL
+            .iter()
+            .position(|step| step == self)
+            .unwrap_or_default()
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PipelineStep::Discover => "Recherche des fichiers",
+            PipelineStep::Download => "Téléchargement des données",
+            PipelineStep::Extract => "Extraction des couches",
+            PipelineStep::CreateProject => "Initialisation du projet",
+            PipelineStep::LoadLayers => "Chargement des couches",
+            PipelineStep::Style => "Mise en forme des couches",
+            PipelineStep::Export => "Finalisation",
+        }
+    }
+
+    fn uses_qgis(&self) -> bool {
+        matches!(
+            self,
+            PipelineStep::Extract
+                | PipelineStep::CreateProject
+                | PipelineStep::LoadLayers
+                | PipelineStep::Style
+                | PipelineStep::Export
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepState {
+    pub step: PipelineStep,
+    pub status: StepStatus,
+    pub error: Option<String>,
+}
+
+/// The last stage a layer went through successfully.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerStage {
+    Pending,
+    Extracted,
+    Loaded,
+    Styled,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LayerState {
+    /// The database the layer comes from ("BDTOPO", "BDFORET").
+    pub source: String,
+    /// The name of the layer files in the archive.
+    pub name: String,
+    /// The folder of the project the layer is extracted into.
+    pub folder: String,
+    /// The name of the layer in the QGIS project.
+    pub project_layer: String,
+    /// The layer tree group the layer is moved into.
+    pub group: String,
+    /// The QGIS expression selecting the features to keep, editable after the creation.
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub filter_mode: FilterMode,
+    pub style: LayerStyle,
+    /// The palette entry of a topographic layer.
+    #[serde(default = "default_topo_color")]
+    pub color: String,
+    /// The line width or marker size of a topographic layer, in millimetres.
+    #[serde(default)]
+    pub width: Option<f64>,
+    #[serde(default)]
+    pub draw_order: i32,
+    /// The CRS read from the .prj of the extracted dataset, empty if it had none.
+    #[serde(default)]
+    pub source_crs: Option<String>,
+    pub stage: LayerStage,
+    /// The error of the last failed stage, if any.
+    pub error: Option<String>,
+}
+
+impl LayerState {
+    fn new(source: &ManifestSource, layer: &ManifestLayer) -> Self {
+        LayerState {
+            source: source.source.clone(),
+            name: layer.name.clone(),
+            folder: source.folder.clone(),
+            project_layer: layer.project_layer().to_string(),
+            group: source.group.clone(),
+            filter: layer.filter.clone(),
+            filter_mode: layer.filter_mode,
+            style: layer.style.clone(),
+            color: layer.color().to_string(),
+            width: layer.width,
+            draw_order: layer.draw_order,
+            source_crs: None,
+            stage: LayerStage::Pending,
+            error: None,
+        }
+    }
+
+    /// Get the path of the extracted dataset of the layer.
+    pub fn shapefile_path(&self, project_name: &str) -> String {
+        format!(
+            "resources/QGIS/{}/{}/{}/{}.shp",
+            project_name, self.folder, self.name, self.name
+        )
+    }
+
+    /// Extract the layer of every department and merge them into one dataset,
+    /// dropping the features duplicated along the borders.
+    /// # Parameters
+    /// - `project_name`: A string slice that holds the name of the project.
+    /// - `codes`: The codes of the departments.
+    /// - `token`: The cancellation token checked during the extraction.
+    /// # Returns
+    /// - An empty result or an error message.
+    fn extract_and_merge(
+        &self,
+        project_name: &str,
+        codes: &[String],
+        token: &CancellationToken,
+    ) -> Result<(), Box<dyn Error>> {
+        let output_folder = format!("{}/{}", project_name, self.folder);
+        if let [code] = codes {
+            return layer_full_extraction(
+                &self.source,
+                code,
+                &self.name,
+                &output_folder,
+                Some(&self.name),
+                token,
+            );
+        }
+
+        let mut parts = Vec::with_capacity(codes.len());
+        for code in codes {
+            let archive_path = format!("tmp/{}_{}.7z", self.source, code);
+            if find_filepath_in_archive(&archive_path, &self.name)?.is_none() {
+                continue;
+            }
+            layer_full_extraction(
+                &self.source,
+                code,
+                &self.name,
+                &format!("{}/{}", output_folder, code),
+                Some(&self.name),
+                token,
+            )?;
+            parts.push(format!(
+                "resources/QGIS/{}/{}/{}/{}.shp",
+                output_folder, code, self.name, self.name
+            ));
+        }
+        if parts.is_empty() {
+            return Err(format!("Layer '{}' not found in any departement", self.name).into());
+        }
+
+        create_directory_if_not_exists(&format!("resources/QGIS/{}/{}", output_folder, self.name))?;
+        merge_vector_files(parts, &self.shapefile_path(project_name))?;
+        for code in codes {
+            let part_folder = format!("resources/QGIS/{}/{}/{}", output_folder, code, self.name);
+            if Path::new(&part_folder).exists() {
+                fs::remove_dir_all(part_folder)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bring the extracted dataset into the project CRS.
+    ///
+    /// The CRS is read from the .prj of the dataset. A dataset in another CRS is
+    /// reprojected, a dataset without CRS is assumed to be in the project CRS.
+    /// # Returns
+    /// - The CRS read from the dataset, empty if it had none, or an error message.
+    fn match_crs(&self, project_name: &str, crs: &str) -> Result<String, Box<dyn Error>> {
+        let shapefile_path = self.shapefile_path(project_name);
+        let source_crs = get_vector_file_crs(&shapefile_path)?;
+        if source_crs.is_empty() {
+            assign_vector_file_crs(&shapefile_path, crs)?;
+        } else if source_crs != crs {
+            reproject_vector_file(&shapefile_path, crs)?;
+        }
+        Ok(source_crs)
+    }
+
+    /// Get the warning to report if the extracted dataset was not in the project CRS.
+    pub fn crs_warning(&self, crs: &str) -> Option<String> {
+        match self.source_crs.as_deref() {
+            Some("") => Some(format!(
+                "{}: no CRS found, assumed {}",
+                self.project_layer, crs
+            )),
+            Some(source_crs) if source_crs != crs => Some(format!(
+                "{}: reprojected from {} to {}",
+                self.project_layer, source_crs, crs
+            )),
+            _ => None,
+        }
+    }
+
+    /// Style the loaded layer with the colours of a palette.
+    /// A symbology naming its own palette is coloured from that palette instead.
+    fn apply_style(&self, project_file: &str, palette: &Palette) -> Result<(), Box<dyn Error>> {
+        match &self.style {
+            LayerStyle::Vegetation => {
+                setup_basic_veg_layer(project_file, &self.project_layer, palette)?;
+            }
+            LayerStyle::Topographic => {
+                let color = palette.color(&self.color).ok_or_else(|| {
+                    format!(
+                        "Colour '{}' not found in palette '{}'",
+                        self.color, palette.name
+                    )
+                })?;
+                setup_basic_topo_layer(
+                    project_file,
+                    &self.project_layer,
+                    &default_style_path(&self.name),
+                    &SimpleSymbol {
+                        color,
+                        width: self.width,
+                    },
+                )?;
+            }
+            LayerStyle::Rules(symbology) => {
+                let rules = match &symbology.palette {
+                    Some(name) => symbology.resolve(&Palette::load(name)?)?,
+                    None => symbology.resolve(palette)?,
+                };
+                apply_rule_symbology(project_file, &self.project_layer, &rules)?;
+            }
+            LayerStyle::File(style_path) => {
+                load_layer_style(project_file, &self.project_layer, style_path)?;
+            }
+            LayerStyle::None => {}
+        }
+        Ok(())
+    }
+
+    /// Check whether the extracted dataset of another layer state is the one of this layer:
+    /// same archive layer and folder, and same filter if it is applied at extraction.
+    fn has_same_dataset(&self, other: &LayerState) -> bool {
+        let copy_filter = |layer: &LayerState| match layer.filter_mode {
+            FilterMode::Copy => layer.filter.clone(),
+            FilterMode::Subset => None,
+        };
+        self.source == other.source
+            && self.name == other.name
+            && self.folder == other.folder
+            && copy_filter(self) == copy_filter(other)
+    }
+
+    /// Run the next stage of the layer.
+    /// # Parameters
+    /// - `project_name`: A string slice that holds the name of the project.
+    /// - `codes`: The codes of the departments.
+    /// - `crs`: A string slice that holds the project CRS the extracted layer is reprojected to.
+    /// - `clip_path`: The clip layer the extracted layer is clipped to, if any.
+    /// - `palette`: A string slice that holds the name of the palette the layer is styled with.
+    /// - `token`: The cancellation token checked during the extraction.
+    /// # Returns
+    /// - An empty result or an error message.
+    fn advance(
+        &mut self,
+        project_name: &str,
+        codes: &[String],
+        crs: &str,
+        clip_path: Option<&str>,
+        palette: &str,
+        token: &CancellationToken,
+    ) -> Result<(), Box<dyn Error>> {
+        let project_file = project_file_path(project_name);
+        let next_stage = match self.stage {
+            LayerStage::Pending => {
+                self.extract_and_merge(project_name, codes, token)?;
+                self.source_crs = Some(self.match_crs(project_name, crs)?);
+                if let (Some(filter), FilterMode::Copy) = (&self.filter, self.filter_mode) {
+                    filter_vector_file(&self.shapefile_path(project_name), filter)?;
+                }
+                if let Some(clip_path) = clip_path {
+                    clip_vector_file(&self.shapefile_path(project_name), clip_path)?;
+                }
+                LayerStage::Extracted
+            }
+            LayerStage::Extracted => {
+                load_vector_layer_to_project(
+                    &project_file,
+                    &self.shapefile_path(project_name),
+                    &self.project_layer,
+                )?;
+                move_layer_to_group(&project_file, &self.project_layer, &self.group)?;
+                set_layer_draw_order(&project_file, &self.project_layer, self.draw_order)?;
+                if let (Some(filter), FilterMode::Subset) = (&self.filter, self.filter_mode) {
+                    set_layer_filter(&project_file, &self.project_layer, filter)?;
+                }
+                LayerStage::Loaded
+            }
+            LayerStage::Loaded => {
+                self.apply_style(&project_file, &Palette::load(palette)?)?;
+                LayerStage::Styled
+            }
+            LayerStage::Styled => LayerStage::Styled,
+        };
+        self.stage = next_stage;
+        self.error = None;
+        Ok(())
+    }
+}
+
+/// An archive of a manifest source for one department.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArchiveState {
+    pub source: String,
+    pub code: String,
+    pub url: String,
+}
+
+impl ArchiveState {
+    pub fn path(&self) -> String {
+        format!("tmp/{}_{}.7z", self.source, self.code)
+    }
+}
+
+/// The persisted state of a project creation, used to resume it after a failure.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PipelineState {
+    pub name: String,
+    /// The codes of the departments the project spans.
+    pub codes: Vec<String>,
+    /// The manifest the project is built from, kept so a resumed creation uses the same layers.
+    pub manifest: LayerManifest,
+    /// The CRS every layer is reprojected to, empty in the states saved before it existed.
+    #[serde(default)]
+    pub crs: String,
+    /// The area every layer is clipped to, the whole departements if None.
+    #[serde(default)]
+    pub clip_area: Option<ClipArea>,
+    /// The name of the palette the layers are styled with.
+    #[serde(default = "default_palette")]
+    pub palette: String,
+    /// The archives found by the discover step, one per manifest source and department.
+    pub archives: Vec<ArchiveState>,
+    pub steps: Vec<StepState>,
+    pub layers: Vec<LayerState>,
+}
+
+impl PipelineState {
+    /// Start the state of a new project.
+    /// # Parameters
+    /// - `name`: A string slice that holds the name of the project.
+    /// - `codes`: The codes of the departments.
+    /// - `manifest`: The manifest listing the layers.
+    /// - `crs`: The project CRS, the official CRS of the first department if None.
+    /// - `clip_area`: The area every layer is clipped to, if any.
+    pub fn new(
+        name: &str,
+        codes: &[String],
+        manifest: &LayerManifest,
+        crs: Option<&str>,
+        clip_area: Option<ClipArea>,
+    ) -> Self {
+        let layers = manifest
+            .sources
+            .iter()
+            .flat_map(|source| {
+                source
+                    .layers
+                    .iter()
+                    .map(move |layer| LayerState::new(source, layer))
+            })
+            .collect();
+
+        PipelineState {
+            name: name.to_string(),
+            codes: codes.to_vec(),
+            manifest: manifest.clone(),
+            crs: crs
+                .map(str::to_string)
+                .unwrap_or_else(|| default_crs(codes)),
+            clip_area,
+            palette: DEFAULT_PALETTE.to_string(),
+            archives: vec![],
+            steps: PipelineStep::ALL
+                .iter()
+                .map(|&step| StepState {
+                    step,
+                    status: StepStatus::Pending,
+                    error: None,
+                })
+                .collect(),
+            layers,
+        }
+    }
+
+    /// Load the state file of a project.
+    /// # Parameters
+    /// - `name`: A string slice that holds the name of the project.
+    /// # Returns
+    /// - The state, None if the project has no state file, or an error message.
+    pub fn load(name: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let path = state_file_path(name);
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        let mut state: Self = serde_json::from_str(&content)?;
+        if state.crs.is_empty() {
+            state.crs = default_crs(&state.codes);
+        }
+        for step in PipelineStep::ALL {
+            if !state.steps.iter().any(|state| state.step == step) {
+                state.steps.insert(
+                    step.index(),
+                    StepState {
+                        step,
+                        status: StepStatus::Pending,
+                        error: None,
+                    },
+                );
+            }
+        }
+        Ok(Some(state))
+    }
+
+    /// Load the state of a project to resume it, or start a new one from the manifest
+    /// and the clip area if the project has no state or was created for other departments
+    /// or another CRS.
+    ///
+    /// A state saved with another manifest or clip area is resumed from the steps they do
+    /// not affect, see `with_inputs`.
+    pub fn load_or_new(
+        name: &str,
+        codes: &[String],
+        manifest: &LayerManifest,
+        crs: Option<&str>,
+        clip_area: Option<ClipArea>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let crs = crs
+            .map(str::to_string)
+            .unwrap_or_else(|| default_crs(codes));
+        match Self::load(name)? {
+            Some(state) if state.codes == codes && state.crs == crs => {
+                Ok(state.with_inputs(manifest, clip_area))
+            }
+            _ => Ok(Self::new(name, codes, manifest, Some(&crs), clip_area)),
+        }
+    }
+
+    /// Switch a saved state to another manifest and clip area, keeping the work they do
+    /// not invalidate.
+    ///
+    /// The archives are kept if the manifest lists the same sources. The extracted
+    /// datasets are kept if the clip area did not change, for the layers whose dataset is
+    /// the same, see `has_same_dataset`. The project is created again and every layer
+    /// loaded and styled again, as removed or changed layers may be in it.
+    /// # Parameters
+    /// - `manifest`: The manifest listing the layers.
+    /// - `clip_area`: The area every layer is clipped to, if any.
+    pub fn with_inputs(self, manifest: &LayerManifest, clip_area: Option<ClipArea>) -> Self {
+        if self.manifest == *manifest && self.clip_area == clip_area {
+            return self;
+        }
+
+        let same_clip_area = self.clip_area == clip_area;
+        let mut state = Self::new(
+            &self.name,
+            &self.codes,
+            manifest,
+            Some(&self.crs),
+            clip_area,
+        );
+        state.palette = self.palette;
+        let catalogues = |manifest: &LayerManifest| -> Vec<(String, String)> {
+            manifest
+                .sources
+                .iter()
+                .map(|source| (source.source.clone(), source.catalogue_url.clone()))
+                .collect()
+        };
+        if catalogues(&self.manifest) == catalogues(manifest) {
+            state.archives = self.archives;
+            for step in [PipelineStep::Discover, PipelineStep::Download] {
+                if self
+                    .steps
+                    .iter()
+                    .any(|old| old.step == step && old.status == StepStatus::Done)
+                {
+                    state.set_step_status(step, StepStatus::Done, None);
+                }
+            }
+        }
+        if same_clip_area {
+            for layer in &mut state.layers {
+                if let Some(old) = self
+                    .layers
+                    .iter()
+                    .find(|old| old.stage >= LayerStage::Extracted && old.has_same_dataset(layer))
+                {
+                    layer.stage = LayerStage::Extracted;
+                    layer.source_crs = old.source_crs.clone();
+                }
+            }
+        }
+        state
+    }
+
+    /// Get the path of the clip layer, if the project is clipped to an area of interest.
+    pub fn clip_path(&self) -> Option<String> {
+        self.clip_area.as_ref().map(|_| clip_layer_path(&self.name))
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        create_directory_if_not_exists(&format!("resources/QGIS/{}", self.name))?;
+        fs::write(
+            state_file_path(&self.name),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Get the first step that is not done yet.
+    pub fn next_step(&self) -> Option<PipelineStep> {
+        self.steps
+            .iter()
+            .find(|state| state.status != StepStatus::Done)
+            .map(|state| state.step)
+    }
+
+    pub fn is_done(&self, step: PipelineStep) -> bool {
+        self.steps
+            .iter()
+            .any(|state| state.step == step && state.status == StepStatus::Done)
+    }
+
+    fn set_step_status(&mut self, step: PipelineStep, status: StepStatus, error: Option<String>) {
+        if let Some(state) = self.steps.iter_mut().find(|state| state.step == step) {
+            state.status = status;
+            state.error = error;
+        }
+    }
+
+    /// Get the layers that failed one of their stages.
+    pub fn failed_layers(&self) -> Vec<&LayerState> {
+        self.layers
+            .iter()
+            .filter(|layer| layer.error.is_some())
+            .collect()
+    }
+}
+
+/// Get the path of the state file of a project.
+pub fn state_file_path(name: &str) -> String {
+    format!("resources/QGIS/{}/pipeline.json", name)
+}
+
+fn default_topo_color() -> String {
+    DEFAULT_TOPO_COLOR.to_string()
+}
+
+fn default_palette() -> String {
+    DEFAULT_PALETTE.to_string()
+}
+
+/// Get the default CRS of a project, the official CRS of its first department.
+fn default_crs(codes: &[String]) -> String {
+    codes
+        .first()
+        .map(|code| get_departement_crs(code))
+        .unwrap_or(LAMBERT_93)
+        .epsg
+        .to_string()
+}
+
+/// Get the path of the preview image of a project, shown in the list of the projects.
+pub fn preview_path(name: &str) -> String {
+    format!("resources/QGIS/{}/preview.png", name)
+}
+
+/// Get the path of the QGIS project file of a project.
+pub fn project_file_path(name: &str) -> String {
+    format!("resources/QGIS/{}/{}.qgz", name, name)
+}
+
+/// Start the embedded Python interpreter and point it to the QGIS installation.
+pub fn initialize_qgis() -> Result<(), String> {
+    pyo3::prepare_freethreaded_python();
+    initialize_qgis_app_path().map_err(|e| format!("Error initializing QGIS app path: {:?}", e))?;
+    Ok(())
+}
+
+/// Run the steps of the pipeline that are not done yet, saving the state after each one.
+/// # Parameters
+/// - `state`: The state of the pipeline, updated as the steps complete.
+/// - `token`: The cancellation token checked between the steps and the layers.
+/// - `report`: Called with every progress event of the pipeline.
+/// # Returns
+/// - Result<(), String> : An empty result or the error of the failed step.
+pub async fn run_pipeline<F>(
+    state: &mut PipelineState,
+    token: &CancellationToken,
+    report: F,
+) -> Result<(), String>
+where
+    F: Fn(ProgressEvent) -> Result<(), String>,
+{
+    let mut qgis_initialized = false;
+
+    while let Some(step) = state.next_step() {
+        token.check().map_err(|e| e.to_string())?;
+        report(ProgressEvent::new(
+            step,
+            0.0,
+            Severity::Info,
+            "step_started",
+        ))?;
+
+        if step.uses_qgis() && !qgis_initialized {
+            initialize_qgis()?;
+            qgis_initialized = true;
+        }
+
+        let result = run_step(state, step, token, &report).await;
+        match &result {
+            Ok(_) => state.set_step_status(step, StepStatus::Done, None),
+            Err(e) => state.set_step_status(step, StepStatus::Failed, Some(e.clone())),
+        }
+        state
+            .save()
+            .map_err(|e| format!("Error saving pipeline state: {:?}", e))?;
+
+        match result {
+            Ok(_) => report(ProgressEvent::new(step, 1.0, Severity::Info, "step_done"))?,
+            Err(e) => {
+                report(ProgressEvent::new(
+                    step,
+                    1.0,
+                    Severity::Error,
+                    "step_failed",
+                ))?;
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the partial outputs of a cancelled run, so the creation can be resumed later.
+///
+/// The completed steps and the extracted layers are kept, and the interrupted step is
+/// pending again. The datasets of the layers not extracted yet are removed, and so is the
+/// project file if its creation was interrupted. Partially downloaded archives are already
+/// removed by the download loop, complete ones are kept.
+/// # Parameters
+/// - `state`: The state of the pipeline.
+/// # Returns
+/// - Result<(), String> : An empty result or an error message.
+pub fn clean_cancelled_run(state: &mut PipelineState) -> Result<(), String> {
+    if let Some(step) = state.next_step() {
+        state.set_step_status(step, StepStatus::Pending, None);
+    }
+
+    let project_folder = format!("resources/QGIS/{}", state.name);
+    let mut partial_paths: Vec<String> = Vec::new();
+    for layer in state
+        .layers
+        .iter()
+        .filter(|layer| layer.stage == LayerStage::Pending)
+    {
+        partial_paths.push(format!(
+            "{}/{}/{}",
+            project_folder, layer.folder, layer.name
+        ));
+        for code in &state.codes {
+            partial_paths.push(format!(
+                "{}/{}/{}/{}",
+                project_folder, layer.folder, code, layer.name
+            ));
+        }
+    }
+    for path in partial_paths {
+        if Path::new(&path).exists() {
+            fs::remove_dir_all(&path).map_err(|e| format!("Error removing '{}': {:?}", path, e))?;
+        }
+    }
+
+    let project_file = project_file_path(&state.name);
+    if !state.is_done(PipelineStep::CreateProject) && Path::new(&project_file).exists() {
+        fs::remove_file(&project_file)
+            .map_err(|e| format!("Error removing '{}': {:?}", project_file, e))?;
+    }
+
+    state
+        .save()
+        .map_err(|e| format!("Error saving pipeline state: {:?}", e))
+}
+
+/// Switch the palette of a project, styling its styled layers again with the new colours.
+/// # Parameters
+/// - `state`: The state of the pipeline.
+/// - `palette_name`: A string slice that holds the name of the new palette.
+/// # Returns
+/// - Result<(), String> : An empty result or an error message.
+pub fn set_project_palette(state: &mut PipelineState, palette_name: &str) -> Result<(), String> {
+    let palette = Palette::load(palette_name)
+        .map_err(|e| format!("Error loading palette '{}': {:?}", palette_name, e))?;
+    state.palette = palette.name.clone();
+
+    initialize_qgis()?;
+    let project_file = project_file_path(&state.name);
+    let mut result = Ok(());
+    for layer in state
+        .layers
+        .iter_mut()
+        .filter(|layer| layer.stage == LayerStage::Styled)
+    {
+        if let Err(e) = layer.apply_style(&project_file, &palette) {
+            layer.error = Some(e.to_string());
+            result = Err(format!(
+                "Error styling layer '{}': {:?}",
+                layer.project_layer, e
+            ));
+        }
+    }
+
+    state
+        .save()
+        .map_err(|e| format!("Error saving pipeline state: {:?}", e))?;
+    result
+}
+
+/// Write a copy of the project file of a project, its styled layers styled with another
+/// palette, leaving the project file and the pipeline state unchanged.
+///
+/// The copy is written next to the project file, so the relative paths of its layers
+/// still resolve, under a name unique to the run so concurrent exports do not share it.
+/// # Parameters
+/// - `state`: The state of the pipeline.
+/// - `palette_name`: A string slice that holds the name of the palette of the copy.
+/// # Returns
+/// - Result<String, String> : The path of the copy, to remove once used, or an error message.
+pub fn palette_project_copy(state: &PipelineState, palette_name: &str) -> Result<String, String> {
+    let palette = Palette::load(palette_name)
+        .map_err(|e| format!("Error loading palette '{}': {:?}", palette_name, e))?;
+    initialize_qgis()?;
+    let copy_path = format!(
+        "resources/QGIS/{0}/{0}.{1}.{2}-{3}.qgz",
+        state.name,
+        palette.name,
+        std::process::id(),
+        PROJECT_COPIES.fetch_add(1, Ordering::Relaxed)
+    );
+    fs::copy(project_file_path(&state.name), &copy_path)
+        .map_err(|e| format!("Error copying project '{}': {:?}", state.name, e))?;
+
+    for layer in state
+        .layers
+        .iter()
+        .filter(|layer| layer.stage == LayerStage::Styled)
+    {
+        if let Err(e) = layer.apply_style(&copy_path, &palette) {
+            let _ = fs::remove_file(&copy_path);
+            return Err(format!(
+                "Error styling layer '{}': {:?}",
+                layer.project_layer, e
+            ));
+        }
+    }
+    Ok(copy_path)
+}
+
+/// Run the stages of a single layer again, up to the last layer step already done.
+/// # Parameters
+/// - `state`: The state of the pipeline.
+/// - `layer_name`: The name of the layer in the QGIS project.
+/// - `token`: The cancellation token checked during the extraction.
+/// # Returns
+/// - Result<(), String> : An empty result or an error message.
+pub fn retry_layer(
+    state: &mut PipelineState,
+    layer_name: &str,
+    token: &CancellationToken,
+) -> Result<(), String> {
+    let target = if state.is_done(PipelineStep::Style) {
+        LayerStage::Styled
+    } else if state.is_done(PipelineStep::LoadLayers) {
+        LayerStage::Loaded
+    } else if state.is_done(PipelineStep::Extract) {
+        LayerStage::Extracted
+    } else {
+        return Err("The layers have not been extracted yet".to_string());
+    };
+
+    initialize_qgis()?;
+
+    let (name, codes, crs, clip_path, palette) = (
+        state.name.clone(),
+        state.codes.clone(),
+        state.crs.clone(),
+        state.clip_path(),
+        state.palette.clone(),
+    );
+    let layer = state
+        .layers
+        .iter_mut()
+        .find(|layer| layer.project_layer == layer_name)
+        .ok_or_else(|| format!("Layer '{}' not found in the pipeline", layer_name))?;
+
+    let mut result = Ok(());
+    while layer.stage < target {
+        if let Err(e) = layer.advance(&name, &codes, &crs, clip_path.as_deref(), &palette, token) {
+            layer.error = Some(e.to_string());
+            result = Err(format!("Error processing layer '{}': {:?}", layer_name, e));
+            break;
+        }
+    }
+
+    state
+        .save()
+        .map_err(|e| format!("Error saving pipeline state: {:?}", e))?;
+    result
+}
+
+/// Replace the filter of a loaded layer, in the QGIS project and in the pipeline state.
+///
+/// The new filter is applied as a subset string. Features dropped at import by a `copy`
+/// filter are not in the extracted dataset anymore, so widening such a filter has no effect.
+/// # Parameters
+/// - `state`: The state of the pipeline.
+/// - `layer_name`: The name of the layer in the QGIS project.
+/// - `filter`: The new QGIS expression, None to show every feature.
+/// # Returns
+/// - Result<(), String> : An empty result or an error message.
+pub fn edit_layer_filter(
+    state: &mut PipelineState,
+    layer_name: &str,
+    filter: Option<String>,
+) -> Result<(), String> {
+    let project_file = project_file_path(&state.name);
+    let layer = state
+        .layers
+        .iter_mut()
+        .find(|layer| layer.project_layer == layer_name)
+        .ok_or_else(|| format!("Layer '{}' not found in the pipeline", layer_name))?;
+    if layer.stage < LayerStage::Loaded {
+        return Err(format!("Layer '{}' is not loaded yet", layer_name));
+    }
+
+    initialize_qgis()?;
+    set_layer_filter(
+        &project_file,
+        layer_name,
+        filter.as_deref().unwrap_or_default(),
+    )
+    .map_err(|e| format!("Error setting filter of layer '{}': {:?}", layer_name, e))?;
+
+    layer.filter = filter;
+    state
+        .save()
+        .map_err(|e| format!("Error saving pipeline state: {:?}", e))
+}
+
+async fn run_step<F>(
+    state: &mut PipelineState,
+    step: PipelineStep,
+    token: &CancellationToken,
+    report: &F,
+) -> Result<(), String>
+where
+    F: Fn(ProgressEvent) -> Result<(), String>,
+{
+    match step {
+        PipelineStep::Discover => {
+            state.archives = get_shp_file_urls(&state.manifest, &state.codes).await?;
+        }
+        PipelineStep::Download => {
+            download_shp_files(&state.archives, token, report).await?;
+        }
+        PipelineStep::Extract => {
+            if let Some(clip_area) = &state.clip_area {
+                prepare_clip_area(&state.name, clip_area, &state.crs)
+                    .await
+                    .map_err(|e| format!("Error preparing the area of interest: {:?}", e))?;
+            }
+            advance_layers(state, step, LayerStage::Pending, token, report)?
+        }
+        PipelineStep::CreateProject => {
+            create_blank_project(&state.name, &state.crs)
+                .map_err(|e| format!("Error creating QGIS project: {:?}", e))?;
+            create_tree_group(&project_file_path(&state.name), state.manifest.groups())
+                .map_err(|e| format!("Error creating tree group: {:?}", e))?;
+        }
+        PipelineStep::LoadLayers => {
+            advance_layers(state, step, LayerStage::Extracted, token, report)?
+        }
+        PipelineStep::Style => advance_layers(state, step, LayerStage::Loaded, token, report)?,
+        PipelineStep::Export => write_preview(state)?,
+    }
+    Ok(())
+}
+
+/// Render the styled layers of a project into its preview image.
+///
+/// The preview covers the area of interest, or the extracted datasets of the styled
+/// layers if the project has none, and is `PREVIEW_SIZE` pixels on its longest side.
+/// # Parameters
+/// - `state`: The state of the pipeline.
+/// # Returns
+/// - Result<(), String> : An empty result or an error message.
+fn write_preview(state: &PipelineState) -> Result<(), String> {
+    let paths: Vec<String> = match state.clip_path() {
+        Some(clip_path) => vec![clip_path],
+        None => state
+            .layers
+            .iter()
+            .filter(|layer| layer.stage == LayerStage::Styled)
+            .map(|layer| layer.shapefile_path(&state.name))
+            .collect(),
+    };
+    let mut extent: Option<Extent> = None;
+    for path in paths {
+        let (xmin, ymin, xmax, ymax) = get_vector_file_extent(&path)
+            .map_err(|e| format!("Error reading the extent of '{}': {:?}", path, e))?;
+        extent = Some(match extent {
+            Some(extent) => Extent {
+                xmin: extent.xmin.min(xmin),
+                ymin: extent.ymin.min(ymin),
+                xmax: extent.xmax.max(xmax),
+                ymax: extent.ymax.max(ymax),
+            },
+            None => Extent {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            },
+        });
+    }
+    let extent = extent.ok_or_else(|| "No styled layer to render in the preview".to_string())?;
+
+    let export = MapExport {
+        extent,
+        crs: state.crs.clone(),
+        resolution: Resolution::MetresPerPixel(
+            extent.width().max(extent.height()) / PREVIEW_SIZE as f64,
+        ),
+        dpi: DEFAULT_DPI,
+        layers: vec![],
+        format: ImageFormat::Png,
+        output_path: preview_path(&state.name),
+        antialiasing: Some(true),
+        world_file: false,
+    };
+    export_map(&project_file_path(&state.name), &export)
+        .map_err(|e| format!("Error rendering the preview: {}", e))?;
+    Ok(())
+}
+
+/// Advance every layer currently at the given stage, recording the failures on the layers.
+/// # Parameters
+/// - `state`: The state of the pipeline.
+/// - `step`: The step running, used for the progress events.
+/// - `stage`: The stage the layers must be at to be advanced.
+/// - `token`: The cancellation token checked before each layer.
+/// - `report`: Called with a progress event for each layer.
+/// # Returns
+/// - Result<(), String> : An empty result, or an error message if the pipeline was cancelled.
+fn advance_layers<F>(
+    state: &mut PipelineState,
+    step: PipelineStep,
+    stage: LayerStage,
+    token: &CancellationToken,
+    report: &F,
+) -> Result<(), String>
+where
+    F: Fn(ProgressEvent) -> Result<(), String>,
+{
+    let (name, codes, crs, clip_path, palette) = (
+        state.name.clone(),
+        state.codes.clone(),
+        state.crs.clone(),
+        state.clip_path(),
+        state.palette.clone(),
+    );
+    let mut layers: Vec<&mut LayerState> = state
+        .layers
+        .iter_mut()
+        .filter(|layer| layer.stage == stage)
+        .collect();
+    let total = layers.len();
+
+    for (index, layer) in layers.iter_mut().enumerate() {
+        token.check().map_err(|e| e.to_string())?;
+        let sub_progress = (index + 1) as f32 / total as f32;
+
+        match layer.advance(&name, &codes, &crs, clip_path.as_deref(), &palette, token) {
+            Ok(_) => {
+                report(
+                    ProgressEvent::new(step, sub_progress, Severity::Info, "layer_done")
+                        .with_layer(&layer.project_layer),
+                )?;
+            }
+            Err(e) => {
+                token.check().map_err(|e| e.to_string())?;
+                layer.error = Some(e.to_string());
+                report(
+                    ProgressEvent::new(step, sub_progress, Severity::Warning, "layer_failed")
+                        .with_layer(&layer.project_layer),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the urls of the shp files of every manifest source for the given department codes.
+/// # Parameters
+/// - `manifest`: The manifest listing the sources.
+/// - `codes`: The codes of the departments.
+/// # Returns
+/// - Result<Vec<ArchiveState>, String> : The archives to download.
+async fn get_shp_file_urls(
+    manifest: &LayerManifest,
+    codes: &[String],
+) -> Result<Vec<ArchiveState>, String> {
+    let mut archives = Vec::with_capacity(manifest.sources.len() * codes.len());
+    for code in codes {
+        for source in &manifest.sources {
+            let url = get_departement_shp_file_url(code, &source.catalogue_url)
+                .await
+                .map_err(|e| {
+                    format!(
+                        "Error getting {} shp file url for {}: {:?}",
+                        source.source, code, e
+                    )
+                })?;
+            archives.push(ArchiveState {
+                source: source.source.clone(),
+                code: code.clone(),
+                url,
+            });
+        }
+    }
+    Ok(archives)
+}
+
+/// Download the archives, skipping the ones already downloaded.
+/// # Parameters
+/// - `archives`: The archives to download.
+/// - `token`: The cancellation token checked by the download loop.
+/// - `report`: Called with a progress event after each downloaded archive.
+/// # Returns
+/// - Result<(), String> : An empty result or an error message.
+async fn download_shp_files<F>(
+    archives: &[ArchiveState],
+    token: &CancellationToken,
+    report: &F,
+) -> Result<(), String>
+where
+    F: Fn(ProgressEvent) -> Result<(), String>,
+{
+    for (index, archive) in archives.iter().enumerate() {
+        if !Path::new(&archive.path()).exists() {
+            download_shp_file(&archive.url, &archive.code, token)
+                .await
+                .map_err(|e| format!("Error downloading shp file from {}: {:?}", archive.url, e))?;
+        }
+        report(ProgressEvent::new(
+            PipelineStep::Download,
+            (index + 1) as f32 / archives.len() as f32,
+            Severity::Info,
+            "archive_downloaded",
+        ))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,452 @@
+use crate::cancellation::CancellationToken;
+use crate::export::{
+    world_file_path, Extent, ImageFormat, MapExport, RenderedMap, Resolution, DEFAULT_DPI,
+};
+use crate::palette::{Palette, DEFAULT_PALETTE};
+use crate::pipeline::{palette_project_copy, project_file_path, PipelineState};
+use crate::processing::clean_image;
+use crate::qgis_api_wrapper::{export_map, get_vector_file_extent, write_geotiff};
+use crate::slicing::{slice_map, tile_file_name, SliceExport, TileGrid, INDEX_FILE};
+use crate::utils::create_directory_if_not_exists;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The file of the export presets saved by the user, next to the palettes.
+pub const PRESETS_FILE: &str = "resources/export_presets.json";
+/// The name of the file describing the content of a preset output folder.
+pub const EXPORT_MANIFEST_FILE: &str = "manifest.json";
+/// The folder of the tiles in a preset output folder.
+const TILES_FOLDER: &str = "tiles";
+
+/// What is done to the rendered images before they are written.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PostProcessing {
+    /// Smooth the edges of the features when rendering.
+    #[serde(default)]
+    pub antialiasing: bool,
+    /// Snap every pixel to the closest palette colour and replace the parasite colours,
+    /// see `processing::clean_image`.
+    #[serde(default)]
+    pub snap_to_palette: bool,
+}
+
+/// A named set of export settings, matching the input specification of a simulator.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportPreset {
+    pub name: String,
+    /// The ground size of a pixel, in metres.
+    pub metres_per_pixel: f64,
+    /// The width and height of the tiles in pixels, one image for the whole extent if None.
+    #[serde(default)]
+    pub tile_size: Option<u32>,
+    /// The number of pixels shared by neighbouring tiles.
+    #[serde(default)]
+    pub overlap: u32,
+    /// The palette the project is styled with during the export, its own palette if None.
+    #[serde(default)]
+    pub palette: Option<String>,
+    /// The layers and groups rendered, the visible layers of the project if empty.
+    #[serde(default)]
+    pub layers: Vec<String>,
+    pub post_processing: PostProcessing,
+    pub format: ImageFormat,
+}
+
+/// The description of a preset output folder, written as `manifest.json`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub project: String,
+    pub preset: ExportPreset,
+    pub crs: String,
+    /// The area covered by the output, grown to whole pixels and tiles.
+    pub extent: Extent,
+    /// The palette the project was styled with.
+    pub palette: Palette,
+    /// The written images, relative to the output folder.
+    pub files: Vec<String>,
+    /// The index of the tiles relative to the output folder, None for a single image.
+    pub tile_index: Option<String>,
+}
+
+/// Get the presets available without a presets file.
+pub fn bundled_presets() -> Vec<ExportPreset> {
+    vec![
+        ExportPreset {
+            name: "Vulcain 5 m".to_string(),
+            metres_per_pixel: 5.0,
+            tile_size: Some(2048),
+            overlap: 0,
+            palette: Some(DEFAULT_PALETTE.to_string()),
+            layers: Vec::new(),
+            post_processing: PostProcessing {
+                antialiasing: false,
+                snap_to_palette: true,
+            },
+            format: ImageFormat::GeoTiff,
+        },
+        ExportPreset {
+            name: "Overview 50 m".to_string(),
+            metres_per_pixel: 50.0,
+            tile_size: None,
+            overlap: 0,
+            palette: None,
+            layers: Vec::new(),
+            post_processing: PostProcessing {
+                antialiasing: true,
+                snap_to_palette: false,
+            },
+            format: ImageFormat::Png,
+        },
+    ]
+}
+
+fn load_saved_presets() -> Result<Vec<ExportPreset>, Box<dyn Error>> {
+    if !Path::new(PRESETS_FILE).exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(PRESETS_FILE)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Get the available presets: the saved ones, then the bundled ones not replaced by a
+/// saved preset of the same name.
+/// # Returns
+/// - The presets or an error message.
+pub fn get_presets() -> Result<Vec<ExportPreset>, Box<dyn Error>> {
+    let mut presets = load_saved_presets()?;
+    for preset in bundled_presets() {
+        if !presets.iter().any(|p| p.name == preset.name) {
+            presets.push(preset);
+        }
+    }
+    Ok(presets)
+}
+
+/// Get a preset by name.
+/// # Parameters
+/// - `name`: A string slice that holds the name of the preset.
+/// # Returns
+/// - The preset or an error message.
+pub fn get_preset(name: &str) -> Result<ExportPreset, Box<dyn Error>> {
+    get_presets()?
+        .into_iter()
+        .find(|preset| preset.name == name)
+        .ok_or_else(|| format!("Export preset '{}' not found", name).into())
+}
+
+/// Save a preset, replacing the saved preset of the same name.
+/// # Parameters
+/// - `preset`: The preset to save.
+/// # Returns
+/// - An empty result or an error message.
+pub fn save_preset(preset: &ExportPreset) -> Result<(), Box<dyn Error>> {
+    if preset.name.trim().is_empty() {
+        return Err("The name of an export preset cannot be empty".into());
+    }
+    let mut presets = load_saved_presets()?;
+    match presets.iter_mut().find(|p| p.name == preset.name) {
+        Some(saved) => *saved = preset.clone(),
+        None => presets.push(preset.clone()),
+    }
+    write_presets(&presets)
+}
+
+/// Delete a saved preset. A deleted bundled preset comes back with its bundled settings.
+/// # Parameters
+/// - `name`: A string slice that holds the name of the preset.
+/// # Returns
+/// - An empty result or an error message.
+pub fn delete_preset(name: &str) -> Result<(), Box<dyn Error>> {
+    let mut presets = load_saved_presets()?;
+    let count = presets.len();
+    presets.retain(|preset| preset.name != name);
+    if presets.len() == count {
+        return Err(format!("Export preset '{}' is not saved", name).into());
+    }
+    write_presets(&presets)
+}
+
+fn write_presets(presets: &[ExportPreset]) -> Result<(), Box<dyn Error>> {
+    if let Some(folder) = Path::new(PRESETS_FILE).parent() {
+        create_directory_if_not_exists(&folder.to_string_lossy())?;
+    }
+    fs::write(PRESETS_FILE, serde_json::to_string_pretty(presets)?)?;
+    Ok(())
+}
+
+/// Get the extent of a project: the extent of its area of interest.
+/// # Parameters
+/// - `state`: The state of the pipeline of the project.
+/// # Returns
+/// - The extent, in the project CRS, or an error message.
+pub fn project_extent(state: &PipelineState) -> Result<Extent, Box<dyn Error>> {
+    let clip_path = state.clip_path().ok_or_else(|| {
+        format!(
+            "Project '{}' has no area of interest, an extent is needed",
+            state.name
+        )
+    })?;
+    let (xmin, ymin, xmax, ymax) = get_vector_file_extent(&clip_path)?;
+    Ok(Extent {
+        xmin,
+        ymin,
+        xmax,
+        ymax,
+    })
+}
+
+/// Run a preset against a project, writing its images and `manifest.json` in a folder.
+///
+/// A project styled with another palette than the preset one is rendered from a copy
+/// styled with the preset palette, see `with_preset_project`.
+/// # Parameters
+/// - `state`: The state of the pipeline of the project.
+/// - `preset`: The preset.
+/// - `extent`: The exported area, in the project CRS, the project extent if None.
+/// - `output_folder`: A string slice that holds the path of the output folder.
+/// - `token`: The cancellation token checked before each tile.
+/// - `report`: Called after each image with the number of images done and the total.
+/// # Returns
+/// - The manifest of the output folder or an error message.
+pub fn run_preset<F>(
+    state: &PipelineState,
+    preset: &ExportPreset,
+    extent: Option<Extent>,
+    output_folder: &str,
+    token: &CancellationToken,
+    report: F,
+) -> Result<ExportManifest, Box<dyn Error>>
+where
+    F: Fn(usize, usize),
+{
+    let extent = match extent {
+        Some(extent) => extent,
+        None => project_extent(state)?,
+    };
+    with_preset_project(state, preset, |project_file| {
+        export_preset(
+            state,
+            project_file,
+            preset,
+            extent,
+            output_folder,
+            token,
+            report,
+        )
+    })
+}
+
+/// Run an export on the project file styled with the palette of a preset.
+///
+/// The project file itself is used if the project already has the preset palette,
+/// otherwise a styled copy, see `palette_project_copy`, removed once the export is done.
+/// # Parameters
+/// - `state`: The state of the pipeline of the project.
+/// - `preset`: The preset.
+/// - `export`: The export, given the path of the project file to render.
+/// # Returns
+/// - The result of the export, or an error message if the copy could not be made or removed.
+pub fn with_preset_project<T, F>(
+    state: &PipelineState,
+    preset: &ExportPreset,
+    export: F,
+) -> Result<T, Box<dyn Error>>
+where
+    F: FnOnce(&str) -> Result<T, Box<dyn Error>>,
+{
+    let palette_name = preset.palette.as_deref().unwrap_or(&state.palette);
+    if palette_name == state.palette {
+        return export(&project_file_path(&state.name));
+    }
+
+    let copy_path = palette_project_copy(state, palette_name)?;
+    let result = export(&copy_path);
+    let removed = fs::remove_file(&copy_path);
+    let exported = result?;
+    removed?;
+    Ok(exported)
+}
+
+/// Export a project with a preset.
+/// # Parameters
+/// - `state`: The state of the pipeline of the project.
+/// - `project_file`: A string slice that holds the path of the project file rendered,
+///   styled with the preset palette, see `with_preset_project`.
+/// - `preset`: The preset.
+/// - `extent`: The exported area, in the project CRS.
+/// - `output_folder`: A string slice that holds the path of the output folder.
+/// - `token`: The cancellation token checked before each tile.
+/// - `report`: Called after each image with the number of images done and the total.
+/// # Returns
+/// - The manifest of the output folder or an error message.
+pub fn export_preset<F>(
+    state: &PipelineState,
+    project_file: &str,
+    preset: &ExportPreset,
+    extent: Extent,
+    output_folder: &str,
+    token: &CancellationToken,
+    report: F,
+) -> Result<ExportManifest, Box<dyn Error>>
+where
+    F: Fn(usize, usize),
+{
+    let palette = Palette::load(preset.palette.as_deref().unwrap_or(&state.palette))?;
+    let post_processing = &preset.post_processing;
+    // The images are post-processed from a lossless render, then written in the preset format.
+    let render_format = if post_processing.snap_to_palette {
+        ImageFormat::Png
+    } else {
+        preset.format
+    };
+    create_directory_if_not_exists(output_folder)?;
+
+    let (covered, files, tile_index) = match preset.tile_size {
+        Some(tile_size) => {
+            let tiles_folder = format!("{}/{}", output_folder, TILES_FOLDER);
+            let mut index = slice_map(
+                project_file,
+                &SliceExport {
+                    grid: TileGrid {
+                        extent,
+                        crs: state.crs.clone(),
+                        tile_size,
+                        metres_per_pixel: preset.metres_per_pixel,
+                        overlap: preset.overlap,
+                    },
+                    layers: preset.layers.clone(),
+                    format: render_format,
+                    antialiasing: post_processing.antialiasing,
+                    output_folder: tiles_folder.clone(),
+                },
+                token,
+                &report,
+            )?;
+
+            if post_processing.snap_to_palette {
+                let size = tile_size + 2 * preset.overlap;
+                for tile in index.tiles.iter_mut() {
+                    token.check()?;
+                    let render_path = format!("{}/{}", tiles_folder, tile.file);
+                    let file = tile_file_name(tile.row, tile.column, &tile.extent, preset.format);
+                    post_process(
+                        &render_path,
+                        &RenderedMap {
+                            path: format!("{}/{}", tiles_folder, file),
+                            width: size,
+                            height: size,
+                            extent: tile.extent,
+                            crs: index.grid.crs.clone(),
+                        },
+                        preset.format,
+                        &palette,
+                    )?;
+                    tile.file = file;
+                }
+                index.format = preset.format;
+                fs::write(
+                    format!("{}/{}", tiles_folder, INDEX_FILE),
+                    serde_json::to_string_pretty(&index)?,
+                )?;
+            }
+
+            let files = index
+                .tiles
+                .iter()
+                .map(|tile| format!("{}/{}", TILES_FOLDER, tile.file))
+                .collect();
+            (
+                index.extent(),
+                files,
+                Some(format!("{}/{}", TILES_FOLDER, INDEX_FILE)),
+            )
+        }
+        None => {
+            let file = format!("{}.{}", state.name, preset.format.extension());
+            let output_path = format!("{}/{}", output_folder, file);
+            let render_path = if post_processing.snap_to_palette {
+                format!("{}.render.png", output_path)
+            } else {
+                output_path.clone()
+            };
+            let rendered = export_map(
+                project_file,
+                &MapExport {
+                    extent,
+                    crs: state.crs.clone(),
+                    resolution: Resolution::MetresPerPixel(preset.metres_per_pixel),
+                    dpi: DEFAULT_DPI,
+                    layers: preset.layers.clone(),
+                    format: render_format,
+                    output_path: render_path.clone(),
+                    antialiasing: Some(post_processing.antialiasing),
+                    world_file: !post_processing.snap_to_palette,
+                },
+            )?;
+            if post_processing.snap_to_palette {
+                post_process(
+                    &render_path,
+                    &RenderedMap {
+                        path: output_path,
+                        ..rendered.clone()
+                    },
+                    preset.format,
+                    &palette,
+                )?;
+            }
+            report(1, 1);
+            (rendered.extent, vec![file], None)
+        }
+    };
+
+    let manifest = ExportManifest {
+        project: state.name.clone(),
+        preset: preset.clone(),
+        crs: state.crs.clone(),
+        extent: covered,
+        palette,
+        files,
+        tile_index,
+    };
+    fs::write(
+        format!("{}/{}", output_folder, EXPORT_MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(manifest)
+}
+
+/// Clean a rendered PNG with `clean_image` and write it in its final format, with a world
+/// file if the format does not embed its georeferencing. The rendered image is removed.
+fn post_process(
+    render_path: &str,
+    map: &RenderedMap,
+    format: ImageFormat,
+    palette: &Palette,
+) -> Result<(), Box<dyn Error>> {
+    let cleaned = clean_image(&image::open(render_path)?, palette);
+    if format == ImageFormat::GeoTiff {
+        let clean_path = format!("{}.clean.png", map.path);
+        cleaned.save(&clean_path)?;
+        let result = write_geotiff(&clean_path, map);
+        fs::remove_file(&clean_path)?;
+        result?;
+    } else {
+        cleaned.save(&map.path)?;
+        fs::write(world_file_path(&map.path, format), map.world_file())?;
+    }
+
+    if render_path != map.path {
+        fs::remove_file(render_path)?;
+        for path in [
+            world_file_path(render_path, ImageFormat::Png),
+            format!("{}.aux.xml", render_path),
+        ] {
+            if Path::new(&path).exists() {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::palette::Palette;
 use image::{DynamicImage, GenericImage, GenericImageView, Pixel, Rgb};
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
@@ -115,26 +116,56 @@ fn post_process_chunk(chunk: &DynamicImage, indesirables: &HashSet<Rgb<u8>>) ->
     processed_chunk
 }
 
-pub fn main() {
+/// Snap every pixel of an image to the closest colour of a palette, then replace the
+/// parasite colours by the most common colour around them.
+/// # Parameters
+/// - `img`: The rendered image.
+/// - `palette`: The palette of the colours kept and of the parasites.
+/// # Returns
+/// - The cleaned image.
+pub fn clean_image(img: &DynamicImage, palette: &Palette) -> DynamicImage {
+    let mut colours = palette.colors();
+    colours.extend(palette.parasite_colors());
+    let indesirables: HashSet<_> = palette
+        .parasite_colors()
+        .into_iter()
+        .map(|(r, g, b)| Rgb([r, g, b]))
+        .collect();
+
+    let (width, height) = img.dimensions();
+
+    let chunk_width = width / smallest_divider(width);
+    let chunk_height = height / smallest_divider(height);
+
+    let chunks: Vec<DynamicImage> = (0..height / chunk_height)
+        .flat_map(|y| (0..width / chunk_width).map(move |x| (x, y)))
+        .collect::<Vec<_>>()
+        .par_iter()
+        .map(|&(x, y)| {
+            let chunk = img.crop_imm(x * chunk_width, y * chunk_height, chunk_width, chunk_height);
+            let processed_chunk = proccessing_chunk(&chunk, &colours);
+            post_process_chunk(&processed_chunk, &indesirables)
+        })
+        .collect();
+
+    let mut new_img = DynamicImage::new_rgb8(width, height);
+    let (mut x, mut y) = (0, 0);
+
+    for chunk in chunks {
+        new_img.copy_from(&chunk, x, y).unwrap();
+        x += chunk.width();
+        if x >= width {
+            x = 0;
+            y += chunk.height();
+        }
+    }
+
+    new_img
+}
+
+pub fn main(palette: &Palette) {
     let veg_folder = "content/veg";
     let tmp_folder = "tmp";
-    let colours = vec![
-        (0, 0, 0),
-        (4, 25, 30),
-        (25, 50, 60),
-        (50, 200, 80),
-        (80, 200, 120),
-        (255, 255, 255),
-        (128, 128, 128),
-        (14, 14, 14), // export parasite
-    ];
-    let indesirables: HashSet<_> = vec![
-        Rgb([255, 255, 255]),
-        Rgb([128, 128, 128]),
-        Rgb([14, 14, 14]),
-    ]
-    .into_iter()
-    .collect();
 
     for entry in std::fs::read_dir(veg_folder).unwrap() {
         let img_path = entry.unwrap().path();
@@ -142,37 +173,8 @@ pub fn main() {
         let mut reader = image::ImageReader::open(&img_path).unwrap();
         reader.no_limits();
         let img = reader.with_guessed_format().unwrap().decode().unwrap();
-        
-        let (width, height) = img.dimensions();
-
-        let chunk_width = width / smallest_divider(width);
-        let chunk_height = height / smallest_divider(height);
-
-        let chunks: Vec<DynamicImage> = (0..height / chunk_height)
-            .flat_map(|y| (0..width / chunk_width).map(move |x| (x, y)))
-            .collect::<Vec<_>>()
-            .par_iter()
-            .map(|&(x, y)| {
-                let chunk =
-                    img.crop_imm(x * chunk_width, y * chunk_height, chunk_width, chunk_height);
-                let processed_chunk = proccessing_chunk(&chunk, &colours);
-                post_process_chunk(&processed_chunk, &indesirables)
-            })
-            .collect();
-
-        let mut new_img = DynamicImage::new_rgb8(width, height);
-        let (mut x, mut y) = (0, 0);
-
-        for chunk in chunks {
-            new_img.copy_from(&chunk, x, y).unwrap();
-            x += chunk.width();
-            if x >= width {
-                x = 0;
-                y += chunk.height();
-            }
-        }
 
-        new_img
+        clean_image(&img, palette)
             .save(format!("{}/{}", tmp_folder, img_name))
             .unwrap();
         println!("{} traité", img_name);
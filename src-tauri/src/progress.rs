@@ -0,0 +1,113 @@
+use crate::pipeline::{PipelineState, PipelineStep, StepStatus};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+/// A progress update of the project creation, sent to the frontend as JSON.
+///
+/// The frontend translates `message_key` and computes the overall percentage from
+/// the step index, the step total and the sub progress.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProgressEvent {
+    pub step: PipelineStep,
+    pub step_index: usize,
+    pub step_total: usize,
+    /// Progress within the current step, between 0 and 1.
+    pub sub_progress: f32,
+    /// The layer being processed, if the event concerns a single layer.
+    pub layer: Option<String>,
+    pub severity: Severity,
+    pub message_key: String,
+}
+
+impl ProgressEvent {
+    pub fn new(
+        step: PipelineStep,
+        sub_progress: f32,
+        severity: Severity,
+        message_key: &str,
+    ) -> Self {
+        ProgressEvent {
+            step,
+            step_index: step.index(),
+            step_total: PipelineStep::ALL.len(),
+            sub_progress: sub_progress.clamp(0.0, 1.0),
+            layer: None,
+            severity,
+            message_key: message_key.to_string(),
+        }
+    }
+
+    pub fn with_layer(mut self, layer: &str) -> Self {
+        self.layer = Some(layer.to_string());
+        self
+    }
+
+    /// Get the overall progress of the pipeline, between 0 and 1.
+    pub fn overall_progress(&self) -> f32 {
+        (self.step_index as f32 + self.sub_progress) / self.step_total as f32
+    }
+}
+
+/// The final event of a project creation, listing what went wrong without stopping it.
+#[derive(Clone, Debug, Serialize)]
+pub struct PipelineSummary {
+    pub name: String,
+    pub completed: bool,
+    pub layers_total: usize,
+    pub layers_failed: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl PipelineSummary {
+    pub fn from_state(state: &PipelineState) -> Self {
+        let failed_layers = state.failed_layers();
+
+        let mut warnings: Vec<String> = failed_layers
+            .iter()
+            .map(|layer| {
+                format!(
+                    "{}: {}",
+                    layer.project_layer,
+                    layer.error.as_deref().unwrap_or_default()
+                )
+            })
+            .collect();
+        warnings.extend(
+            state
+                .layers
+                .iter()
+                .filter_map(|layer| layer.crs_warning(&state.crs)),
+        );
+        warnings.extend(
+            state
+                .steps
+                .iter()
+                .filter(|step| step.status == StepStatus::Failed)
+                .map(|step| {
+                    format!(
+                        "{}: {}",
+                        step.step.label(),
+                        step.error.as_deref().unwrap_or_default()
+                    )
+                }),
+        );
+
+        PipelineSummary {
+            name: state.name.clone(),
+            completed: state.next_step().is_none(),
+            layers_total: state.layers.len(),
+            layers_failed: failed_layers
+                .iter()
+                .map(|layer| layer.project_layer.clone())
+                .collect(),
+            warnings,
+        }
+    }
+}
@@ -1,11 +1,72 @@
+use crate::export::{world_file_path, ExportError, Extent, ImageFormat, MapExport, RenderedMap};
+use crate::layout::{Frame, LayoutExport, LayoutFormat};
+use crate::manifest::LayerManifest;
+use crate::masks::MaskClass;
+use crate::palette::Palette;
+use crate::rasterize::Geometry;
+use crate::slicing::TileWindow;
+use crate::styles::StyleFormat;
+use crate::symbology::{
+    CategoryUpdate, LayerRenderer, ResolvedRule, SimpleSymbol, DEFAULT_LINE_WIDTH,
+    DEFAULT_MARKER_SIZE,
+};
+use crate::tiles::{TilePyramid, TileStorage};
 use crate::utils::{self, create_directory_if_not_exists};
-use pyo3::{prelude::*, types::PyDict};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyDict};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
 
 const QGIS_APP_PATHS: &[(&str, &str)] = &[
     ("windows", "AppData\\Roaming\\QGIS\\QGIS3"),
     ("linux", ".local/share/QGIS/QGIS3"),
     ("macos", "Library/Application Support/QGIS/QGIS3"),
 ];
+/// The custom property of the layers holding their draw order, see `set_layer_draw_order`.
+const DRAW_ORDER_PROPERTY: &str = "eve_mapmaker/draw_order";
+
+/// Python snippet selecting the layers of `names` in `project`, a name being a layer or
+/// a group path like "combustion/BDTOPO". Defines `layers`, in the order of the layer tree,
+/// and `missing`, the names not found. Every visible layer is selected if `names` is empty.
+const SELECT_LAYERS: &str = r#"
+root = project.layerTreeRoot()
+tree_layers = root.findLayers()
+missing = []
+if names:
+    selected = set()
+    for name in names:
+        group = root
+        for part in name.split("/"):
+            group = group.findGroup(part) if group is not None else None
+        if group is not None:
+            selected.update(node.layerId() for node in group.findLayers())
+            continue
+        layers = project.mapLayersByName(name)
+        if layers:
+            selected.update(layer.id() for layer in layers)
+        else:
+            missing.append(name)
+    layers = [node.layer() for node in tree_layers if node.layerId() in selected]
+else:
+    layers = [node.layer() for node in tree_layers if node.isVisible()]
+layers = [layer for layer in layers if layer is not None]
+"#;
+
+/// Held while a script uses the `QgsProject` singleton, so the commands and exports
+/// running on several threads do not replace the project under each other.
+static PROJECT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Run Python code that reads or writes the `QgsProject` singleton, holding the project
+/// lock for the whole run. Every script using `QgsProject.instance()` goes through it.
+fn with_project<F, R>(f: F) -> R
+where
+    F: for<'py> FnOnce(Python<'py>) -> R,
+{
+    let _project_lock = PROJECT_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    Python::with_gil(f)
+}
 
 fn get_qgis_app_path(os: &str) -> Option<&'static str> {
     QGIS_APP_PATHS
@@ -13,6 +74,39 @@ fn get_qgis_app_path(os: &str) -> Option<&'static str> {
         .find_map(|&(key, path)| if key == os { Some(path) } else { None })
 }
 
+/// Python helpers prepended to the scripts that run QGIS processing algorithms
+/// or rewrite a shapefile in place.
+const VECTOR_FILE_HELPERS: &str = r#"
+import os
+from qgis.core import QgsApplication, QgsProcessingContext, QgsProcessingFeedback, QgsVectorFileWriter
+from qgis.analysis import QgsNativeAlgorithms
+
+if QgsApplication.instance() is None:
+    qgs_app = QgsApplication([], False)
+    qgs_app.initQgis()
+if QgsApplication.processingRegistry().providerById("native") is None:
+    QgsApplication.processingRegistry().addProvider(QgsNativeAlgorithms())
+
+processing_context = QgsProcessingContext()
+
+def run_algorithm(algorithm_id, parameters):
+    algorithm = QgsApplication.processingRegistry().createAlgorithmById(algorithm_id)
+    if algorithm is None:
+        raise Exception("Algorithm " + algorithm_id + " not found")
+    results, ok = algorithm.run(parameters, processing_context, QgsProcessingFeedback())
+    if not ok:
+        raise Exception("Algorithm " + algorithm_id + " failed")
+    return results
+
+def replace_shapefile(layer_path, new_layer_path):
+    QgsVectorFileWriter.deleteShapeFile(layer_path)
+    base, _ = os.path.splitext(layer_path)
+    new_base, _ = os.path.splitext(new_layer_path)
+    for extension in [".shp", ".shx", ".dbf", ".prj", ".cpg", ".qpj"]:
+        if os.path.exists(new_base + extension):
+            os.replace(new_base + extension, base + extension)
+"#;
+
 fn run_python_code(py: Python, code: &str) -> PyResult<()> {
     py.run_bound(code, None, None)?;
     Ok(())
@@ -40,7 +134,7 @@ QgsApplication.setPrefixPath("{path}", True)
 }
 
 #[pyfunction]
-pub fn create_blank_project(project_name: &str) -> PyResult<String> {
+pub fn create_blank_project(project_name: &str, crs: &str) -> PyResult<String> {
     let project_folder = format!("resources/QGIS/{}", project_name);
     let project_file_path = format!("{}/{}.qgz", project_folder, project_name);
 
@@ -48,15 +142,17 @@ pub fn create_blank_project(project_name: &str) -> PyResult<String> {
 
     let code = format!(
         r#"
-from qgis.core import QgsProject
+from qgis.core import QgsProject, QgsCoordinateReferenceSystem
 project = QgsProject.instance()
 project.clear()
+project.setCrs(QgsCoordinateReferenceSystem("{crs}"))
 project.write("{project_file_path}")
 "#,
-        project_file_path = project_file_path
+        project_file_path = project_file_path,
+        crs = crs
     );
 
-    Python::with_gil(|py| {
+    with_project(|py| {
         run_python_code(py, &code)?;
         Ok(format!(
             "Project {} created at {}",
@@ -80,6 +176,8 @@ layer = QgsVectorLayer("{layer_path}", "{layer_name}", "ogr")
 if not layer.isValid():
     raise Exception("Layer not valid")
 else:
+    if not layer.crs().isValid():
+        layer.setCrs(project.crs())
     project.addMapLayer(layer)
 project.write(project.fileName())
 "#,
@@ -88,7 +186,7 @@ project.write(project.fileName())
         layer_name = layer_name
     );
 
-    Python::with_gil(|py| {
+    with_project(|py| {
         run_python_code(py, &code)?;
         Ok(format!(
             "Layer {} loaded to project {}",
@@ -98,97 +196,554 @@ project.write(project.fileName())
 }
 
 #[pyfunction]
-pub fn create_tree_group(project_name: &str) -> PyResult<String> {
+pub fn create_tree_group(project_name: &str, groups: Vec<String>) -> PyResult<String> {
     let code = format!(
         r#"
 from qgis.core import QgsProject, QgsLayerTreeGroup
 project = QgsProject.instance()
 project.read("{project_name}")
 root = project.layerTreeRoot()
-group = root.addGroup("combustion")
-group.addGroup("BDTOPO")
-group.addGroup("Vegetation")
+for path in {groups:?}:
+    group = root
+    for name in path.split("/"):
+        child = group.findGroup(name)
+        group = child if child else group.addGroup(name)
 project.write(project.fileName())
 "#,
-        project_name = project_name
+        project_name = project_name,
+        groups = groups
     );
 
-    Python::with_gil(|py| {
+    with_project(|py| {
         run_python_code(py, &code)?;
         Ok(format!(
-            "Group 'combustion' created in project {}",
-            project_name
+            "Groups {:?} created in project {}",
+            groups, project_name
         ))
     })
 }
 
 #[pyfunction]
-pub fn setup_basic_veg_layer(project_name: &str, layer_name: &str) -> PyResult<String> {
+pub fn move_layer_to_group(
+    project_name: &str,
+    layer_name: &str,
+    group_path: &str,
+) -> PyResult<String> {
     let code = format!(
         r#"
-from qgis.core import QgsProject, QgsCategorizedSymbolRenderer, QgsField, QgsFillSymbol, QgsRendererCategory, QgsLayerTreeGroup
-from qgis.PyQt.QtGui import QColor
-
+from qgis.core import QgsProject, QgsLayerTreeGroup
 project = QgsProject.instance()
 project.read("{project_name}")
-
 layer = project.mapLayersByName("{layer_name}")
 if not layer:
     raise Exception("Layer not found")
 layer = layer[0]
 
 root = project.layerTreeRoot()
-combustion_group = root.findGroup('combustion')
-vegetation_group = combustion_group.findGroup('Vegetation')
+group = root
+for name in "{group_path}".split("/"):
+    group = group.findGroup(name)
+    if not group:
+        raise Exception("Group '{group_path}' not found")
 
-if not vegetation_group:
-    raise Exception("Group 'Vegetation' not found")
 layer_node = root.findLayer(layer.id())
 if layer_node:
     parent = layer_node.parent()
     parent.removeChildNode(layer_node)
+group.addLayer(layer)
+project.write(project.fileName())
+"#,
+        project_name = project_name,
+        layer_name = layer_name,
+        group_path = group_path
+    );
 
-vegetation_group.addLayer(layer)
-essence_field_index = layer.fields().indexFromName('ESSENCE')
-if essence_field_index == -1:
-    raise Exception("'ESSENCE' field not found")
+    with_project(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!(
+            "Layer {} moved to group '{}' in project {}",
+            layer_name, group_path, project_name
+        ))
+    })
+}
 
-unique_values = layer.uniqueValues(essence_field_index)
+#[pyfunction]
+pub fn set_layer_filter(project_name: &str, layer_name: &str, filter: &str) -> PyResult<String> {
+    let code = format!(
+        r#"
+from qgis.core import QgsProject
+project = QgsProject.instance()
+project.read("{project_name}")
+layer = project.mapLayersByName("{layer_name}")
+if not layer:
+    raise Exception("Layer not found")
+layer = layer[0]
+if not layer.setSubsetString({filter:?}):
+    raise Exception("Invalid filter expression")
+project.write(project.fileName())
+"#,
+        project_name = project_name,
+        layer_name = layer_name,
+        filter = filter
+    );
 
-categories = []
-for value in unique_values:
-    if str(value) in ['Feuillus', 'Châtaignier', 'Chênes sempervirents', 'Chênes décidus', 'Hêtre']:
-        symbol = QgsFillSymbol.createSimple({{'color': '80,200,120,255', 'outline_style': 'no'}})
-    elif str(value) in ['NC', 'NR']:
-        symbol = QgsFillSymbol.createSimple({{'color': '25,50,60,255', 'outline_style': 'no'}})
-    else:
-        symbol = QgsFillSymbol.createSimple({{'color': '50,200,80,255', 'outline_style': 'no'}})
-    category = QgsRendererCategory(value, symbol, str(value))
-    categories.append(category)
+    with_project(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!(
+            "Filter '{}' applied to layer {} in project {}",
+            filter, layer_name, project_name
+        ))
+    })
+}
 
-renderer = QgsCategorizedSymbolRenderer('ESSENCE', categories)
-layer.setRenderer(renderer)
-layer.triggerRepaint()
-project.write(project.fileName())
+#[pyfunction]
+pub fn get_layer_filter(project_name: &str, layer_name: &str) -> PyResult<String> {
+    let code = format!(
+        r#"
+from qgis.core import QgsProject
+project = QgsProject.instance()
+project.read("{project_name}")
+layer = project.mapLayersByName("{layer_name}")
+if not layer:
+    raise Exception("Layer not found")
+layer_filter = layer[0].subsetString()
 "#,
         project_name = project_name,
         layer_name = layer_name
     );
 
+    with_project(|py| -> PyResult<String> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        let result: String = match locals.get_item("layer_filter") {
+            Ok(Some(value)) => value.extract().unwrap_or_default(),
+            Ok(None) => String::new(),
+            Err(_) => String::new(),
+        };
+        Ok(result)
+    })
+}
+
+#[pyfunction]
+pub fn filter_vector_file(layer_path: &str, filter: &str) -> PyResult<String> {
+    let code = format!(
+        r#"{helpers}
+from qgis.core import QgsVectorLayer, QgsCoordinateTransformContext, QgsExpression
+expression = QgsExpression({filter:?})
+if expression.hasParserError():
+    raise Exception("Invalid filter expression: " + expression.parserErrorString())
+layer = QgsVectorLayer("{layer_path}", "filtered", "ogr")
+if not layer.isValid():
+    raise Exception("Layer not valid")
+layer.selectByExpression({filter:?})
+options = QgsVectorFileWriter.SaveVectorOptions()
+options.driverName = "ESRI Shapefile"
+options.fileEncoding = "UTF-8"
+options.onlySelectedFeatures = True
+filtered_path = os.path.splitext("{layer_path}")[0] + "_filtered.shp"
+result = QgsVectorFileWriter.writeAsVectorFormatV3(layer, filtered_path, QgsCoordinateTransformContext(), options)
+if result[0] != QgsVectorFileWriter.NoError:
+    raise Exception("Failed to filter layer: " + result[1])
+del layer
+replace_shapefile("{layer_path}", filtered_path)
+"#,
+        helpers = VECTOR_FILE_HELPERS,
+        layer_path = layer_path,
+        filter = filter
+    );
+
+    Python::with_gil(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!("Layer {} filtered with '{}'", layer_path, filter))
+    })
+}
+
+#[pyfunction]
+pub fn prepare_clip_layer(
+    source_path: &str,
+    output_path: &str,
+    buffer: f64,
+    crs: &str,
+) -> PyResult<String> {
+    let code = format!(
+        r#"{helpers}
+from qgis.core import QgsCoordinateReferenceSystem
+reprojected = run_algorithm("native:reprojectlayer", {{
+    "INPUT": "{source_path}",
+    "TARGET_CRS": QgsCoordinateReferenceSystem("{crs}"),
+    "OUTPUT": "TEMPORARY_OUTPUT",
+}})["OUTPUT"]
+run_algorithm("native:buffer", {{
+    "INPUT": reprojected,
+    "DISTANCE": {buffer},
+    "SEGMENTS": 8,
+    "DISSOLVE": True,
+    "OUTPUT": "{output_path}",
+}})
+"#,
+        helpers = VECTOR_FILE_HELPERS,
+        source_path = source_path,
+        output_path = output_path,
+        buffer = buffer,
+        crs = crs
+    );
+
     Python::with_gil(|py| {
         run_python_code(py, &code)?;
         Ok(format!(
-            "Layer {} in project {} categorized by ESSENCE, moved to 'combustion' group, and processed successfully",
-            layer_name, project_name
+            "Clip layer {} created from {} with a {} m buffer",
+            output_path, source_path, buffer
+        ))
+    })
+}
+
+#[pyfunction]
+pub fn clip_vector_file(layer_path: &str, clip_path: &str) -> PyResult<String> {
+    let code = format!(
+        r#"{helpers}
+clipped_path = os.path.splitext("{layer_path}")[0] + "_clipped.shp"
+run_algorithm("native:clip", {{
+    "INPUT": "{layer_path}",
+    "OVERLAY": "{clip_path}",
+    "OUTPUT": clipped_path,
+}})
+replace_shapefile("{layer_path}", clipped_path)
+"#,
+        helpers = VECTOR_FILE_HELPERS,
+        layer_path = layer_path,
+        clip_path = clip_path
+    );
+
+    Python::with_gil(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!("Layer {} clipped to {}", layer_path, clip_path))
+    })
+}
+
+#[pyfunction]
+pub fn get_vector_file_crs(layer_path: &str) -> PyResult<String> {
+    let code = format!(
+        r#"
+from qgis.core import QgsVectorLayer
+layer = QgsVectorLayer("{layer_path}", "crs", "ogr")
+if not layer.isValid():
+    raise Exception("Layer not valid")
+layer_crs = layer.crs().authid() if layer.crs().isValid() else ""
+"#,
+        layer_path = layer_path
+    );
+
+    Python::with_gil(|py| -> PyResult<String> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        let result: String = match locals.get_item("layer_crs") {
+            Ok(Some(value)) => value.extract().unwrap_or_default(),
+            Ok(None) => String::new(),
+            Err(_) => String::new(),
+        };
+        Ok(result)
+    })
+}
+
+#[pyfunction]
+pub fn get_vector_file_extent(layer_path: &str) -> PyResult<(f64, f64, f64, f64)> {
+    let code = format!(
+        r#"
+from qgis.core import QgsVectorLayer
+layer = QgsVectorLayer("{layer_path}", "extent", "ogr")
+if not layer.isValid():
+    raise Exception("Layer not valid")
+extent = layer.extent()
+layer_extent = (extent.xMinimum(), extent.yMinimum(), extent.xMaximum(), extent.yMaximum())
+"#,
+        layer_path = layer_path
+    );
+
+    Python::with_gil(|py| -> PyResult<(f64, f64, f64, f64)> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        match locals.get_item("layer_extent") {
+            Ok(Some(value)) => value.extract(),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Extent not computed",
+            )),
+        }
+    })
+}
+
+/// Get the extent of every feature of a vector file, in a CRS and grown by a buffer.
+/// # Parameters
+/// - `layer_path`: A string slice that holds the path of the vector file.
+/// - `crs`: A string slice that holds the CRS of the extents, like "EPSG:2154".
+/// - `name_field`: The field naming the features, their feature ID if None.
+/// - `buffer`: The distance added on every side of the extents, in `crs` units.
+/// # Returns
+/// - The name and the extent of the features with a geometry.
+pub fn get_feature_extents(
+    layer_path: &str,
+    crs: &str,
+    name_field: Option<&str>,
+    buffer: f64,
+) -> PyResult<Vec<(String, Extent)>> {
+    let name_field = match name_field {
+        Some(name_field) => format!("{:?}", name_field),
+        None => "None".to_string(),
+    };
+    let code = format!(
+        r#"
+from qgis.core import QgsVectorLayer, QgsCoordinateReferenceSystem, QgsCoordinateTransform, QgsProject
+layer = QgsVectorLayer({layer_path:?}, "areas", "ogr")
+if not layer.isValid():
+    raise Exception("Layer not valid")
+name_field = {name_field}
+if name_field is not None and layer.fields().indexOf(name_field) < 0:
+    raise Exception(f"Field {{name_field}} not found")
+to_crs = QgsCoordinateTransform(layer.crs(), QgsCoordinateReferenceSystem({crs:?}), QgsProject.instance())
+feature_extents = []
+for feature in layer.getFeatures():
+    if not feature.hasGeometry():
+        continue
+    extent = to_crs.transformBoundingBox(feature.geometry().boundingBox())
+    extent.grow({buffer:?})
+    name = str(feature[name_field]) if name_field is not None else str(feature.id())
+    feature_extents.append((name, [extent.xMinimum(), extent.yMinimum(), extent.xMaximum(), extent.yMaximum()]))
+"#,
+        layer_path = layer_path,
+        name_field = name_field,
+        crs = crs,
+        buffer = buffer
+    );
+
+    let feature_extents: Vec<(String, [f64; 4])> = with_project(|py| -> PyResult<_> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        match locals.get_item("feature_extents") {
+            Ok(Some(value)) => value.extract(),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Extents not computed",
+            )),
+        }
+    })?;
+    Ok(feature_extents
+        .into_iter()
+        .map(|(name, [xmin, ymin, xmax, ymax])| {
+            (
+                name,
+                Extent {
+                    xmin,
+                    ymin,
+                    xmax,
+                    ymax,
+                },
+            )
+        })
+        .collect())
+}
+
+#[pyfunction]
+pub fn reproject_vector_file(layer_path: &str, crs: &str) -> PyResult<String> {
+    let code = format!(
+        r#"{helpers}
+from qgis.core import QgsCoordinateReferenceSystem
+reprojected_path = os.path.splitext("{layer_path}")[0] + "_reprojected.shp"
+run_algorithm("native:reprojectlayer", {{
+    "INPUT": "{layer_path}",
+    "TARGET_CRS": QgsCoordinateReferenceSystem("{crs}"),
+    "OUTPUT": reprojected_path,
+}})
+replace_shapefile("{layer_path}", reprojected_path)
+"#,
+        helpers = VECTOR_FILE_HELPERS,
+        layer_path = layer_path,
+        crs = crs
+    );
+
+    Python::with_gil(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!("Layer {} reprojected to {}", layer_path, crs))
+    })
+}
+
+#[pyfunction]
+pub fn assign_vector_file_crs(layer_path: &str, crs: &str) -> PyResult<String> {
+    let code = format!(
+        r#"{helpers}
+from qgis.core import QgsCoordinateReferenceSystem
+assigned_path = os.path.splitext("{layer_path}")[0] + "_assigned.shp"
+run_algorithm("native:assignprojection", {{
+    "INPUT": "{layer_path}",
+    "CRS": QgsCoordinateReferenceSystem("{crs}"),
+    "OUTPUT": assigned_path,
+}})
+replace_shapefile("{layer_path}", assigned_path)
+"#,
+        helpers = VECTOR_FILE_HELPERS,
+        layer_path = layer_path,
+        crs = crs
+    );
+
+    Python::with_gil(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!("CRS {} assigned to layer {}", crs, layer_path))
+    })
+}
+
+#[pyfunction]
+pub fn merge_vector_files(layer_paths: Vec<String>, output_path: &str) -> PyResult<String> {
+    let code = format!(
+        r#"{helpers}
+merged = run_algorithm("native:mergevectorlayers", {{
+    "LAYERS": {layer_paths:?},
+    "OUTPUT": "TEMPORARY_OUTPUT",
+}})["OUTPUT"]
+cleaned = run_algorithm("native:deletecolumn", {{
+    "INPUT": merged,
+    "COLUMN": ["layer", "path"],
+    "OUTPUT": "TEMPORARY_OUTPUT",
+}})["OUTPUT"]
+run_algorithm("native:deleteduplicategeometries", {{
+    "INPUT": cleaned,
+    "OUTPUT": "{output_path}",
+}})
+"#,
+        helpers = VECTOR_FILE_HELPERS,
+        layer_paths = layer_paths,
+        output_path = output_path
+    );
+
+    Python::with_gil(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!(
+            "Layers {:?} merged into {}",
+            layer_paths, output_path
         ))
     })
 }
 
 #[pyfunction]
-pub fn setup_basic_topo_layer(project_name: &str, layer_name: &str) -> PyResult<String> {
+pub fn get_buffered_extent_wgs84(
+    layer_path: &str,
+    buffer: f64,
+    crs: &str,
+) -> PyResult<(f64, f64, f64, f64)> {
     let code = format!(
         r#"
-from qgis.core import QgsProject, QgsSimpleLineSymbolLayer, QgsSimpleFillSymbolLayer, QgsSymbol, QgsLayerTreeGroup
+from qgis.core import QgsVectorLayer, QgsCoordinateReferenceSystem, QgsCoordinateTransform, QgsProject
+layer = QgsVectorLayer("{layer_path}", "extent", "ogr")
+if not layer.isValid():
+    raise Exception("Layer not valid")
+metric_crs = QgsCoordinateReferenceSystem("{crs}")
+wgs84 = QgsCoordinateReferenceSystem("EPSG:4326")
+to_metric = QgsCoordinateTransform(layer.crs(), metric_crs, QgsProject.instance())
+extent = to_metric.transformBoundingBox(layer.extent())
+extent.grow({buffer})
+to_wgs84 = QgsCoordinateTransform(metric_crs, wgs84, QgsProject.instance())
+extent = to_wgs84.transformBoundingBox(extent)
+layer_extent = (extent.xMinimum(), extent.yMinimum(), extent.xMaximum(), extent.yMaximum())
+"#,
+        layer_path = layer_path,
+        buffer = buffer,
+        crs = crs
+    );
+
+    with_project(|py| -> PyResult<(f64, f64, f64, f64)> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        match locals.get_item("layer_extent") {
+            Ok(Some(value)) => value.extract(),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "Extent not computed",
+            )),
+        }
+    })
+}
+
+pub fn setup_basic_veg_layer(
+    project_name: &str,
+    layer_name: &str,
+    palette: &Palette,
+) -> PyResult<String> {
+    let rules = LayerManifest::vegetation_symbology()
+        .resolve(palette)
+        .map_err(PyValueError::new_err)?;
+    apply_rule_symbology(project_name, layer_name, &rules)?;
+    Ok(format!(
+        "Layer {} in project {} categorized by ESSENCE and processed successfully",
+        layer_name, project_name
+    ))
+}
+
+/// Replace the renderer of a layer by a rule-based renderer, polygons drawn without outline.
+/// The rules are expected to be exclusive, see `Symbology::resolve`.
+pub fn apply_rule_symbology(
+    project_name: &str,
+    layer_name: &str,
+    rules: &[ResolvedRule],
+) -> PyResult<String> {
+    let rules_json =
+        serde_json::to_string(rules).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let code = format!(
+        r#"
+import json
+from qgis.core import QgsProject, QgsRuleBasedRenderer, QgsSymbol, QgsFillSymbol, QgsWkbTypes
+from qgis.PyQt.QtGui import QColor
+
+project = QgsProject.instance()
+project.read("{project_name}")
+
+layer = project.mapLayersByName("{layer_name}")
+if not layer:
+    raise Exception("Layer not found")
+layer = layer[0]
+
+root_rule = QgsRuleBasedRenderer.Rule(None)
+for rule in json.loads({rules_json:?}):
+    if layer.geometryType() == QgsWkbTypes.PolygonGeometry:
+        symbol = QgsFillSymbol.createSimple({{'outline_style': 'no'}})
+    else:
+        symbol = QgsSymbol.defaultSymbol(layer.geometryType())
+    symbol.setColor(QColor(*rule["color"]))
+    is_else = rule["filter"] is None
+    root_rule.appendChild(QgsRuleBasedRenderer.Rule(symbol, 0, 0, rule["filter"] or "", rule["label"], "", is_else))
+
+layer.setRenderer(QgsRuleBasedRenderer(root_rule))
+layer.triggerRepaint()
+project.write(project.fileName())
+"#,
+        project_name = project_name,
+        layer_name = layer_name,
+        rules_json = rules_json
+    );
+
+    with_project(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!(
+            "{} rules applied to layer {} in project {}",
+            rules.len(),
+            layer_name,
+            project_name
+        ))
+    })
+}
+
+/// Style a layer with a single symbol chosen from its geometry: a fill without outline for
+/// polygons, a line for lines and a marker for points.
+/// The style file is loaded first if it exists and fits the geometry of the layer.
+pub fn setup_basic_topo_layer(
+    project_name: &str,
+    layer_name: &str,
+    style_path: &str,
+    symbol: &SimpleSymbol,
+) -> PyResult<String> {
+    let width = match symbol.width {
+        Some(width) => format!("{:?}", width),
+        None => "None".to_string(),
+    };
+    let code = format!(
+        r#"
+import os
+from qgis.core import (QgsProject, QgsSingleSymbolRenderer, QgsSymbol, QgsFillSymbol,
+                       QgsLineSymbol, QgsMarkerSymbol, QgsRenderContext, QgsWkbTypes)
 from qgis.PyQt.QtGui import QColor
 
 project = QgsProject.instance()
@@ -198,36 +753,41 @@ if not layer:
     raise Exception(f"Layer '{layer_name}' not found in the project '{project_name}'")
 layer = layer[0]
 
-root = project.layerTreeRoot()
-combustion_group = root.findGroup('combustion')
-bdtopo_group = combustion_group.findGroup('BDTOPO')
+SYMBOL_TYPES = {{
+    QgsWkbTypes.PolygonGeometry: QgsSymbol.Fill,
+    QgsWkbTypes.LineGeometry: QgsSymbol.Line,
+    QgsWkbTypes.PointGeometry: QgsSymbol.Marker,
+}}
+geometry = layer.geometryType()
+width = {width}
 
-if not bdtopo_group:
-    raise Exception("Group 'BDTOPO' not found")
+def fits_geometry(renderer):
+    return all(symbol.type() == SYMBOL_TYPES.get(geometry) for symbol in renderer.symbols(QgsRenderContext()))
 
-layer_node = root.findLayer(layer.id())
-if layer_node:
-    current_parent = layer_node.parent()
-    current_parent.removeChildNode(layer_node)
-    bdtopo_group.addLayer(layer)
 try:
-    if layer.name() in ["COURS_D_EAU", "TRONCON_DE_ROUTE", "TRONCON_DE_VOIE_FERREE"]:
-        symbol = QgsSymbol.defaultSymbol(layer.geometryType())
-        symbol.deleteSymbolLayer(0)
-        symbol_layer = QgsSimpleLineSymbolLayer.create({{'color': '0,0,0,255', 'width': '0,46000'}})
-        if symbol_layer:
-            symbol.appendSymbolLayer(symbol_layer)
-        else:
-            raise Exception("Failed to create line symbol layer")
-    else:
-        symbol = QgsSymbol.defaultSymbol(layer.geometryType())
-        symbol.deleteSymbolLayer(0)
-        symbol_layer = QgsSimpleFillSymbolLayer.create({{'color': '0,0,0,255', 'outline_style': 'no'}})
-        if symbol_layer:
-            symbol.appendSymbolLayer(symbol_layer)
+    styled = False
+    if os.path.exists({style_path:?}):
+        message, ok = layer.loadNamedStyle({style_path:?})
+        if not ok:
+            raise Exception(message)
+        styled = fits_geometry(layer.renderer())
+    if not styled:
+        if geometry == QgsWkbTypes.PolygonGeometry:
+            symbol = QgsFillSymbol.createSimple({{'outline_style': 'no'}})
+        elif geometry == QgsWkbTypes.LineGeometry:
+            symbol = QgsLineSymbol.createSimple({{'line_width': '{line_width:?}', 'line_width_unit': 'MM'}})
+        elif geometry == QgsWkbTypes.PointGeometry:
+            symbol = QgsMarkerSymbol.createSimple({{'size': '{marker_size:?}', 'size_unit': 'MM', 'outline_style': 'no'}})
         else:
-            raise Exception("Failed to create fill symbol layer")
-    layer.renderer().setSymbol(symbol)
+            raise Exception("Unsupported geometry type")
+        layer.setRenderer(QgsSingleSymbolRenderer(symbol))
+    if isinstance(layer.renderer(), QgsSingleSymbolRenderer):
+        symbol = layer.renderer().symbol()
+        symbol.setColor(QColor({red}, {green}, {blue}))
+        if width is not None and symbol.type() == QgsSymbol.Line:
+            symbol.setWidth(width)
+        elif width is not None and symbol.type() == QgsSymbol.Marker:
+            symbol.setSize(width)
     layer.triggerRepaint()
     print(f"Styling applied to the layer '{layer_name}'.")
 except Exception as e:
@@ -236,17 +796,161 @@ project.write(project.fileName())
 "#,
         project_name = project_name,
         layer_name = layer_name,
+        style_path = style_path,
+        width = width,
+        line_width = DEFAULT_LINE_WIDTH,
+        marker_size = DEFAULT_MARKER_SIZE,
+        red = symbol.color.0,
+        green = symbol.color.1,
+        blue = symbol.color.2,
     );
 
-    Python::with_gil(|py| {
+    with_project(|py| {
         run_python_code(py, &code)?;
         Ok(format!(
-            "Layer '{}' in project '{}' categorized and moved to group 'combustion/BDTOPO' successfully.",
+            "Layer '{}' in project '{}' styled successfully.",
             layer_name, project_name
         ))
     })
 }
 
+/// Set the draw order of a layer and sort the layer tree by draw order.
+///
+/// The layers with the highest order are put on top, and so drawn above the others.
+/// A group is placed by the highest order of its layers. The layers and groups of the
+/// same order keep their position.
+#[pyfunction]
+pub fn set_layer_draw_order(
+    project_name: &str,
+    layer_name: &str,
+    draw_order: i32,
+) -> PyResult<String> {
+    let code = format!(
+        r#"
+from qgis.core import QgsProject, QgsLayerTree
+project = QgsProject.instance()
+project.read("{project_name}")
+layer = project.mapLayersByName("{layer_name}")
+if not layer:
+    raise Exception("Layer not found")
+layer[0].setCustomProperty("{order_property}", {draw_order})
+
+def draw_order(node):
+    if QgsLayerTree.isLayer(node):
+        layer = node.layer()
+        return int(layer.customProperty("{order_property}", 0)) if layer else 0
+    return max((draw_order(child) for child in node.children()), default=0)
+
+def sort_group(group):
+    for child in group.children():
+        if QgsLayerTree.isGroup(child):
+            sort_group(child)
+    children = group.children()
+    ordered = sorted(children, key=draw_order, reverse=True)
+    if any(a is not b for a, b in zip(children, ordered)):
+        # Insert the sorted copies before removing the nodes, so the layers never
+        # leave the tree and stay in the project.
+        group.insertChildNodes(0, [child.clone() for child in ordered])
+        for child in children:
+            group.removeChildNode(child)
+
+sort_group(project.layerTreeRoot())
+project.write(project.fileName())
+"#,
+        project_name = project_name,
+        layer_name = layer_name,
+        order_property = DRAW_ORDER_PROPERTY,
+        draw_order = draw_order
+    );
+
+    with_project(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!(
+            "Draw order of layer {} set to {} in project {}",
+            layer_name, draw_order, project_name
+        ))
+    })
+}
+
+pub fn load_layer_style(
+    project_name: &str,
+    layer_name: &str,
+    style_path: &str,
+) -> PyResult<String> {
+    let load_function = match StyleFormat::from_path(style_path)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?
+    {
+        StyleFormat::Qml => "loadNamedStyle",
+        StyleFormat::Sld => "loadSldStyle",
+    };
+    let code = format!(
+        r#"
+from qgis.core import QgsProject
+project = QgsProject.instance()
+project.read("{project_name}")
+layer = project.mapLayersByName("{layer_name}")
+if not layer:
+    raise Exception("Layer not found")
+layer = layer[0]
+message, ok = layer.{load_function}({style_path:?})
+if not ok:
+    raise Exception("Failed to load style: " + message)
+layer.triggerRepaint()
+project.write(project.fileName())
+"#,
+        project_name = project_name,
+        layer_name = layer_name,
+        load_function = load_function,
+        style_path = style_path
+    );
+
+    with_project(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!(
+            "Style {} loaded onto layer {} in project {}",
+            style_path, layer_name, project_name
+        ))
+    })
+}
+
+pub fn save_layer_style(
+    project_name: &str,
+    layer_name: &str,
+    style_path: &str,
+) -> PyResult<String> {
+    let save_function = match StyleFormat::from_path(style_path)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?
+    {
+        StyleFormat::Qml => "saveNamedStyle",
+        StyleFormat::Sld => "saveSldStyle",
+    };
+    let code = format!(
+        r#"
+from qgis.core import QgsProject
+project = QgsProject.instance()
+project.read("{project_name}")
+layer = project.mapLayersByName("{layer_name}")
+if not layer:
+    raise Exception("Layer not found")
+message, ok = layer[0].{save_function}({style_path:?})
+if not ok:
+    raise Exception("Failed to save style: " + message)
+"#,
+        project_name = project_name,
+        layer_name = layer_name,
+        save_function = save_function,
+        style_path = style_path
+    );
+
+    with_project(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!(
+            "Style of layer {} in project {} saved to {}",
+            layer_name, project_name, style_path
+        ))
+    })
+}
+
 #[pyfunction]
 pub fn get_layer_fields_by_category(
     project_name: &str,
@@ -273,7 +977,7 @@ unique_values
         category = category
     );
 
-    Python::with_gil(|py| -> PyResult<Vec<String>> {
+    with_project(|py| -> PyResult<Vec<String>> {
         let locals = PyDict::new_bound(py);
         py.run_bound(&code, None, Some(&locals))?;
         let result: Vec<String> = match locals.get_item("unique_values") {
@@ -293,109 +997,872 @@ pub fn edit_layer_field_color(
     field_name: &str,
     color: &str,
 ) -> PyResult<String> {
+    update_layer_categories(
+        project_name,
+        layer_name,
+        category,
+        &[CategoryUpdate {
+            value: field_name.to_string(),
+            color: Some(color.to_string()),
+            ..Default::default()
+        }],
+    )?;
+    Ok(format!(
+        "Field color for {} in layer {} in project {} updated to {} for category {}",
+        field_name, layer_name, project_name, color, category
+    ))
+}
+
+/// Python helpers prepended to the scripts that read or edit the renderer of a layer.
+const RENDERER_HELPERS: &str = r#"
+import json
+from qgis.core import QgsProject, QgsSymbol, QgsSymbolLayerUtils, QgsLineSymbol, QgsMarkerSymbol, QgsWkbTypes
+from qgis.core import QgsCategorizedSymbolRenderer, QgsRendererCategory
+
+def symbol_type(layer):
+    if layer.geometryType() == QgsWkbTypes.LineGeometry:
+        return "line"
+    if layer.geometryType() == QgsWkbTypes.PointGeometry:
+        return "marker"
+    return "fill"
+
+def category_index(renderer, value):
+    for index, category in enumerate(renderer.categories()):
+        if str(category.value()) == value:
+            return index
+    return -1
+"#;
+
+pub fn get_layer_renderer(project_name: &str, layer_name: &str) -> PyResult<LayerRenderer> {
     let code = format!(
-        r#"
-from qgis.core import QgsProject, QgsCategorizedSymbolRenderer, QgsRendererCategory, QgsFillSymbol
-from qgis.PyQt.QtGui import QColor
+        r#"{helpers}
 project = QgsProject.instance()
 project.read("{project_name}")
 layer = project.mapLayersByName("{layer_name}")
 if not layer:
     raise Exception("Layer not found")
 layer = layer[0]
-category_field_index = layer.fields().indexFromName("{category}")
-if category_field_index == -1:
-    raise Exception("Category field not found")
-unique_values = layer.uniqueValues(category_field_index)
+
+renderer = layer.renderer()
+field = None
 categories = []
-for value in unique_values:
-    if str(value) == "{field_name}":
-        symbol = QgsFillSymbol.createSimple({{'color': '{color}', 'outline_style': 'no'}})
-    else:
-        symbol = QgsFillSymbol.createSimple({{'color': '50,200,80,255', 'outline_style': 'no'}})
-    category = QgsRendererCategory(value, symbol, str(value))
-    categories.append(category)
+if isinstance(renderer, QgsCategorizedSymbolRenderer):
+    field = renderer.classAttribute()
+    for category in renderer.categories():
+        symbol = category.symbol()
+        categories.append({{
+            "value": "" if category.value() is None else str(category.value()),
+            "label": category.label(),
+            "color": QgsSymbolLayerUtils.encodeColor(symbol.color()),
+            "visible": category.renderState(),
+            "width": symbol.width() if isinstance(symbol, QgsLineSymbol) else None,
+            "size": symbol.size() if isinstance(symbol, QgsMarkerSymbol) else None,
+        }})
 
-renderer = QgsCategorizedSymbolRenderer('{category}', categories)
-layer.setRenderer(renderer)
+renderer_json = json.dumps({{
+    "kind": renderer.type(),
+    "field": field,
+    "symbol_type": symbol_type(layer),
+    "categories": categories,
+}})
+"#,
+        helpers = RENDERER_HELPERS,
+        project_name = project_name,
+        layer_name = layer_name
+    );
+
+    with_project(|py| -> PyResult<LayerRenderer> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        let renderer_json: String = match locals.get_item("renderer_json") {
+            Ok(Some(value)) => value.extract()?,
+            _ => return Err(PyValueError::new_err("Renderer not read")),
+        };
+        serde_json::from_str(&renderer_json).map_err(|e| PyValueError::new_err(e.to_string()))
+    })
+}
+
+/// Update categories of the categorized renderer of a layer, the other categories untouched.
+///
+/// If the layer is not categorized by `field` yet, a categorized renderer is first created
+/// with a default symbol for every value of the field. An update for a value without
+/// category adds the category.
+pub fn update_layer_categories(
+    project_name: &str,
+    layer_name: &str,
+    field: &str,
+    updates: &[CategoryUpdate],
+) -> PyResult<String> {
+    let updates_json =
+        serde_json::to_string(updates).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let code = format!(
+        r#"{helpers}
+project = QgsProject.instance()
+project.read("{project_name}")
+layer = project.mapLayersByName("{layer_name}")
+if not layer:
+    raise Exception("Layer not found")
+layer = layer[0]
+
+renderer = layer.renderer()
+is_new_renderer = not isinstance(renderer, QgsCategorizedSymbolRenderer) or renderer.classAttribute() != {field:?}
+if is_new_renderer:
+    field_index = layer.fields().indexFromName({field:?})
+    if field_index == -1:
+        raise Exception("Category field not found")
+    categories = [
+        QgsRendererCategory(value, QgsSymbol.defaultSymbol(layer.geometryType()), str(value))
+        for value in sorted(layer.uniqueValues(field_index), key=str)
+    ]
+    renderer = QgsCategorizedSymbolRenderer({field:?}, categories)
+
+for update in json.loads({updates_json:?}):
+    index = category_index(renderer, update["value"])
+    if index == -1:
+        renderer.addCategory(QgsRendererCategory(update["value"], QgsSymbol.defaultSymbol(layer.geometryType()), update["value"]))
+        index = len(renderer.categories()) - 1
+    symbol = renderer.categories()[index].symbol().clone()
+    if update["color"] is not None:
+        symbol.setColor(QgsSymbolLayerUtils.decodeColor(update["color"]))
+    if update["width"] is not None and isinstance(symbol, QgsLineSymbol):
+        symbol.setWidth(update["width"])
+    if update["size"] is not None and isinstance(symbol, QgsMarkerSymbol):
+        symbol.setSize(update["size"])
+    renderer.updateCategorySymbol(index, symbol)
+    if update["visible"] is not None:
+        renderer.updateCategoryRenderState(index, update["visible"])
+    if update["label"] is not None:
+        renderer.updateCategoryLabel(index, update["label"])
+
+if is_new_renderer:
+    layer.setRenderer(renderer)
 layer.triggerRepaint()
 project.write(project.fileName())
 "#,
+        helpers = RENDERER_HELPERS,
         project_name = project_name,
         layer_name = layer_name,
-        field_name = field_name,
-        color = color,
-        category = category
+        field = field,
+        updates_json = updates_json
     );
 
-    Python::with_gil(|py| {
+    with_project(|py| {
         run_python_code(py, &code)?;
         Ok(format!(
-            "Field color for {} in layer {} in project {} updated to {} for category {}",
-            field_name, layer_name, project_name, color, category
+            "{} categories of layer {} in project {} updated",
+            updates.len(),
+            layer_name,
+            project_name
         ))
     })
 }
 
-// TODO : FIX THIS
-#[pyfunction]
-pub fn export_map_to_jpg(
-    project_name: &str,
-    xmin: f64,
-    ymin: f64,
-    xmax: f64,
-    ymax: f64,
-    output_image_name: &str,
-) -> PyResult<String> {
-    let zoom = 25000.0;
-    let dpi = 63.5;
+/// Render layers of a project into an image of exactly the size given by the extent and
+/// the resolution of the export, see `MapExport::pixel_size`.
+/// # Parameters
+/// - `project_name`: A string slice that holds the path of the project file.
+/// - `export`: The extent, resolution, layers, format and output path of the image.
+/// # Returns
+/// - The written image and the area it covers, or the reason of the failure.
+pub fn export_map(project_name: &str, export: &MapExport) -> Result<RenderedMap, ExportError> {
+    let (width, height) = export.pixel_size()?;
+    let extent = export.pixel_extent()?;
+    if let Some(folder) = Path::new(&export.output_path).parent() {
+        fs::create_dir_all(folder).map_err(|e| ExportError::Qgis(e.to_string()))?;
+    }
+    let image_path = match export.format {
+        ImageFormat::GeoTiff => format!("{}.render.png", export.output_path),
+        _ => export.output_path.clone(),
+    };
+
+    let code = format!(
+        r#"
+from xml.sax.saxutils import escape
+from qgis.core import (QgsProject, QgsMapSettings, QgsMapRendererSequentialJob,
+                       QgsCoordinateReferenceSystem, QgsRectangle)
+from qgis.PyQt.QtCore import QSize
+from qgis.PyQt.QtGui import QColor
+
+project = QgsProject.instance()
+if not project.read({project_name:?}):
+    raise Exception("Project {project_name} could not be read")
+names = {names:?}
+{select_layers}
 
-    let project_file_path = format!("resources/QGIS/{}.qgz", project_name);
-    let output_image_path = format!("resources/QGIS/{}.jpg", output_image_name);
+if not missing:
+    settings = QgsMapSettings()
+    settings.setDestinationCrs(QgsCoordinateReferenceSystem({crs:?}))
+    settings.setLayers(layers)
+    settings.setOutputSize(QSize({width}, {height}))
+    settings.setOutputDpi({dpi:?})
+    settings.setExtent(QgsRectangle({xmin:?}, {ymin:?}, {xmax:?}, {ymax:?}))
+    settings.setBackgroundColor(QColor(255, 255, 255))
+    settings.setFlag(QgsMapSettings.Antialiasing, {antialiasing})
+
+    job = QgsMapRendererSequentialJob(settings)
+    job.start()
+    job.waitForFinished()
+    image = job.renderedImage()
+    if image.width() != {width} or image.height() != {height}:
+        raise Exception(f"Rendered {{image.width()}}x{{image.height()}} pixels instead of {width}x{height}")
+    if not image.save({image_path:?}, {format:?}):
+        raise Exception("Image could not be written to {image_path}")
+    if {write_aux}:
+        wkt = settings.destinationCrs().toWkt(QgsCoordinateReferenceSystem.WKT_PREFERRED_GDAL)
+        with open({aux_path:?}, "w") as aux_file:
+            aux_file.write("<PAMDataset>\n  <SRS>" + escape(wkt) + "</SRS>\n</PAMDataset>\n")
+"#,
+        project_name = project_name,
+        names = export.layers,
+        select_layers = SELECT_LAYERS,
+        crs = export.crs,
+        width = width,
+        height = height,
+        dpi = export.dpi,
+        xmin = extent.xmin,
+        ymin = extent.ymin,
+        xmax = extent.xmax,
+        ymax = extent.ymax,
+        antialiasing = python_bool(export.antialiasing()),
+        image_path = image_path,
+        format = export.format.qt_format(),
+        write_aux = python_bool(export.world_file && export.format != ImageFormat::GeoTiff),
+        aux_path = format!("{}.aux.xml", export.output_path),
+    );
+
+    let missing = with_project(|py| -> PyResult<Vec<String>> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        match locals.get_item("missing") {
+            Ok(Some(value)) => value.extract(),
+            _ => Ok(Vec::new()),
+        }
+    })
+    .map_err(|e| ExportError::Qgis(e.to_string()))?;
+    if !missing.is_empty() {
+        return Err(ExportError::LayersNotFound(missing));
+    }
+
+    let rendered = RenderedMap {
+        path: export.output_path.clone(),
+        width,
+        height,
+        extent,
+        crs: export.crs.clone(),
+    };
+    if export.format == ImageFormat::GeoTiff {
+        let result = write_geotiff(&image_path, &rendered);
+        let _ = fs::remove_file(&image_path);
+        result.map_err(|e| ExportError::Qgis(e.to_string()))?;
+    } else if export.world_file {
+        fs::write(
+            world_file_path(&export.output_path, export.format),
+            rendered.world_file(),
+        )
+        .map_err(|e| ExportError::Qgis(e.to_string()))?;
+    }
+    Ok(rendered)
+}
+
+/// Export a printable layout of a project: a title, the map, a legend listing the
+/// categories of its layers, a scale bar, a north arrow and a source attribution.
+/// # Parameters
+/// - `project_name`: A string slice that holds the path of the project file.
+/// - `export`: The title, extent, layers, page and output of the layout.
+/// - `attribution`: A string slice that holds the source text printed below the map.
+/// # Returns
+/// - The path of the written PDF or PNG, or the reason of the failure.
+pub fn export_layout(
+    project_name: &str,
+    export: &LayoutExport,
+    attribution: &str,
+) -> Result<String, ExportError> {
+    if !(export.extent.width() > 0.0 && export.extent.height() > 0.0) {
+        return Err(ExportError::InvalidExtent);
+    }
+    if !(export.dpi > 0.0 && export.dpi.is_finite()) {
+        return Err(ExportError::InvalidResolution);
+    }
+    if let Some(folder) = Path::new(&export.output_path).parent() {
+        fs::create_dir_all(folder).map_err(|e| ExportError::Qgis(e.to_string()))?;
+    }
+    let (page_width, page_height) = export.page_dimensions();
+    let frames = export.frames();
+    let frame = |frame: Frame| (frame.x, frame.y, frame.width, frame.height);
 
     let code = format!(
         r#"
-from qgis.core import QgsProject, QgsPrintLayout, QgsLayoutItemMap, QgsLayoutExporter, QgsCoordinateReferenceSystem, QgsRectangle
-from qgis.PyQt.QtCore import QSize, QRectF
-try:
-    project = QgsProject.instance()
-    project.read("{project_file_path}")
+from qgis.core import (QgsProject, QgsPrintLayout, QgsLayoutSize, QgsLayoutPoint, QgsUnitTypes,
+                       QgsLayoutItemMap, QgsLayoutItemLabel, QgsLayoutItemLegend,
+                       QgsLayoutItemScaleBar, QgsLayoutItemPicture, QgsLayoutExporter,
+                       QgsCoordinateReferenceSystem, QgsRectangle, QgsSymbolLayerUtils, QgsTextFormat)
+from qgis.PyQt.QtCore import Qt
+from qgis.PyQt.QtGui import QFont
+
+project = QgsProject.instance()
+if not project.read({project_name:?}):
+    raise Exception("Project {project_name} could not be read")
+names = {names:?}
+{select_layers}
+
+def place(item, frame):
+    layout.addLayoutItem(item)
+    x, y, width, height = frame
+    item.attemptMove(QgsLayoutPoint(x, y, QgsUnitTypes.LayoutMillimeters))
+    item.attemptResize(QgsLayoutSize(width, height, QgsUnitTypes.LayoutMillimeters))
+
+def add_label(text, size, bold, frame, alignment):
+    label = QgsLayoutItemLabel(layout)
+    label.setText(text)
+    font = QFont()
+    font.setPointSizeF(size)
+    font.setBold(bold)
+    if hasattr(label, "setTextFormat"):
+        text_format = QgsTextFormat.fromQFont(font)
+        text_format.setSize(size)
+        label.setTextFormat(text_format)
+    else:
+        label.setFont(font)
+    label.setHAlign(alignment)
+    label.setVAlign(Qt.AlignVCenter)
+    place(label, frame)
+
+if not missing:
     layout = QgsPrintLayout(project)
     layout.initializeDefaults()
+    layout.setName({title:?})
+    layout.pageCollection().page(0).setPageSize(
+        QgsLayoutSize({page_width:?}, {page_height:?}, QgsUnitTypes.LayoutMillimeters))
+
+    add_label({title:?}, 20, True, {title_frame:?}, Qt.AlignLeft)
+
     map_item = QgsLayoutItemMap(layout)
-    layout.addLayoutItem(map_item)
-    map_rect = QgsRectangle({xmin}, {ymin}, {xmax}, {ymax})
-    map_item.setExtent(map_rect)
-    map_item.setCrs(QgsCoordinateReferenceSystem('EPSG:2154'))
-    map_item.setScale({zoom})
-    map_item.setFixedSize(QSize(400, 400))
-    map_item.attemptMove(QRectF(5, 5, 200, 150))
+    place(map_item, {map_frame:?})
+    map_item.setCrs(QgsCoordinateReferenceSystem({crs:?}))
+    map_item.setLayers(layers)
+    map_item.setKeepLayerSet(True)
+    map_item.zoomToExtent(QgsRectangle({xmin:?}, {ymin:?}, {xmax:?}, {ymax:?}))
+    map_item.setFrameEnabled(True)
+
+    legend = QgsLayoutItemLegend(layout)
+    legend.setTitle({legend_title:?})
+    legend.setLinkedMap(map_item)
+    legend.setAutoUpdateModel(False)
+    legend_root = legend.model().rootGroup()
+    legend_root.removeAllChildren()
+    for layer in layers:
+        legend_root.addLayer(layer)
+    legend.setResizeToContents(False)
+    place(legend, {legend_frame:?})
+    legend.updateLegend()
+
+    north_arrow = QgsLayoutItemPicture(layout)
+    north_arrow.setPicturePath(QgsSymbolLayerUtils.svgSymbolNameToPath(
+        "arrows/NorthArrow_02.svg", project.pathResolver()))
+    north_arrow.setLinkedMap(map_item)
+    north_arrow.setNorthMode(QgsLayoutItemPicture.GridNorth)
+    place(north_arrow, {north_arrow_frame:?})
+
+    scale_bar = QgsLayoutItemScaleBar(layout)
+    scale_bar.setStyle("Single Box")
+    scale_bar.setLinkedMap(map_item)
+    scale_bar.applyDefaultSize()
+    layout.addLayoutItem(scale_bar)
+    scale_x, scale_y, _, _ = {scale_bar_frame:?}
+    scale_bar.attemptMove(QgsLayoutPoint(scale_x, scale_y, QgsUnitTypes.LayoutMillimeters))
+
+    add_label({attribution:?}, 8, False, {attribution_frame:?}, Qt.AlignRight)
+
     exporter = QgsLayoutExporter(layout)
-    export_settings = QgsLayoutExporter.ImageExportSettings()
-    export_settings.dpi = {dpi}
-    result = exporter.exportToImage("{output_image_path}", export_settings)
-    if result == QgsLayoutExporter.Success:
-        print("Map exported successfully to " + "{output_image_path}")
+    if {pdf}:
+        settings = QgsLayoutExporter.PdfExportSettings()
+        settings.dpi = {dpi:?}
+        result = exporter.exportToPdf({output_path:?}, settings)
     else:
-        print("Failed to export map")
-except Exception as e:
-    print("An error occurred: " + str(e))
+        settings = QgsLayoutExporter.ImageExportSettings()
+        settings.dpi = {dpi:?}
+        result = exporter.exportToImage({output_path:?}, settings)
+    if result != QgsLayoutExporter.Success:
+        raise Exception("Layout could not be written to " + {output_path:?} + f" (error {{result}})")
 "#,
-        project_file_path = project_file_path,
-        xmin = xmin,
-        ymin = ymin,
-        xmax = xmax,
-        ymax = ymax,
-        dpi = dpi,
-        zoom = zoom,
-        output_image_path = output_image_path,
+        project_name = project_name,
+        names = export.layers,
+        select_layers = SELECT_LAYERS,
+        title = export.title,
+        legend_title = export.legend_title,
+        page_width = page_width,
+        page_height = page_height,
+        title_frame = frame(frames.title),
+        map_frame = frame(frames.map),
+        legend_frame = frame(frames.legend),
+        north_arrow_frame = frame(frames.north_arrow),
+        scale_bar_frame = frame(frames.scale_bar),
+        attribution_frame = frame(frames.attribution),
+        crs = export.crs,
+        xmin = export.extent.xmin,
+        ymin = export.extent.ymin,
+        xmax = export.extent.xmax,
+        ymax = export.extent.ymax,
+        attribution = attribution,
+        pdf = python_bool(export.format == LayoutFormat::Pdf),
+        dpi = export.dpi,
+        output_path = export.output_path,
+    );
+
+    let missing = with_project(|py| -> PyResult<Vec<String>> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        match locals.get_item("missing") {
+            Ok(Some(value)) => value.extract(),
+            _ => Ok(Vec::new()),
+        }
+    })
+    .map_err(|e| ExportError::Qgis(e.to_string()))?;
+    if !missing.is_empty() {
+        return Err(ExportError::LayersNotFound(missing));
+    }
+    Ok(export.output_path.clone())
+}
+
+/// Convert a rendered image into a GeoTIFF holding the CRS and geotransform of the map.
+///
+/// The pixels are written as indices on a palette of the colours of the image, DEFLATE
+/// compressed so the colours are kept exactly. An image of more than 256 colours, like
+/// an antialiased render, is refused.
+/// # Parameters
+/// - `image_path`: A string slice that holds the path of the rendered image.
+/// - `map`: The path, size, extent and CRS of the GeoTIFF.
+/// # Returns
+/// - PyResult<String>
+pub fn write_geotiff(image_path: &str, map: &RenderedMap) -> PyResult<String> {
+    let code = format!(
+        r#"
+import numpy as np
+from osgeo import gdal, osr
+gdal.UseExceptions()
+
+source = gdal.Open({image_path:?})
+bands = source.ReadAsArray()
+source = None
+red, green, blue = (bands[i].astype(np.uint32) for i in range(3))
+packed = (red << 16) | (green << 8) | blue
+colors, indices = np.unique(packed, return_inverse=True)
+
+if len(colors) > 256:
+    raise ValueError(f"The map holds {{len(colors)}} colours, a GeoTIFF palette holds at most 256: render it without antialiasing")
+
+driver = gdal.GetDriverByName("GTiff")
+dataset = driver.Create({output_path:?}, {width}, {height}, 1, gdal.GDT_Byte, ["COMPRESS=DEFLATE"])
+band = dataset.GetRasterBand(1)
+band.WriteArray(indices.reshape({height}, {width}).astype(np.uint8))
+table = gdal.ColorTable()
+for index, color in enumerate(colors):
+    color = int(color)
+    table.SetColorEntry(index, ((color >> 16) & 255, (color >> 8) & 255, color & 255, 255))
+band.SetRasterColorTable(table)
+band.SetRasterColorInterpretation(gdal.GCI_PaletteIndex)
+
+dataset.SetGeoTransform({geotransform:?})
+srs = osr.SpatialReference()
+srs.SetFromUserInput({crs:?})
+dataset.SetProjection(srs.ExportToWkt())
+dataset.FlushCache()
+dataset = None
+"#,
+        image_path = image_path,
+        output_path = map.path,
+        width = map.width,
+        height = map.height,
+        geotransform = map.geotransform(),
+        crs = map.crs,
+    );
+
+    Python::with_gil(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!("GeoTIFF written to {}", map.path))
+    })
+}
+
+/// Write tiles into one GeoTIFF, one window at a time.
+///
+/// Tiles indexed on a palette give a mosaic indexed on the colours of their palettes,
+/// the first tile keeping its indices, and the mosaic is refused if the palettes hold
+/// more than 256 colours together. Other tiles give an RGB mosaic.
+/// # Parameters
+/// - `mosaic`: The path, size, extent and CRS of the GeoTIFF.
+/// - `windows`: The parts of the tiles copied, in copy order.
+/// # Returns
+/// - PyResult<String>
+pub fn write_mosaic(mosaic: &RenderedMap, windows: &[TileWindow]) -> PyResult<String> {
+    let windows_json =
+        serde_json::to_string(windows).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let code = format!(
+        r#"
+import json
+import numpy as np
+from osgeo import gdal, osr
+gdal.UseExceptions()
+
+windows = json.loads({windows_json:?})
+tables = {{}}
+for window in windows:
+    if window["path"] not in tables:
+        tile = gdal.Open(window["path"])
+        table = tile.GetRasterBand(1).GetColorTable()
+        tables[window["path"]] = None if table is None else [table.GetColorEntry(i)[:3] for i in range(table.GetCount())]
+        tile = None
+indexed = all(table is not None for table in tables.values())
+
+colors = []
+lookups = {{}}
+if indexed:
+    for table in tables.values():
+        colors.extend(color for color in table if color not in colors)
+    if len(colors) > 256:
+        raise ValueError(f"The palettes of the tiles hold {{len(colors)}} colours together, a GeoTIFF palette holds at most 256")
+    for path, table in tables.items():
+        lookups[path] = np.array([colors.index(color) for color in table], dtype=np.uint8)
+
+driver = gdal.GetDriverByName("GTiff")
+options = ["COMPRESS=DEFLATE", "TILED=YES", "BIGTIFF=IF_SAFER"]
+if indexed:
+    dataset = driver.Create({output_path:?}, {width}, {height}, 1, gdal.GDT_Byte, options)
+    table = gdal.ColorTable()
+    for index, color in enumerate(colors):
+        table.SetColorEntry(index, (*color, 255))
+    dataset.GetRasterBand(1).SetRasterColorTable(table)
+    dataset.GetRasterBand(1).SetRasterColorInterpretation(gdal.GCI_PaletteIndex)
+else:
+    dataset = driver.Create({output_path:?}, {width}, {height}, 3, gdal.GDT_Byte, options + ["PHOTOMETRIC=RGB"])
+dataset.SetGeoTransform({geotransform:?})
+srs = osr.SpatialReference()
+srs.SetFromUserInput({crs:?})
+dataset.SetProjection(srs.ExportToWkt())
+
+for window in windows:
+    tile = gdal.Open(window["path"])
+    pixels = tile.ReadAsArray(window["source_x"], window["source_y"], window["width"], window["height"])
+    table = tables[window["path"]]
+    if pixels.ndim == 3 and table is not None:
+        pixels = pixels[0]
+    if indexed:
+        dataset.GetRasterBand(1).WriteArray(lookups[window["path"]][pixels], window["target_x"], window["target_y"])
+    else:
+        if table is not None:
+            pixels = np.moveaxis(np.array(table, dtype=np.uint8)[pixels], -1, 0)
+        elif pixels.ndim == 2:
+            pixels = np.stack([pixels] * 3)
+        for i in range(3):
+            dataset.GetRasterBand(i + 1).WriteArray(pixels[i], window["target_x"], window["target_y"])
+    tile = None
+
+dataset.FlushCache()
+dataset = None
+"#,
+        output_path = mosaic.path,
+        width = mosaic.width,
+        height = mosaic.height,
+        geotransform = mosaic.geotransform(),
+        crs = mosaic.crs,
+        windows_json = windows_json,
     );
 
     Python::with_gil(|py| {
         run_python_code(py, &code)?;
         Ok(format!(
-            "Map exported successfully to {}",
-            output_image_path
+            "{} tiles assembled into {}",
+            windows.len(),
+            mosaic.path
         ))
     })
 }
+
+/// Render the visible layers of a project into a Web Mercator XYZ tile pyramid of PNG tiles.
+/// # Parameters
+/// - `project_name`: A string slice that holds the path of the project file.
+/// - `pyramid`: The zoom range, storage and extent of the pyramid.
+/// - `output_path`: A string slice that holds the folder or MBTiles file of the tiles.
+/// # Returns
+/// - PyResult<String>
+pub fn generate_xyz_tiles(
+    project_name: &str,
+    pyramid: &TilePyramid,
+    output_path: &str,
+) -> PyResult<String> {
+    let extent = match &pyramid.extent {
+        Some(extent) => format!(
+            "QgsRectangle({:?}, {:?}, {:?}, {:?})",
+            extent.xmin, extent.ymin, extent.xmax, extent.ymax
+        ),
+        None => "None".to_string(),
+    };
+    let (algorithm, output_parameter) = match pyramid.storage {
+        TileStorage::Directory => ("native:tilesxyzdirectory", "OUTPUT_DIRECTORY"),
+        TileStorage::MbTiles => ("native:tilesxyzmbtiles", "OUTPUT_FILE"),
+    };
+    let code = format!(
+        r#"{helpers}
+from qgis.core import QgsProject, QgsCoordinateTransform, QgsRectangle
+from qgis.PyQt.QtGui import QColor
+
+project = QgsProject.instance()
+if not project.read({project_name:?}):
+    raise Exception("Project {project_name} could not be read")
+processing_context.setProject(project)
+
+extent = {extent}
+if extent is None:
+    extent = QgsRectangle()
+    extent.setMinimal()
+    for layer in project.mapLayers().values():
+        transform = QgsCoordinateTransform(layer.crs(), project.crs(), project)
+        extent.combineExtentWith(transform.transformBoundingBox(layer.extent()))
+if extent.isEmpty():
+    raise Exception("The project has no extent to render")
+
+run_algorithm({algorithm:?}, {{
+    "EXTENT": "{{}},{{}},{{}},{{}} [{{}}]".format(extent.xMinimum(), extent.xMaximum(),
+        extent.yMinimum(), extent.yMaximum(), project.crs().authid()),
+    "ZOOM_MIN": {min_zoom},
+    "ZOOM_MAX": {max_zoom},
+    "DPI": 96,
+    "BACKGROUND_COLOR": QColor(0, 0, 0, 0),
+    "TILE_FORMAT": 0,
+    "METATILESIZE": 4,
+    "TILE_WIDTH": 256,
+    "TILE_HEIGHT": 256,
+    "TMS_CONVENTION": False,
+    {output_parameter:?}: {output_path:?},
+}})
+"#,
+        helpers = VECTOR_FILE_HELPERS,
+        project_name = project_name,
+        extent = extent,
+        algorithm = algorithm,
+        min_zoom = pyramid.min_zoom,
+        max_zoom = pyramid.max_zoom,
+        output_parameter = output_parameter,
+        output_path = output_path,
+    );
+
+    with_project(|py| run_python_code(py, &code))?;
+    Ok(format!(
+        "Tiles of zoom levels {} to {} written to {}",
+        pyramid.min_zoom, pyramid.max_zoom, output_path
+    ))
+}
+
+/// Get the children of a group of the layer tree.
+/// # Parameters
+/// - `project_name`: A string slice that holds the path of the project file.
+/// - `group_path`: A string slice that holds the group, nested groups separated by '/'.
+/// # Returns
+/// - The paths of the child groups and the names of the child layers, in tree order.
+pub fn get_group_children(project_name: &str, group_path: &str) -> PyResult<Vec<String>> {
+    let code = format!(
+        r#"
+from qgis.core import QgsProject, QgsLayerTree
+project = QgsProject.instance()
+project.read("{project_name}")
+group = project.layerTreeRoot()
+for name in "{group_path}".split("/"):
+    group = group.findGroup(name)
+    if not group:
+        raise Exception("Group '{group_path}' not found")
+
+children = []
+for child in group.children():
+    if QgsLayerTree.isGroup(child):
+        children.append("{group_path}/" + child.name())
+    elif QgsLayerTree.isLayer(child) and child.layer() is not None:
+        children.append(child.layer().name())
+"#,
+        project_name = project_name,
+        group_path = group_path
+    );
+
+    with_project(|py| -> PyResult<Vec<String>> {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        match locals.get_item("children") {
+            Ok(Some(value)) => value.extract(),
+            _ => Err(PyValueError::new_err("Group children not listed")),
+        }
+    })
+}
+
+/// Read the features of a vector file crossing an extent, with the class of their first
+/// matching rule.
+///
+/// The subset string and the rule expressions are evaluated by QGIS, as when the layer is
+/// drawn. The features no rule matches are left out.
+/// # Parameters
+/// - `layer_path`: A string slice that holds the path of the vector file.
+/// - `subset`: The subset string of the layer, every feature if None.
+/// - `rules`: The QGIS expression and the class of each rule, in order, a rule without
+///   expression matching every feature.
+/// - `extent`: The area the features cross, in the coordinates of the file.
+/// # Returns
+/// - The class and the geometry of each feature, in the coordinates of the file.
+pub fn read_classified_features(
+    layer_path: &str,
+    subset: Option<&str>,
+    rules: &[(Option<String>, u8)],
+    extent: &Extent,
+) -> PyResult<Vec<(u8, Geometry)>> {
+    let subset = match subset {
+        Some(subset) => format!("{:?}", subset),
+        None => "None".to_string(),
+    };
+    let rules: Vec<String> = rules
+        .iter()
+        .map(|(expression, class)| match expression {
+            Some(expression) => format!("({:?}, {})", expression, class),
+            None => format!("(None, {})", class),
+        })
+        .collect();
+    let code = format!(
+        r#"
+from qgis.core import (QgsVectorLayer, QgsFeatureRequest, QgsRectangle, QgsExpression,
+                       QgsExpressionContext, QgsExpressionContextUtils, QgsWkbTypes)
+layer = QgsVectorLayer({layer_path:?}, "features", "ogr")
+if not layer.isValid():
+    raise Exception("Layer {layer_path} is not valid")
+subset = {subset}
+if subset is not None and not layer.setSubsetString(subset):
+    raise Exception("Invalid filter expression: " + subset)
+
+context = QgsExpressionContext()
+context.appendScopes(QgsExpressionContextUtils.globalProjectLayerScopes(layer))
+rules = []
+for expression, class_id in [{rules}]:
+    if expression is not None:
+        expression = QgsExpression(expression)
+        if expression.hasParserError():
+            raise Exception("Invalid rule expression: " + expression.parserErrorString())
+        expression.prepare(context)
+    rules.append((expression, class_id))
+
+def geometry_parts(geometry):
+    kind = QgsWkbTypes.geometryType(geometry.wkbType())
+    multi = geometry.isMultipart()
+    if kind == QgsWkbTypes.PolygonGeometry:
+        polygons = geometry.asMultiPolygon() if multi else [geometry.asPolygon()]
+        return "polygon", [[(p.x(), p.y()) for p in ring] for polygon in polygons for ring in polygon]
+    if kind == QgsWkbTypes.LineGeometry:
+        lines = geometry.asMultiPolyline() if multi else [geometry.asPolyline()]
+        return "lines", [[(p.x(), p.y()) for p in line] for line in lines]
+    if kind == QgsWkbTypes.PointGeometry:
+        points = geometry.asMultiPoint() if multi else [geometry.asPoint()]
+        return "points", [[(p.x(), p.y()) for p in points]]
+    return None, []
+
+request = QgsFeatureRequest().setFilterRect(QgsRectangle({xmin:?}, {ymin:?}, {xmax:?}, {ymax:?}))
+features = []
+for feature in layer.getFeatures(request):
+    geometry = feature.geometry()
+    if geometry.isNull() or geometry.isEmpty():
+        continue
+    context.setFeature(feature)
+    class_id = next((class_id for expression, class_id in rules
+                     if expression is None or expression.evaluate(context)), None)
+    kind, parts = geometry_parts(geometry)
+    if class_id is not None and kind is not None:
+        features.append((class_id, kind, parts))
+"#,
+        layer_path = layer_path,
+        subset = subset,
+        rules = rules.join(", "),
+        xmin = extent.xmin,
+        ymin = extent.ymin,
+        xmax = extent.xmax,
+        ymax = extent.ymax,
+    );
+
+    let features = with_project(|py| {
+        let locals = PyDict::new_bound(py);
+        py.run_bound(&code, None, Some(&locals))?;
+        match locals.get_item("features") {
+            Ok(Some(value)) => value.extract::<Vec<(u8, String, Vec<Vec<(f64, f64)>>)>>(),
+            _ => Err(PyValueError::new_err("Features not read")),
+        }
+    })?;
+    Ok(features
+        .into_iter()
+        .map(|(class, kind, mut parts)| {
+            let geometry = match kind.as_str() {
+                "polygon" => Geometry::Polygon(parts),
+                "lines" => Geometry::Lines(parts),
+                _ => Geometry::Points(parts.pop().unwrap_or_default()),
+            };
+            (class, geometry)
+        })
+        .collect())
+}
+
+/// Convert an image of class IDs into a single band GeoTIFF, the classes being written as
+/// its colour table and category names.
+/// # Parameters
+/// - `image_path`: A string slice that holds the path of the grayscale image of the IDs.
+/// - `map`: The path, size, extent and CRS of the GeoTIFF.
+/// - `classes`: The classes of the IDs.
+/// # Returns
+/// - PyResult<String>
+pub fn write_class_geotiff(
+    image_path: &str,
+    map: &RenderedMap,
+    classes: &[MaskClass],
+) -> PyResult<String> {
+    let classes_json =
+        serde_json::to_string(classes).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let code = format!(
+        r#"
+import json
+from osgeo import gdal, osr
+gdal.UseExceptions()
+
+ids = gdal.Open({image_path:?}).GetRasterBand(1).ReadAsArray()
+classes = json.loads({classes_json:?})
+
+driver = gdal.GetDriverByName("GTiff")
+dataset = driver.Create({output_path:?}, {width}, {height}, 1, gdal.GDT_Byte, ["COMPRESS=DEFLATE"])
+band = dataset.GetRasterBand(1)
+band.WriteArray(ids)
+table = gdal.ColorTable()
+names = [""] * (max(c["id"] for c in classes) + 1)
+for c in classes:
+    table.SetColorEntry(c["id"], (*c["color"], 255))
+    names[c["id"]] = c["name"]
+band.SetRasterColorTable(table)
+band.SetRasterColorInterpretation(gdal.GCI_PaletteIndex)
+band.SetCategoryNames(names)
+
+dataset.SetGeoTransform({geotransform:?})
+srs = osr.SpatialReference()
+srs.SetFromUserInput({crs:?})
+dataset.SetProjection(srs.ExportToWkt())
+dataset.FlushCache()
+dataset = None
+"#,
+        image_path = image_path,
+        classes_json = classes_json,
+        output_path = map.path,
+        width = map.width,
+        height = map.height,
+        geotransform = map.geotransform(),
+        crs = map.crs,
+    );
+
+    Python::with_gil(|py| {
+        run_python_code(py, &code)?;
+        Ok(format!("Class raster written to {}", map.path))
+    })
+}
+
+fn python_bool(value: bool) -> &'static str {
+    if value {
+        "True"
+    } else {
+        "False"
+    }
+}
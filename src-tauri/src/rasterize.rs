@@ -0,0 +1,357 @@
+use crate::export::{ExportError, Extent, RenderedMap, MAX_IMAGE_SIZE};
+use crate::manifest::{FilterMode, LayerManifest, LayerStyle};
+use crate::masks::{mask_classes, MaskClass, MaskEncoding};
+use crate::palette::Palette;
+use crate::pipeline::{LayerStage, LayerState, PipelineState};
+use crate::qgis_api_wrapper::{read_classified_features, write_class_geotiff};
+use crate::utils::create_directory_if_not_exists;
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The width of the burnt lines and points without width, in pixels.
+const DEFAULT_LINE_PIXELS: f64 = 1.0;
+/// The most pixels of a class raster, which keeps a class and a priority per pixel.
+pub const MAX_RASTER_PIXELS: u64 = 1 << 28;
+
+/// A geometry read from a layer dataset, in the coordinates of the project CRS.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Geometry {
+    /// The rings of a polygon, inner rings included, filled with the even-odd rule.
+    Polygon(Vec<Vec<(f64, f64)>>),
+    Lines(Vec<Vec<(f64, f64)>>),
+    Points(Vec<(f64, f64)>),
+}
+
+/// A grid of class IDs, anchored on the top left corner of its extent.
+///
+/// Each pixel keeps the priority of its class, so a feature only replaces the classes of
+/// lower or equal priority: overlapping features of the same priority are burnt in order.
+pub struct ClassRaster {
+    pub width: u32,
+    pub height: u32,
+    pub extent: Extent,
+    pub metres_per_pixel: f64,
+    classes: Vec<u8>,
+    priorities: Vec<i32>,
+}
+
+/// What to rasterize from a project and where.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RasterizeExport {
+    /// The area of the raster, in the project CRS.
+    pub extent: Extent,
+    /// The ground size of a pixel, in metres.
+    pub metres_per_pixel: f64,
+    pub output_path: String,
+}
+
+/// A layer of a project burnt into a class raster.
+struct BurnSource {
+    shapefile_path: String,
+    /// The subset string of the layer, if its filter is not applied at extraction.
+    subset: Option<String>,
+    /// The QGIS expression and the class of each rule, the first matching rule winning.
+    rules: Vec<(Option<String>, u8)>,
+    priority: i32,
+    line_pixels: f64,
+}
+
+impl ClassRaster {
+    /// Create a raster of class 0 covering an extent, the partial pixels on the right and
+    /// bottom sides being rounded up.
+    /// # Returns
+    /// - The raster or an error message if the extent or the resolution is empty, or if
+    ///   the raster would be wider or higher than `MAX_IMAGE_SIZE` or larger than
+    ///   `MAX_RASTER_PIXELS`.
+    pub fn new(extent: Extent, metres_per_pixel: f64) -> Result<Self, Box<dyn Error>> {
+        if !(extent.width() > 0.0 && extent.height() > 0.0 && metres_per_pixel > 0.0) {
+            return Err("The extent and the resolution must be strictly positive".into());
+        }
+        let width = (extent.width() / metres_per_pixel - 1e-6).ceil().max(1.0) as u64;
+        let height = (extent.height() / metres_per_pixel - 1e-6).ceil().max(1.0) as u64;
+        if width > MAX_IMAGE_SIZE as u64 || height > MAX_IMAGE_SIZE as u64 {
+            return Err(Box::new(ExportError::TooLarge { width, height }));
+        }
+        if width * height > MAX_RASTER_PIXELS {
+            return Err(format!(
+                "The class raster would be {}x{} pixels, more than {} pixels: use a smaller extent or a coarser resolution",
+                width, height, MAX_RASTER_PIXELS
+            )
+            .into());
+        }
+        let (width, height) = (width as u32, height as u32);
+        let size = width as usize * height as usize;
+        Ok(ClassRaster {
+            width,
+            height,
+            extent: Extent {
+                xmin: extent.xmin,
+                ymin: extent.ymax - height as f64 * metres_per_pixel,
+                xmax: extent.xmin + width as f64 * metres_per_pixel,
+                ymax: extent.ymax,
+            },
+            metres_per_pixel,
+            classes: vec![0; size],
+            priorities: vec![i32::MIN; size],
+        })
+    }
+
+    /// Get the class of a pixel.
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        self.classes[y as usize * self.width as usize + x as usize]
+    }
+
+    /// Get the class IDs, row by row from the top left corner.
+    pub fn as_raw(&self) -> &[u8] {
+        &self.classes
+    }
+
+    /// Convert map coordinates to pixel coordinates, the centre of the top left pixel
+    /// being (0.5, 0.5).
+    fn to_pixel(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        (
+            (x - self.extent.xmin) / self.metres_per_pixel,
+            (self.extent.ymax - y) / self.metres_per_pixel,
+        )
+    }
+
+    fn set(&mut self, x: i64, y: i64, class: u8, priority: i32) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let index = y as usize * self.width as usize + x as usize;
+        if priority >= self.priorities[index] {
+            self.classes[index] = class;
+            self.priorities[index] = priority;
+        }
+    }
+
+    /// Burn a geometry into the raster.
+    /// # Parameters
+    /// - `geometry`: The geometry, in the coordinates of the raster extent.
+    /// - `class`: The class ID burnt.
+    /// - `priority`: The priority of the class over the classes already burnt.
+    /// - `line_pixels`: The width of lines and the size of points, in pixels.
+    pub fn burn(&mut self, geometry: &Geometry, class: u8, priority: i32, line_pixels: f64) {
+        match geometry {
+            Geometry::Polygon(rings) => self.burn_polygon(rings, class, priority),
+            Geometry::Lines(lines) => {
+                for line in lines {
+                    for segment in line.windows(2) {
+                        self.burn_segment(segment[0], segment[1], class, priority, line_pixels);
+                    }
+                }
+            }
+            Geometry::Points(points) => {
+                for &point in points {
+                    self.burn_segment(point, point, class, priority, line_pixels);
+                }
+            }
+        }
+    }
+
+    /// Fill the pixels whose centre is inside the polygon, with the even-odd rule.
+    /// The vertices with a non-finite coordinate are skipped.
+    fn burn_polygon(&mut self, rings: &[Vec<(f64, f64)>], class: u8, priority: i32) {
+        let rings: Vec<Vec<(f64, f64)>> = rings
+            .iter()
+            .map(|ring| {
+                ring.iter()
+                    .filter(|(x, y)| x.is_finite() && y.is_finite())
+                    .map(|&point| self.to_pixel(point))
+                    .collect()
+            })
+            .collect();
+        let (ymin, ymax) = rings
+            .iter()
+            .flatten()
+            .fold((f64::MAX, f64::MIN), |(ymin, ymax), &(_, y)| {
+                (ymin.min(y), ymax.max(y))
+            });
+        if ymin > ymax {
+            return;
+        }
+
+        let first_row = (ymin - 0.5).ceil().max(0.0) as i64;
+        let last_row = (ymax - 0.5).floor().min(self.height as f64 - 1.0) as i64;
+        let mut crossings: Vec<f64> = Vec::new();
+        for row in first_row..=last_row {
+            let y = row as f64 + 0.5;
+            crossings.clear();
+            for ring in &rings {
+                for (i, &(x1, y1)) in ring.iter().enumerate() {
+                    let (x2, y2) = ring[(i + 1) % ring.len()];
+                    if (y1 <= y) != (y2 <= y) {
+                        crossings.push(x1 + (y - y1) / (y2 - y1) * (x2 - x1));
+                    }
+                }
+            }
+            crossings.sort_by(f64::total_cmp);
+            for pair in crossings.chunks_exact(2) {
+                let first_column = (pair[0] - 0.5).ceil().max(0.0) as i64;
+                let last_column = (pair[1] - 0.5).floor().min(self.width as f64 - 1.0) as i64;
+                for column in first_column..=last_column {
+                    self.set(column, row, class, priority);
+                }
+            }
+        }
+    }
+
+    /// Burn the pixels within half the width of a segment.
+    fn burn_segment(
+        &mut self,
+        start: (f64, f64),
+        end: (f64, f64),
+        class: u8,
+        priority: i32,
+        line_pixels: f64,
+    ) {
+        let (x1, y1) = self.to_pixel(start);
+        let (x2, y2) = self.to_pixel(end);
+        let radius = (line_pixels / 2.0).max(0.5);
+        let steps = ((x2 - x1).hypot(y2 - y1) * 2.0).ceil().max(1.0) as i64;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let (x, y) = (x1 + t * (x2 - x1), y1 + t * (y2 - y1));
+            let (first_column, last_column) = ((x - radius).floor(), (x + radius).ceil());
+            let (first_row, last_row) = ((y - radius).floor(), (y + radius).ceil());
+            for row in first_row as i64..last_row as i64 {
+                for column in first_column as i64..last_column as i64 {
+                    let (dx, dy) = (column as f64 + 0.5 - x, row as f64 + 0.5 - y);
+                    if dx.hypot(dy) <= radius {
+                        self.set(column, row, class, priority);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Get the class of a colour.
+fn class_of_color(classes: &[MaskClass], color: (u8, u8, u8)) -> Result<u8, String> {
+    classes
+        .iter()
+        .find(|class| class.color == color)
+        .map(|class| class.id)
+        .ok_or_else(|| format!("Colour {:?} has no class", color))
+}
+
+/// Build the burn source of a layer from its style.
+/// # Returns
+/// - The source, None for the layers styled from a style file or not styled, or an error
+///   message.
+fn burn_source(
+    layer: &LayerState,
+    project_name: &str,
+    palette: &Palette,
+    classes: &[MaskClass],
+) -> Result<Option<BurnSource>, Box<dyn Error>> {
+    let symbology = match &layer.style {
+        LayerStyle::Vegetation => LayerManifest::vegetation_symbology(),
+        LayerStyle::Rules(symbology) => symbology.clone(),
+        LayerStyle::Topographic => {
+            let color = palette.color(&layer.color).ok_or_else(|| {
+                format!(
+                    "Colour '{}' not found in palette '{}'",
+                    layer.color, palette.name
+                )
+            })?;
+            return Ok(Some(BurnSource {
+                shapefile_path: layer.shapefile_path(project_name),
+                subset: layer_subset(layer),
+                rules: vec![(None, class_of_color(classes, color)?)],
+                priority: layer.draw_order,
+                line_pixels: DEFAULT_LINE_PIXELS,
+            }));
+        }
+        LayerStyle::File(_) | LayerStyle::None => return Ok(None),
+    };
+
+    let rule_palette = match &symbology.palette {
+        Some(name) => Palette::load(name)?,
+        None => palette.clone(),
+    };
+    let mut rules = Vec::with_capacity(symbology.rules.len() + 1);
+    for rule in symbology.resolve(&rule_palette)? {
+        rules.push((rule.filter, class_of_color(classes, rule.color)?));
+    }
+    Ok(Some(BurnSource {
+        shapefile_path: layer.shapefile_path(project_name),
+        subset: layer_subset(layer),
+        rules,
+        priority: layer.draw_order,
+        line_pixels: DEFAULT_LINE_PIXELS,
+    }))
+}
+
+/// Get the subset string of a layer, None if it has no filter or if its filter was
+/// applied at extraction.
+fn layer_subset(layer: &LayerState) -> Option<String> {
+    match layer.filter_mode {
+        FilterMode::Subset => layer.filter.clone(),
+        FilterMode::Copy => None,
+    }
+}
+
+/// Burn the loaded layers of a project into a class raster, without rendering them.
+///
+/// Each feature gets the class of the palette entry its style gives it, see `mask_classes`.
+/// Where features overlap, the layer of the highest draw order wins, then the last feature.
+/// # Parameters
+/// - `state`: The state of the project pipeline, giving the layers, their styles and the palette.
+/// - `export`: The extent, resolution and output path of the raster.
+/// # Returns
+/// - The written GeoTIFF and the area it covers, or an error message.
+pub fn rasterize_project(
+    state: &PipelineState,
+    export: &RasterizeExport,
+) -> Result<RenderedMap, Box<dyn Error>> {
+    let palette = Palette::load(&state.palette)?;
+    let classes = mask_classes(MaskEncoding::Classes, &palette);
+    let mut raster = ClassRaster::new(export.extent, export.metres_per_pixel)?;
+
+    let mut sources = Vec::new();
+    for layer in &state.layers {
+        if layer.stage < LayerStage::Extracted {
+            continue;
+        }
+        if let Some(source) = burn_source(layer, &state.name, &palette, &classes)? {
+            sources.push(source);
+        }
+    }
+    sources.sort_by_key(|source| source.priority);
+
+    for source in &sources {
+        let features = read_classified_features(
+            &source.shapefile_path,
+            source.subset.as_deref(),
+            &source.rules,
+            &raster.extent,
+        )?;
+        for (class, geometry) in &features {
+            raster.burn(geometry, *class, source.priority, source.line_pixels);
+        }
+    }
+
+    if let Some(folder) = Path::new(&export.output_path).parent() {
+        create_directory_if_not_exists(&folder.to_string_lossy())?;
+    }
+    let class_path = format!("{}.classes.png", export.output_path);
+    GrayImage::from_raw(raster.width, raster.height, raster.classes)
+        .ok_or("Class raster has an invalid size")?
+        .save(&class_path)?;
+    let map = RenderedMap {
+        path: export.output_path.clone(),
+        width: raster.width,
+        height: raster.height,
+        extent: raster.extent,
+        crs: state.crs.clone(),
+    };
+    let result = write_class_geotiff(&class_path, &map, &classes);
+    fs::remove_file(&class_path)?;
+    result?;
+    Ok(map)
+}
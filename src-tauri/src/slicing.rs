@@ -1,5 +1,382 @@
+use crate::cancellation::CancellationToken;
+use crate::export::{Extent, ImageFormat, MapExport, RenderedMap, Resolution, DEFAULT_DPI};
+use crate::qgis_api_wrapper::{export_map, write_mosaic};
+use crate::utils::create_directory_if_not_exists;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
 
+/// The name of the file describing the grid, written in the output folder of the tiles.
+pub const INDEX_FILE: &str = "index.json";
 
+/// A grid of square tiles covering an extent, starting from its top left corner.
+///
+/// The tiles of the last row and column are full size, so the grid may overflow the
+/// extent on the right and bottom sides.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileGrid {
+    /// The area to cover, in the coordinates of `crs`.
+    pub extent: Extent,
+    pub crs: String,
+    /// The width and height of a tile without its overlap, in pixels.
+    pub tile_size: u32,
+    /// The ground size of a pixel, in metres.
+    pub metres_per_pixel: f64,
+    /// The pixels added on every side of a tile, shared with its neighbours.
+    #[serde(default)]
+    pub overlap: u32,
+}
 
+/// A tile of a grid, as described in the index file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tile {
+    pub row: u32,
+    pub column: u32,
+    /// The area of the tile image, overlap included.
+    pub extent: Extent,
+    /// The path of the tile image, relative to the index file.
+    pub file: String,
+}
 
+/// The content of the index file of a sliced map.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TileIndex {
+    pub grid: TileGrid,
+    pub rows: u32,
+    pub columns: u32,
+    pub format: ImageFormat,
+    pub tiles: Vec<Tile>,
+}
 
+/// How the pixels covered by several tiles are chosen in a mosaic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapRule {
+    /// The overlaps are split in their middle, each tile keeping the pixels closest to it.
+    #[default]
+    Centre,
+    /// The first tile, row by row from the top left, keeps the shared pixels.
+    First,
+    /// The last tile, row by row from the top left, keeps the shared pixels.
+    Last,
+}
+
+/// The part of a tile image copied into a mosaic, in pixels.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TileWindow {
+    pub path: String,
+    pub source_x: u32,
+    pub source_y: u32,
+    pub target_x: u32,
+    pub target_y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What to slice from a project and where to write the tiles.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SliceExport {
+    pub grid: TileGrid,
+    /// The layers and groups rendered, the visible layers of the project if empty.
+    #[serde(default)]
+    pub layers: Vec<String>,
+    pub format: ImageFormat,
+    /// Smooth the edges of the features, see `MapExport::antialiasing`.
+    #[serde(default)]
+    pub antialiasing: bool,
+    pub output_folder: String,
+}
+
+impl TileGrid {
+    /// Get the ground size of a tile without its overlap, in metres.
+    fn tile_ground_size(&self) -> f64 {
+        self.tile_size as f64 * self.metres_per_pixel
+    }
+
+    /// Get the number of rows and columns of the grid.
+    /// # Returns
+    /// - The rows and columns, or an error message if the grid is empty.
+    pub fn dimensions(&self) -> Result<(u32, u32), Box<dyn Error>> {
+        if !(self.extent.width() > 0.0 && self.extent.height() > 0.0) {
+            return Err("The extent of the grid is empty".into());
+        }
+        if !(self.tile_size > 0 && self.metres_per_pixel > 0.0) {
+            return Err("The tile size and the resolution must be strictly positive".into());
+        }
+        let size = self.tile_ground_size();
+        // Drop the floating point noise so an extent of exactly N tiles is not rounded up.
+        let rows = (self.extent.height() / size - 1e-6).ceil().max(1.0) as u32;
+        let columns = (self.extent.width() / size - 1e-6).ceil().max(1.0) as u32;
+        Ok((rows, columns))
+    }
+
+    /// Get the area of a tile, overlap included.
+    /// # Parameters
+    /// - `row`: The row of the tile, from the top.
+    /// - `column`: The column of the tile, from the left.
+    pub fn tile_extent(&self, row: u32, column: u32) -> Extent {
+        let size = self.tile_ground_size();
+        let overlap = self.overlap as f64 * self.metres_per_pixel;
+        let xmin = self.extent.xmin + column as f64 * size;
+        let ymax = self.extent.ymax - row as f64 * size;
+        Extent {
+            xmin: xmin - overlap,
+            ymin: ymax - size - overlap,
+            xmax: xmin + size + overlap,
+            ymax: ymax + overlap,
+        }
+    }
+
+    /// Get the tiles of the grid, row by row from the top left corner.
+    /// # Parameters
+    /// - `format`: The format of the tile images, giving their extension.
+    pub fn tiles(&self, format: ImageFormat) -> Result<Vec<Tile>, Box<dyn Error>> {
+        let (rows, columns) = self.dimensions()?;
+        let mut tiles = Vec::with_capacity((rows * columns) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                let extent = self.tile_extent(row, column);
+                tiles.push(Tile {
+                    row,
+                    column,
+                    file: tile_file_name(row, column, &extent, format),
+                    extent,
+                });
+            }
+        }
+        Ok(tiles)
+    }
+}
+
+impl TileIndex {
+    /// Load the index file of a sliced map.
+    /// # Parameters
+    /// - `path`: A string slice that holds the path of the index file.
+    /// # Returns
+    /// - The index or an error message.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Get the size of the mosaic of the tiles: the extent of the grid, the partial pixels
+    /// on the right and bottom sides being rounded up.
+    ///
+    /// The overlaps on the borders of the grid and the parts of the last tiles past the
+    /// extent are left out.
+    pub fn pixel_size(&self) -> (u32, u32) {
+        let pixels = |length: f64| {
+            // Drop the floating point noise so an extent of exactly N pixels is not rounded up.
+            (length / self.grid.metres_per_pixel - 1e-6).ceil().max(1.0) as u32
+        };
+        (
+            pixels(self.grid.extent.width()),
+            pixels(self.grid.extent.height()),
+        )
+    }
+
+    /// Get the area of the mosaic of the tiles.
+    pub fn extent(&self) -> Extent {
+        let (width, height) = self.pixel_size();
+        let extent = &self.grid.extent;
+        Extent {
+            xmin: extent.xmin,
+            ymin: extent.ymax - height as f64 * self.grid.metres_per_pixel,
+            xmax: extent.xmin + width as f64 * self.grid.metres_per_pixel,
+            ymax: extent.ymax,
+        }
+    }
+
+    /// Get the part of a tile copied into the mosaic.
+    /// # Parameters
+    /// - `tile`: The tile, from the index.
+    /// - `folder`: A string slice that holds the folder of the tile images.
+    /// - `rule`: How the overlaps with the other tiles are resolved.
+    /// # Returns
+    /// - The window of the tile, None if nothing of it is kept.
+    pub fn tile_window(&self, tile: &Tile, folder: &str, rule: OverlapRule) -> Option<TileWindow> {
+        let (mosaic_width, mosaic_height) = self.pixel_size();
+        let (source_x, target_x, width) = axis_window(
+            tile.column,
+            mosaic_width,
+            self.grid.tile_size,
+            self.grid.overlap,
+            rule,
+        )?;
+        let (source_y, target_y, height) = axis_window(
+            tile.row,
+            mosaic_height,
+            self.grid.tile_size,
+            self.grid.overlap,
+            rule,
+        )?;
+        Some(TileWindow {
+            path: format!("{}/{}", folder, tile.file),
+            source_x,
+            source_y,
+            target_x,
+            target_y,
+            width,
+            height,
+        })
+    }
+}
+
+/// Get the pixels of a tile kept along one axis of the mosaic.
+/// # Parameters
+/// - `position`: The row or column of the tile.
+/// - `mosaic_size`: The width or height of the mosaic.
+/// - `tile_size`: The size of a tile without its overlap.
+/// - `overlap`: The overlap on each side of a tile.
+/// - `rule`: How the overlaps with the other tiles are resolved.
+/// # Returns
+/// - The first kept pixel in the tile, its position in the mosaic and the number of kept
+///   pixels, None if nothing is kept.
+fn axis_window(
+    position: u32,
+    mosaic_size: u32,
+    tile_size: u32,
+    overlap: u32,
+    rule: OverlapRule,
+) -> Option<(u32, u32, u32)> {
+    let (position, tile_size, overlap) = (position as i64, tile_size as i64, overlap as i64);
+    let image_start = position * tile_size - overlap;
+    let image_end = (position + 1) * tile_size + overlap;
+    let (start, end) = match rule {
+        OverlapRule::Centre => (position * tile_size, (position + 1) * tile_size),
+        // The previous tile already covers up to `overlap` pixels past the core start.
+        OverlapRule::First if position > 0 => (position * tile_size + overlap, image_end),
+        OverlapRule::First | OverlapRule::Last => (image_start, image_end),
+    };
+    let start = start.max(0);
+    let end = end.min(mosaic_size as i64);
+    if start >= end {
+        return None;
+    }
+    Some((
+        (start - image_start) as u32,
+        start as u32,
+        (end - start) as u32,
+    ))
+}
+
+/// Name a tile by its row, column and bounds, like "r000_c001_1210000_6083000_1220000_6093000.png".
+pub fn tile_file_name(row: u32, column: u32, extent: &Extent, format: ImageFormat) -> String {
+    format!(
+        "r{:03}_c{:03}_{:.0}_{:.0}_{:.0}_{:.0}.{}",
+        row,
+        column,
+        extent.xmin,
+        extent.ymin,
+        extent.xmax,
+        extent.ymax,
+        format.extension()
+    )
+}
+
+/// Export a project as a grid of georeferenced tiles and write the index of the grid.
+///
+/// The tiles without embedded georeferencing get a world file.
+/// # Parameters
+/// - `project_file`: A string slice that holds the path of the project file.
+/// - `slice`: The grid, layers, format and output folder of the tiles.
+/// - `token`: The cancellation token checked before each tile.
+/// - `report`: Called after each tile with the number of tiles done and the total.
+/// # Returns
+/// - The index of the grid or an error message.
+pub fn slice_map<F>(
+    project_file: &str,
+    slice: &SliceExport,
+    token: &CancellationToken,
+    report: F,
+) -> Result<TileIndex, Box<dyn Error>>
+where
+    F: Fn(usize, usize),
+{
+    let (rows, columns) = slice.grid.dimensions()?;
+    let tiles = slice.grid.tiles(slice.format)?;
+    create_directory_if_not_exists(&slice.output_folder)?;
+
+    for (done, tile) in tiles.iter().enumerate() {
+        token.check()?;
+        export_map(
+            project_file,
+            &MapExport {
+                extent: tile.extent,
+                crs: slice.grid.crs.clone(),
+                resolution: Resolution::MetresPerPixel(slice.grid.metres_per_pixel),
+                dpi: DEFAULT_DPI,
+                layers: slice.layers.clone(),
+                format: slice.format,
+                output_path: format!("{}/{}", slice.output_folder, tile.file),
+                antialiasing: Some(slice.antialiasing),
+                world_file: true,
+            },
+        )?;
+        report(done + 1, tiles.len());
+    }
+
+    let index = TileIndex {
+        grid: slice.grid.clone(),
+        rows,
+        columns,
+        format: slice.format,
+        tiles,
+    };
+    fs::write(
+        format!("{}/{}", slice.output_folder, INDEX_FILE),
+        serde_json::to_string_pretty(&index)?,
+    )?;
+    Ok(index)
+}
+
+/// Assemble the tiles of a sliced map into one GeoTIFF covering the extent of the grid.
+///
+/// The tiles are copied one at a time, so the mosaic is never held in memory.
+/// # Parameters
+/// - `index_path`: A string slice that holds the path of the index file.
+/// - `tiles_folder`: The folder of the tile images, like processed copies of the exported
+///   tiles, the folder of the index file if None.
+/// - `output_path`: A string slice that holds the path of the GeoTIFF.
+/// - `rule`: How the overlaps of the tiles are resolved.
+/// # Returns
+/// - The written mosaic and the area it covers, or an error message.
+pub fn mosaic(
+    index_path: &str,
+    tiles_folder: Option<&str>,
+    output_path: &str,
+    rule: OverlapRule,
+) -> Result<RenderedMap, Box<dyn Error>> {
+    let index = TileIndex::load(index_path)?;
+    let index_folder = match Path::new(index_path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() => folder.to_string_lossy().to_string(),
+        _ => ".".to_string(),
+    };
+    let tiles_folder = tiles_folder.unwrap_or(&index_folder);
+
+    let windows: Vec<TileWindow> = index
+        .tiles
+        .iter()
+        .filter_map(|tile| index.tile_window(tile, tiles_folder, rule))
+        .collect();
+    for window in &windows {
+        if !Path::new(&window.path).exists() {
+            return Err(format!("Tile '{}' not found", window.path).into());
+        }
+    }
+
+    if let Some(folder) = Path::new(output_path).parent() {
+        create_directory_if_not_exists(&folder.to_string_lossy())?;
+    }
+    let (width, height) = index.pixel_size();
+    let mosaic = RenderedMap {
+        path: output_path.to_string(),
+        width,
+        height,
+        extent: index.extent(),
+        crs: index.grid.crs.clone(),
+    };
+    write_mosaic(&mosaic, &windows)?;
+    Ok(mosaic)
+}
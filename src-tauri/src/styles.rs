@@ -0,0 +1,86 @@
+use crate::utils::create_directory_if_not_exists;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const STYLES_FOLDER: &str = "resources/styles";
+/// The default styles of the BD TOPO layers of the bundled manifest.
+const BUNDLED_STYLES: &[(&str, &str)] = &[
+    ("AERODROME", include_str!("../styles/AERODROME.qml")),
+    ("BATIMENT", include_str!("../styles/BATIMENT.qml")),
+    (
+        "CONSTRUCTION_SURFACIQUE",
+        include_str!("../styles/CONSTRUCTION_SURFACIQUE.qml"),
+    ),
+    ("COURS_D_EAU", include_str!("../styles/COURS_D_EAU.qml")),
+    (
+        "EQUIPEMENT_DE_TRANSPORT",
+        include_str!("../styles/EQUIPEMENT_DE_TRANSPORT.qml"),
+    ),
+    (
+        "PISTE_D_AERODROME",
+        include_str!("../styles/PISTE_D_AERODROME.qml"),
+    ),
+    ("PLAN_D_EAU", include_str!("../styles/PLAN_D_EAU.qml")),
+    ("RESERVOIR", include_str!("../styles/RESERVOIR.qml")),
+    (
+        "TERRAIN_DE_SPORT",
+        include_str!("../styles/TERRAIN_DE_SPORT.qml"),
+    ),
+    (
+        "TRONCON_DE_ROUTE",
+        include_str!("../styles/TRONCON_DE_ROUTE.qml"),
+    ),
+    (
+        "TRONCON_DE_VOIE_FERREE",
+        include_str!("../styles/TRONCON_DE_VOIE_FERREE.qml"),
+    ),
+    ("ZONE_D_ESTRAN", include_str!("../styles/ZONE_D_ESTRAN.qml")),
+    (
+        "ZONE_D_HABITATION",
+        include_str!("../styles/ZONE_D_HABITATION.qml"),
+    ),
+];
+
+/// The style file formats QGIS can load onto a layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StyleFormat {
+    /// The QGIS layer style file.
+    Qml,
+    /// The OGC Styled Layer Descriptor.
+    Sld,
+}
+
+impl StyleFormat {
+    /// Get the format of a style file from its extension.
+    pub fn from_path(path: &str) -> Result<Self, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase());
+        match extension.as_deref() {
+            Some("qml") => Ok(StyleFormat::Qml),
+            Some("sld") => Ok(StyleFormat::Sld),
+            _ => Err(format!("Unsupported style file '{}', expected .qml or .sld", path).into()),
+        }
+    }
+}
+
+/// Get the path of the default style of a layer, by the name of its files in the archive.
+pub fn default_style_path(layer_name: &str) -> String {
+    format!("{}/{}.qml", STYLES_FOLDER, layer_name)
+}
+
+/// Write the bundled styles that do not exist yet, keeping the styles edited by the user.
+/// # Returns
+/// - An empty result or an error message.
+pub fn install_default_styles() -> Result<(), Box<dyn Error>> {
+    create_directory_if_not_exists(STYLES_FOLDER)?;
+    for (layer_name, content) in BUNDLED_STYLES {
+        let path = default_style_path(layer_name);
+        if !Path::new(&path).exists() {
+            fs::write(path, content)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use crate::palette::Palette;
+use serde::{Deserialize, Serialize};
+
+/// A rule matching the features whose attribute is one of the given values.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbologyRule {
+    pub field: String,
+    pub values: Vec<String>,
+    /// The name of the palette entry the matching features are drawn with.
+    pub color: String,
+    /// The legend label of the rule, defaults to the values.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// An ordered list of rules, the first matching rule giving the colour of a feature.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbology {
+    /// The name of the palette the rule colours are taken from, the project palette if None.
+    #[serde(default)]
+    pub palette: Option<String>,
+    pub rules: Vec<SymbologyRule>,
+    /// The palette entry of the features no rule matches, hidden if None.
+    #[serde(default)]
+    pub default_color: Option<String>,
+}
+
+/// A rule turned into a QGIS filter expression and a colour.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ResolvedRule {
+    /// The QGIS expression of the rule, None for the rule of the unmatched features.
+    pub filter: Option<String>,
+    pub color: (u8, u8, u8),
+    pub label: String,
+}
+
+impl Symbology {
+    /// Resolve the rules against a palette.
+    ///
+    /// The filter of each rule excludes the features matched by the previous rules,
+    /// so a feature is only drawn by its first matching rule.
+    /// # Parameters
+    /// - `palette`: The palette the colours are taken from.
+    /// # Returns
+    /// - The resolved rules, the rule of the unmatched features last, or an error message
+    ///   if a colour is not in the palette.
+    pub fn resolve(&self, palette: &Palette) -> Result<Vec<ResolvedRule>, String> {
+        let color = |name: &str| {
+            palette
+                .color(name)
+                .ok_or_else(|| format!("Colour '{}' not found in palette '{}'", name, palette.name))
+        };
+
+        let mut resolved = Vec::with_capacity(self.rules.len() + 1);
+        let mut previous: Vec<String> = Vec::new();
+        for rule in &self.rules {
+            let condition = rule_condition(rule);
+            let filter = if previous.is_empty() {
+                condition.clone()
+            } else {
+                format!("({}) AND NOT ({})", condition, previous.join(" OR "))
+            };
+            resolved.push(ResolvedRule {
+                filter: Some(filter),
+                color: color(&rule.color)?,
+                label: rule.label.clone().unwrap_or_else(|| rule.values.join(", ")),
+            });
+            previous.push(condition);
+        }
+
+        if let Some(default_color) = &self.default_color {
+            resolved.push(ResolvedRule {
+                filter: None,
+                color: color(default_color)?,
+                label: "Autres".to_string(),
+            });
+        }
+        Ok(resolved)
+    }
+}
+
+/// Build the QGIS expression matching the features of a rule.
+fn rule_condition(rule: &SymbologyRule) -> String {
+    let values: Vec<String> = rule
+        .values
+        .iter()
+        .map(|value| format!("'{}'", value.replace('\'', "''")))
+        .collect();
+    format!(
+        "\"{}\" IN ({})",
+        rule.field.replace('"', "\"\""),
+        values.join(", ")
+    )
+}
+
+/// The kind of symbol a layer is drawn with, from its geometry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolType {
+    Fill,
+    Line,
+    Marker,
+}
+
+/// A category of a categorized renderer, as shown in the style editor.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CategoryStyle {
+    pub value: String,
+    pub label: String,
+    /// The colour as encoded by QGIS, like "80,200,120,255".
+    pub color: String,
+    pub visible: bool,
+    /// The width of a line symbol, in millimetres.
+    pub width: Option<f64>,
+    /// The size of a marker symbol, in millimetres.
+    pub size: Option<f64>,
+}
+
+/// The current renderer of a layer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerRenderer {
+    /// The QGIS renderer type, like "categorizedSymbol", "singleSymbol" or "RuleRenderer".
+    pub kind: String,
+    /// The attribute of a categorized renderer.
+    pub field: Option<String>,
+    pub symbol_type: SymbolType,
+    /// The categories of a categorized renderer, empty for the other renderers.
+    pub categories: Vec<CategoryStyle>,
+}
+
+/// A change to one category, the properties left to None being kept.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CategoryUpdate {
+    pub value: String,
+    /// The new colour, in any form QGIS decodes: "r,g,b,a", "#rrggbb" or a colour name.
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub visible: Option<bool>,
+    #[serde(default)]
+    pub label: Option<String>,
+    /// The new width, for line symbols only.
+    #[serde(default)]
+    pub width: Option<f64>,
+    /// The new size, for marker symbols only.
+    #[serde(default)]
+    pub size: Option<f64>,
+}
+
+/// The single symbol of a layer drawn with one colour, its kind (fill, line or marker)
+/// being chosen from the geometry of the layer.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SimpleSymbol {
+    pub color: (u8, u8, u8),
+    /// The width of a line symbol or the size of a marker symbol, in millimetres.
+    /// Ignored for fill symbols, the style file or `DEFAULT_LINE_WIDTH` and
+    /// `DEFAULT_MARKER_SIZE` being used if None.
+    pub width: Option<f64>,
+}
+
+/// The width of the line symbols without style file, in millimetres.
+pub const DEFAULT_LINE_WIDTH: f64 = 0.46;
+/// The size of the marker symbols without style file, in millimetres.
+pub const DEFAULT_MARKER_SIZE: f64 = 2.0;
@@ -0,0 +1,232 @@
+use crate::aoi::{AreaOfInterest, ClipArea};
+use crate::utils::get_departement_name;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use tokio::sync::OnceCell;
+
+const REGIONS: &str = include_str!("../reference/regions.json");
+const REFERENCE_FOLDER: &str = "reference";
+/// The number of communes returned by a search.
+pub const SEARCH_LIMIT: usize = 20;
+
+static REFERENCE: OnceCell<AdminReference> = OnceCell::const_new();
+
+/// A region and the codes of its departments, from the embedded reference.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Region {
+    pub code: String,
+    pub name: String,
+    pub departements: Vec<String>,
+}
+
+/// A commune, as listed by the geo.api.gouv.fr API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Commune {
+    /// The INSEE code of the commune.
+    pub code: String,
+    #[serde(rename = "nom")]
+    pub name: String,
+    #[serde(rename = "codesPostaux", default)]
+    pub postal_codes: Vec<String>,
+    #[serde(rename = "codeDepartement")]
+    pub departement: String,
+    #[serde(rename = "codeRegion")]
+    pub region: String,
+    #[serde(rename = "codeEpci", default)]
+    pub epci: Option<String>,
+}
+
+/// An EPCI (intercommunality), as listed by the geo.api.gouv.fr API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Epci {
+    /// The SIREN code of the EPCI.
+    pub code: String,
+    #[serde(rename = "nom")]
+    pub name: String,
+}
+
+/// A commune found by a search, with what a new project needs from it.
+#[derive(Clone, Debug, Serialize)]
+pub struct CommuneMatch {
+    pub commune: Commune,
+    pub departement_name: Option<String>,
+    pub region_name: Option<String>,
+    pub epci_name: Option<String>,
+    /// The commune contour, the default area of interest of the project.
+    pub aoi: ClipArea,
+}
+
+/// The communes and EPCIs of France.
+///
+/// Unlike the regions, they are too many to be embedded in the binary and are shipped
+/// as resources of the application in `reference`, see `reference/update.sh`.
+#[derive(Clone, Debug, Default)]
+pub struct AdminReference {
+    pub communes: Vec<Commune>,
+    pub epcis: Vec<Epci>,
+}
+
+impl AdminReference {
+    /// Load the reference shipped in `reference`.
+    /// # Returns
+    /// - The reference or an error message.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let read = |file: &str| {
+            let path = format!("{}/{}", REFERENCE_FOLDER, file);
+            fs::read_to_string(&path)
+                .map_err(|e| format!("Error reading reference file '{}': {}", path, e))
+        };
+        Ok(AdminReference {
+            communes: serde_json::from_str(&read("communes.json")?)?,
+            epcis: serde_json::from_str(&read("epcis.json")?)?,
+        })
+    }
+
+    /// Get the reference shared by the whole application, loading it on first use.
+    pub async fn shared() -> Result<&'static Self, Box<dyn Error>> {
+        if let Some(reference) = REFERENCE.get() {
+            return Ok(reference);
+        }
+        let reference = Self::load()?;
+        Ok(REFERENCE.get_or_init(|| async { reference }).await)
+    }
+
+    /// Search the communes by INSEE code, postal code or name.
+    ///
+    /// A 5 character query is matched against the INSEE and postal codes. Otherwise the
+    /// accent and case insensitive name is matched: exact names first, then the names
+    /// starting with the query, then the names containing it.
+    /// # Parameters
+    /// - `query`: A string slice that holds the searched code or name.
+    /// - `limit`: The maximum number of communes returned.
+    /// # Returns
+    /// - The matching communes, best matches first.
+    pub fn search_communes(&self, query: &str, limit: usize) -> Vec<&Commune> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        if query.len() == 5 && query.chars().all(|c| c.is_ascii_alphanumeric()) {
+            let query = query.to_uppercase();
+            let found: Vec<&Commune> = self
+                .communes
+                .iter()
+                .filter(|commune| commune.code == query || commune.postal_codes.contains(&query))
+                .take(limit)
+                .collect();
+            if !found.is_empty() {
+                return found;
+            }
+        }
+
+        let query = normalize_name(query);
+        let mut found: Vec<(usize, &Commune)> = self
+            .communes
+            .iter()
+            .filter_map(|commune| {
+                let name = normalize_name(&commune.name);
+                if name == query {
+                    Some((0, commune))
+                } else if name.starts_with(&query) {
+                    Some((1, commune))
+                } else if name.contains(&query) {
+                    Some((2, commune))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        found.sort_by(|(rank_a, a), (rank_b, b)| {
+            rank_a
+                .cmp(rank_b)
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.code.cmp(&b.code))
+        });
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(_, commune)| commune)
+            .collect()
+    }
+
+    /// Get a commune by INSEE code.
+    pub fn get_commune(&self, code: &str) -> Option<&Commune> {
+        self.communes.iter().find(|commune| commune.code == code)
+    }
+
+    /// Get the name of an EPCI.
+    pub fn get_epci_name(&self, code: &str) -> Option<String> {
+        self.epcis
+            .iter()
+            .find(|epci| epci.code == code)
+            .map(|epci| epci.name.clone())
+    }
+
+    /// Get the communes of an EPCI.
+    pub fn get_epci_communes(&self, code: &str) -> Vec<&Commune> {
+        self.communes
+            .iter()
+            .filter(|commune| commune.epci.as_deref() == Some(code))
+            .collect()
+    }
+
+    /// Resolve what a new project needs from a commune: its department and its contour.
+    pub fn resolve_commune(&self, commune: &Commune) -> CommuneMatch {
+        CommuneMatch {
+            commune: commune.clone(),
+            departement_name: get_departement_name(&commune.departement),
+            region_name: get_region(&commune.region).map(|region| region.name),
+            epci_name: commune
+                .epci
+                .as_deref()
+                .and_then(|code| self.get_epci_name(code)),
+            aoi: ClipArea {
+                area: AreaOfInterest::Commune {
+                    query: commune.code.clone(),
+                },
+                buffer: 0.0,
+            },
+        }
+    }
+}
+
+/// Get the regions of the embedded reference.
+pub fn get_regions() -> Vec<Region> {
+    serde_json::from_str(REGIONS).expect("Embedded regions reference is invalid")
+}
+
+/// Get a region by code.
+pub fn get_region(code: &str) -> Option<Region> {
+    get_regions().into_iter().find(|region| region.code == code)
+}
+
+/// Get the region of a department.
+pub fn get_departement_region(code: &str) -> Option<Region> {
+    get_regions()
+        .into_iter()
+        .find(|region| region.departements.iter().any(|d| d == code))
+}
+
+/// Normalize a place name for searching: lowercase, without accents, and with the
+/// hyphens and apostrophes replaced by spaces.
+pub fn normalize_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        match c {
+            'à' | 'â' | 'ä' | 'á' | 'ã' => normalized.push('a'),
+            'ç' => normalized.push('c'),
+            'é' | 'è' | 'ê' | 'ë' => normalized.push('e'),
+            'î' | 'ï' | 'í' | 'ì' => normalized.push('i'),
+            'ô' | 'ö' | 'ó' | 'ò' => normalized.push('o'),
+            'ù' | 'û' | 'ü' | 'ú' => normalized.push('u'),
+            'ÿ' => normalized.push('y'),
+            'œ' => normalized.push_str("oe"),
+            'æ' => normalized.push_str("ae"),
+            '-' | '\'' | '’' => normalized.push(' '),
+            _ => normalized.push(c),
+        }
+    }
+    normalized.split_whitespace().collect::<Vec<_>>().join(" ")
+}
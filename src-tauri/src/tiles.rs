@@ -0,0 +1,235 @@
+use crate::export::Extent;
+use crate::qgis_api_wrapper::generate_xyz_tiles;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// The URI scheme the frontend requests the tiles with, as `tiles://{project}/{z}/{x}/{y}.png`.
+pub const TILES_SCHEME: &str = "tiles";
+/// The highest zoom level QGIS renders tiles for.
+pub const MAX_ZOOM: u8 = 25;
+
+/// Where the tiles of a pyramid are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileStorage {
+    /// One PNG per tile, in `{z}/{x}/{y}.png` folders.
+    #[default]
+    Directory,
+    /// Every tile in one MBTiles SQLite file.
+    MbTiles,
+}
+
+/// The Web Mercator tile pyramid of a project.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TilePyramid {
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    #[serde(default)]
+    pub storage: TileStorage,
+    /// The area rendered, in the project CRS, the extent of every layer if None.
+    #[serde(default)]
+    pub extent: Option<Extent>,
+}
+
+/// A tile requested through the `tiles` protocol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TileRequest {
+    pub project: String,
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Get the folder of the tiles of a project stored as a directory.
+pub fn tiles_folder(project_name: &str) -> String {
+    format!("resources/QGIS/{}/tiles", project_name)
+}
+
+/// Get the MBTiles file of the tiles of a project.
+pub fn mbtiles_path(project_name: &str) -> String {
+    format!("resources/QGIS/{}/tiles.mbtiles", project_name)
+}
+
+/// Render a project into a tile pyramid, replacing its previous tiles.
+///
+/// The tiles are rendered next to the previous ones, which are only replaced once the
+/// rendering succeeded. The previous tiles are moved aside until the new ones are in
+/// place, and moved back if the replacement fails.
+/// # Parameters
+/// - `project_name`: A string slice that holds the name of the project.
+/// - `project_file`: A string slice that holds the path of the project file.
+/// - `pyramid`: The zoom range, storage and extent of the pyramid.
+/// # Returns
+/// - The path of the folder or file of the tiles, or an error message.
+pub fn generate_pyramid(
+    project_name: &str,
+    project_file: &str,
+    pyramid: &TilePyramid,
+) -> Result<String, Box<dyn Error>> {
+    if pyramid.min_zoom > pyramid.max_zoom || pyramid.max_zoom > MAX_ZOOM {
+        return Err(format!(
+            "Invalid zoom range {}-{}, expected levels from 0 to {}",
+            pyramid.min_zoom, pyramid.max_zoom, MAX_ZOOM
+        )
+        .into());
+    }
+
+    let folder = tiles_folder(project_name);
+    let mbtiles = mbtiles_path(project_name);
+    let (output_path, partial_path) = match pyramid.storage {
+        TileStorage::Directory => (folder.clone(), format!("{}_partial", folder)),
+        TileStorage::MbTiles => (
+            mbtiles.clone(),
+            format!("resources/QGIS/{}/tiles_partial.mbtiles", project_name),
+        ),
+    };
+    remove_tiles(&partial_path)?;
+    if let Err(e) = generate_xyz_tiles(project_file, pyramid, &partial_path) {
+        remove_tiles(&partial_path)?;
+        return Err(e.into());
+    }
+
+    let previous = [
+        (folder.clone(), format!("{}_previous", folder)),
+        (
+            mbtiles,
+            format!("resources/QGIS/{}/tiles_previous.mbtiles", project_name),
+        ),
+    ];
+    let replaced = set_tiles_aside(&previous)
+        .and_then(|_| fs::rename(&partial_path, &output_path).map_err(|e| e.into()));
+    if let Err(e) = replaced {
+        restore_tiles(&previous)?;
+        return Err(e);
+    }
+    for (_, previous_path) in &previous {
+        remove_tiles(previous_path)?;
+    }
+    Ok(output_path)
+}
+
+/// Move the tiles of a project aside, so they can be restored if their replacement fails.
+/// # Parameters
+/// - `previous`: The path of each tile folder or MBTiles file, and the path it is moved to.
+/// # Returns
+/// - An empty result or an error message.
+fn set_tiles_aside(previous: &[(String, String)]) -> Result<(), Box<dyn Error>> {
+    for (path, previous_path) in previous {
+        remove_tiles(previous_path)?;
+        if Path::new(path).exists() {
+            fs::rename(path, previous_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Move back the tiles set aside by `set_tiles_aside`.
+fn restore_tiles(previous: &[(String, String)]) -> Result<(), Box<dyn Error>> {
+    for (path, previous_path) in previous {
+        if Path::new(previous_path).exists() {
+            remove_tiles(path)?;
+            fs::rename(previous_path, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a tile folder or MBTiles file, if it exists.
+fn remove_tiles(path: &str) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(path);
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Parse the URI of a tile request.
+///
+/// The webviews without custom schemes get the URI as `https://tiles.localhost/...`,
+/// which is accepted too.
+/// # Parameters
+/// - `uri`: A string slice that holds the requested URI.
+/// # Returns
+/// - The requested tile, None if the URI is not a tile URI.
+pub fn parse_tile_uri(uri: &str) -> Option<TileRequest> {
+    let path = uri
+        .strip_prefix("tiles://")
+        .or_else(|| uri.strip_prefix("https://tiles.localhost/"))
+        .or_else(|| uri.strip_prefix("http://tiles.localhost/"))?;
+    let path = path.split(['?', '#']).next()?;
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let [project, z, x, y] = parts.as_slice() else {
+        return None;
+    };
+    let project = percent_decode(project)?;
+    if project.is_empty() || project.contains("..") {
+        return None;
+    }
+    Some(TileRequest {
+        project,
+        z: z.parse().ok()?,
+        x: x.parse().ok()?,
+        y: y.strip_suffix(".png")?.parse().ok()?,
+    })
+}
+
+/// Decode the %XX escapes of a URI segment.
+fn percent_decode(segment: &str) -> Option<String> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = segment.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Read a tile of a project, from its tile folder or else its MBTiles file.
+/// # Parameters
+/// - `request`: The requested tile, in XYZ numbering.
+/// # Returns
+/// - The PNG of the tile, None if the project has no such tile, or an error message.
+pub fn read_tile(request: &TileRequest) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let tile_path = format!(
+        "{}/{}/{}/{}.png",
+        tiles_folder(&request.project),
+        request.z,
+        request.x,
+        request.y
+    );
+    if Path::new(&tile_path).exists() {
+        return Ok(Some(fs::read(tile_path)?));
+    }
+
+    let mbtiles = mbtiles_path(&request.project);
+    if !Path::new(&mbtiles).exists() || request.z > MAX_ZOOM {
+        return Ok(None);
+    }
+    let rows = 1u64 << request.z;
+    if request.y as u64 >= rows {
+        return Ok(None);
+    }
+    // MBTiles numbers the rows from the bottom, as TMS does.
+    let tile_row = rows - 1 - request.y as u64;
+    let connection = Connection::open_with_flags(&mbtiles, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let tile = connection
+        .query_row(
+            "SELECT tile_data FROM tiles WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+            (request.z as i64, request.x as i64, tile_row as i64),
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(tile)
+}
@@ -1,10 +1,13 @@
+use crate::cancellation::{CancellationToken, Cancelled};
 use lazy_static::lazy_static;
 
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::Duration;
 
 lazy_static! {
     pub static ref DEPARTEMENTS: HashMap<String, String> = [
@@ -66,7 +69,7 @@ lazy_static! {
         ("55", "Meuse"),
         ("56", "Morbihan"),
         ("57", "Moselle"),
-        ("58 ", "Nièvre"),
+        ("58", "Nièvre"),
         ("59", "Nord"),
         ("60", "Oise"),
         ("61", "Orne"),
@@ -125,6 +128,47 @@ lazy_static! {
         [(255, 255, 255), (128, 128, 128), (14, 14, 14)].to_vec();
 }
 
+/// The official CRS of a department and the label IGN uses for it in archive names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DepartementCrs {
+    pub epsg: &'static str,
+    pub ign_label: &'static str,
+}
+
+/// Lambert-93, the CRS of metropolitan France.
+pub const LAMBERT_93: DepartementCrs = DepartementCrs {
+    epsg: "EPSG:2154",
+    ign_label: "LAMB93",
+};
+
+/// Get the official CRS of a department, Lambert-93 for metropolitan France and the
+/// local UTM projection for the overseas departments.
+pub fn get_departement_crs(code: &str) -> DepartementCrs {
+    match code {
+        // RGAF09 / UTM zone 20N
+        "971" | "972" => DepartementCrs {
+            epsg: "EPSG:5490",
+            ign_label: "RGAF09UTM20",
+        },
+        // RGFG95 / UTM zone 22N
+        "973" => DepartementCrs {
+            epsg: "EPSG:2972",
+            ign_label: "UTM22RGFG95",
+        },
+        // RGR92 / UTM zone 40S
+        "974" => DepartementCrs {
+            epsg: "EPSG:2975",
+            ign_label: "RGR92UTM40S",
+        },
+        // RGM04 / UTM zone 38S
+        "976" => DepartementCrs {
+            epsg: "EPSG:4471",
+            ign_label: "RGM04UTM38S",
+        },
+        _ => LAMBERT_93,
+    }
+}
+
 pub fn get_departement_list() -> HashMap<String, String> {
     DEPARTEMENTS.clone()
 }
@@ -161,6 +205,35 @@ pub fn create_directory_if_not_exists(path: &str) -> Result<(), Box<dyn Error>>
     Ok(())
 }
 
+/// Run a command, killing it if the token is cancelled before it exits.
+/// # Parameters
+/// - `command`: The command to run, its output is discarded.
+/// - `token`: The cancellation token polled while the command is running.
+/// # Returns
+/// - The exit status of the command, `Cancelled` or an error message.
+pub fn run_cancellable_command(
+    command: &mut Command,
+    token: &CancellationToken,
+) -> Result<ExitStatus, Box<dyn Error>> {
+    token.check()?;
+    let mut child = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if token.is_cancelled() {
+            child.kill()?;
+            child.wait()?;
+            return Err(Cancelled.into());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
 /// Compress a folder.
 /// # Parameters
 /// - `folder_directory_path`: A string slice that holds the path of the folder.
@@ -313,6 +386,7 @@ fn move_folder_contents(src_dir: &Path, dst_dir: &Path) -> Result<(), Box<dyn Er
 /// - `output_dir`: A string slice that holds the path of the output directory.
 /// - `extracted_name`: An optional string slice that holds the name of the extracted folder.
 /// - `filter`: An optional string slice that holds the name of the file to extract.
+/// - `token`: The cancellation token checked during the extraction.
 /// # Returns
 /// - An empty result or an error message.
 pub fn extract_specific_folder(
@@ -321,14 +395,21 @@ pub fn extract_specific_folder(
     output_dir: &str,
     extracted_name: Option<&str>,
     filter: Option<&str>,
+    token: &CancellationToken,
 ) -> Result<(), Box<dyn Error>> {
     create_directory_if_not_exists(output_dir)?;
     let temp_extract_dir = Path::new(output_dir).join("temp_extract");
     create_directory_if_not_exists(temp_extract_dir.to_str().unwrap())?;
-    Command::new("7z")
-        .args(&["x", archive_path])
-        .arg(format!("-o{}", temp_extract_dir.to_str().unwrap()))
-        .output()?;
+    let extraction = run_cancellable_command(
+        Command::new("7z")
+            .args(&["x", archive_path])
+            .arg(format!("-o{}", temp_extract_dir.to_str().unwrap())),
+        token,
+    );
+    if let Err(e) = extraction {
+        fs::remove_dir_all(&temp_extract_dir)?;
+        return Err(e);
+    }
 
     let extracted_folder_path = temp_extract_dir.join(folder_name);
     let destination = if let Some(extracted_name) = extracted_name {
@@ -382,6 +463,7 @@ pub fn extract_specific_folder(
 /// - `output_dir`: A string slice that holds the path of the output directory.
 /// - `extracted_name`: An optional string slice that holds the name of the extracted folder.
 /// - `filter`: An optional string slice that holds the name of the file to extract.
+/// - `token`: The cancellation token checked before and during the extraction.
 /// # Returns
 /// - An empty result or an error message.
 pub fn layer_full_extraction(
@@ -390,10 +472,12 @@ pub fn layer_full_extraction(
     layer_name: &str,
     project_name: &str,
     filter: Option<&str>,
+    token: &CancellationToken,
 ) -> Result<(), Box<dyn Error>> {
     let archive_path = format!("tmp/{}_{}.7z", db_name, code);
     let output_dir = format!("resources/QGIS/{}", project_name);
 
+    token.check()?;
     if let Some(folder_name) = find_filepath_in_archive(&archive_path, layer_name)? {
         println!("Found folder: {}", folder_name);
         extract_specific_folder(
@@ -402,6 +486,7 @@ pub fn layer_full_extraction(
             &output_dir,
             Some(layer_name),
             filter,
+            token,
         )?;
     } else {
         println!(
@@ -1,3 +1,5 @@
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::utils::get_departement_crs;
 use futures_util::StreamExt;
 use reqwest;
 use scraper::{Html, Selector};
@@ -6,6 +8,9 @@ use tokio::{fs::File, io::AsyncWriteExt};
 
 /// Gets the URL of a SHP file from the IGN Database.
 ///
+/// IGN names the archives after the CRS and the department code padded to 3 characters,
+/// like `..._LAMB93_D02A_...` or `..._RGR92UTM40S_D974_...`.
+///
 /// # Parameters
 /// - `code`: A string slice that holds the department code.
 /// - `url`: A string slice that holds the URL to a specific page of the IGN database.
@@ -16,11 +21,12 @@ pub async fn get_departement_shp_file_url(code: &str, url: &str) -> Result<Strin
     let body = reqwest::get(url).await?.text().await?;
     let document = Html::parse_document(&body);
     let selector = Selector::parse("a")?;
+    let pattern = format!("_{}_D{:0>3}_", get_departement_crs(code).ign_label, code);
 
     let mut shp_files: Vec<_> = document
         .select(&selector)
         .filter_map(|element| element.value().attr("href"))
-        .filter(|href| href.contains(&format!("D0{}", code)))
+        .filter(|href| href.contains(&pattern))
         .collect();
 
     if shp_files.is_empty() {
@@ -45,10 +51,17 @@ pub async fn get_departement_shp_file_url(code: &str, url: &str) -> Result<Strin
 /// # Parameters
 /// - `url`: A string slice that holds the URL to be checked.
 /// - `code`: A string slice that holds the department code.
+/// - `token`: The cancellation token checked between each downloaded chunk.
 ///
 /// # Returns
 /// - A string slice representing the determined name.
-pub async fn download_shp_file(url: &str, code: &str) -> Result<(), Box<dyn Error>> {
+///
+/// If the token is cancelled, the partial archive is removed and `Cancelled` is returned.
+pub async fn download_shp_file(
+    url: &str,
+    code: &str,
+    token: &CancellationToken,
+) -> Result<(), Box<dyn Error>> {
     let name = match url {
         url if url.contains("BDTOPO") => "BDTOPO",
         url if url.contains("BDFORET") => "BDFORET",
@@ -61,9 +74,14 @@ pub async fn download_shp_file(url: &str, code: &str) -> Result<(), Box<dyn Erro
         fs::remove_file(&archive_path)?;
     }
 
-    let mut file = File::create(archive_path).await?;
+    let mut file = File::create(&archive_path).await?;
     let mut stream = reqwest::get(url).await?.bytes_stream();
     while let Some(chunk_result) = stream.next().await {
+        if token.is_cancelled() {
+            drop(file);
+            fs::remove_file(&archive_path)?;
+            return Err(Cancelled.into());
+        }
         let chunk = chunk_result?;
         file.write_all(&chunk).await?;
     }
@@ -71,3 +89,76 @@ pub async fn download_shp_file(url: &str, code: &str) -> Result<(), Box<dyn Erro
 
     Ok(())
 }
+
+/// Downloads the contour of a commune from the geo.api.gouv.fr API as GeoJSON.
+///
+/// - If the query is an INSEE code (5 characters, digits or "2A"/"2B"), the commune is found by code.
+/// - Otherwise, the commune is found by name and the best match is kept.
+///
+/// # Parameters
+/// - `query`: A string slice that holds the INSEE code or the name of the commune.
+/// - `output_path`: A string slice that holds the path of the GeoJSON file to write.
+///
+/// # Returns
+/// - An empty result or an error message.
+pub async fn download_commune_contour(
+    query: &str,
+    output_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let query = query.trim();
+    let is_insee_code = query.len() == 5
+        && query
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == 'A' || c == 'B');
+    let search_field = if is_insee_code { "code" } else { "nom" };
+
+    let body = reqwest::Client::new()
+        .get("https://geo.api.gouv.fr/communes")
+        .query(&[
+            (search_field, query),
+            ("format", "geojson"),
+            ("geometry", "contour"),
+            ("fields", "code,nom,codeDepartement"),
+        ])
+        .send()
+        .await?
+        .text()
+        .await?;
+
+    let mut collection: serde_json::Value = serde_json::from_str(&body)?;
+    let best_match = match collection["features"].as_array() {
+        Some(features) if !features.is_empty() => features[0].clone(),
+        _ => return Err("No commune found".into()),
+    };
+    collection["features"] = serde_json::Value::Array(vec![best_match]);
+
+    fs::write(output_path, collection.to_string())?;
+    Ok(())
+}
+
+/// Gets the code of the department containing a point, from the geo.api.gouv.fr API.
+///
+/// # Parameters
+/// - `lon`: The longitude of the point, in WGS84.
+/// - `lat`: The latitude of the point, in WGS84.
+///
+/// # Returns
+/// - The department code, None if the point is not in a French commune (e.g. at sea).
+pub async fn get_departement_at(lon: f64, lat: f64) -> Result<Option<String>, Box<dyn Error>> {
+    let body = reqwest::Client::new()
+        .get("https://geo.api.gouv.fr/communes")
+        .query(&[
+            ("lon", lon.to_string()),
+            ("lat", lat.to_string()),
+            ("fields", "codeDepartement".to_string()),
+        ])
+        .send()
+        .await?
+        .text()
+        .await?;
+
+    let communes: serde_json::Value = serde_json::from_str(&body)?;
+    Ok(communes[0]["codeDepartement"]
+        .as_str()
+        .map(|code| code.to_string()))
+}
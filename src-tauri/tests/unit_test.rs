@@ -1,6 +1,23 @@
+use eve_mapmaker::aoi;
 use eve_mapmaker::app_setup;
+use eve_mapmaker::batch;
+use eve_mapmaker::cancellation::CancellationToken;
 use eve_mapmaker::dependency;
+use eve_mapmaker::export;
+use eve_mapmaker::layout;
+use eve_mapmaker::manifest;
+use eve_mapmaker::masks;
+use eve_mapmaker::palette;
+use eve_mapmaker::pipeline;
+use eve_mapmaker::presets;
+use eve_mapmaker::progress;
 use eve_mapmaker::qgis_api_wrapper;
+use eve_mapmaker::rasterize;
+use eve_mapmaker::slicing;
+use eve_mapmaker::styles;
+use eve_mapmaker::symbology;
+use eve_mapmaker::territory;
+use eve_mapmaker::tiles;
 use eve_mapmaker::utils;
 use eve_mapmaker::web_request;
 
@@ -72,6 +89,7 @@ mod tests {
             output_dir,
             Some("Vegetation"),
             None,
+            &CancellationToken::new(),
         );
         assert!(
             std::path::Path::new("resources/QGIS/test/Vegetation/FORMATION_VEGETALE.shp").exists()
@@ -87,6 +105,807 @@ mod tests {
         assert_eq!(result.unwrap().unwrap(), "BDFORET_2-0__SHP_LAMB93_D02A_2017-05-10/BDFORET/1_DONNEES_LIVRAISON/BDF_2-0_SHP_LAMB93_D02A/");
     }
 
+    #[test]
+    fn test_cancellation_token_shared_between_clones() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(
+            token.check().unwrap_err().to_string(),
+            "Operation cancelled"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_state_starts_at_discover() {
+        let state = pipeline::PipelineState::new(
+            "test",
+            &["2A".to_string()],
+            &manifest::LayerManifest::bundled(),
+            None,
+            None,
+        );
+        assert_eq!(state.next_step(), Some(pipeline::PipelineStep::Discover));
+        assert_eq!(state.layers.len(), 14);
+        assert!(state.failed_layers().is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_state_save_and_load() {
+        let state = pipeline::PipelineState::new(
+            "test_pipeline_state",
+            &["2A".to_string()],
+            &manifest::LayerManifest::bundled(),
+            None,
+            None,
+        );
+        state.save().unwrap();
+        let loaded = pipeline::PipelineState::load("test_pipeline_state")
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.codes, vec!["2A"]);
+        assert_eq!(loaded.crs, "EPSG:2154");
+        assert_eq!(loaded.next_step(), Some(pipeline::PipelineStep::Discover));
+        let resumed = pipeline::PipelineState::load_or_new(
+            "test_pipeline_state",
+            &["2A".to_string(), "2B".to_string()],
+            &manifest::LayerManifest::bundled(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(resumed.codes, vec!["2A", "2B"]);
+        std::fs::remove_dir_all("resources/QGIS/test_pipeline_state").unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_state_adds_missing_steps_on_load() {
+        let mut state = pipeline::PipelineState::new(
+            "test_pipeline_state_steps",
+            &["2A".to_string()],
+            &manifest::LayerManifest::bundled(),
+            None,
+            None,
+        );
+        state
+            .steps
+            .retain(|step| step.step != pipeline::PipelineStep::Export);
+        for step in state.steps.iter_mut() {
+            step.status = pipeline::StepStatus::Done;
+        }
+        state.save().unwrap();
+        let loaded = pipeline::PipelineState::load("test_pipeline_state_steps")
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.steps.len(), pipeline::PipelineStep::ALL.len());
+        assert_eq!(loaded.next_step(), Some(pipeline::PipelineStep::Export));
+        std::fs::remove_dir_all("resources/QGIS/test_pipeline_state_steps").unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_state_resets_steps_of_new_inputs() {
+        let bundled = manifest::LayerManifest::bundled();
+        let mut state =
+            pipeline::PipelineState::new("test", &["2A".to_string()], &bundled, None, None);
+        for step in state.steps.iter_mut() {
+            step.status = pipeline::StepStatus::Done;
+        }
+        for layer in state.layers.iter_mut() {
+            layer.stage = pipeline::LayerStage::Styled;
+        }
+
+        let unchanged = state.clone().with_inputs(&bundled, None);
+        assert_eq!(unchanged.next_step(), None);
+
+        let mut fewer_layers = bundled.clone();
+        fewer_layers.sources[0].layers.pop();
+        let resumed = state.clone().with_inputs(&fewer_layers, None);
+        assert!(resumed.is_done(pipeline::PipelineStep::Download));
+        assert_eq!(resumed.next_step(), Some(pipeline::PipelineStep::Extract));
+        assert_eq!(resumed.layers.len(), state.layers.len() - 1);
+        assert!(resumed
+            .layers
+            .iter()
+            .all(|layer| layer.stage == pipeline::LayerStage::Extracted));
+
+        let clip_area = aoi::ClipArea {
+            area: aoi::AreaOfInterest::Commune {
+                query: "2A004".to_string(),
+            },
+            buffer: 0.0,
+        };
+        let clipped = state.with_inputs(&bundled, Some(clip_area));
+        assert_eq!(clipped.next_step(), Some(pipeline::PipelineStep::Extract));
+        assert!(clipped
+            .layers
+            .iter()
+            .all(|layer| layer.stage == pipeline::LayerStage::Pending));
+    }
+
+    #[test]
+    fn test_progress_event_overall_progress() {
+        let event = progress::ProgressEvent::new(
+            pipeline::PipelineStep::Extract,
+            0.5,
+            progress::Severity::Info,
+            "layer_done",
+        )
+        .with_layer("BATIMENT");
+        assert_eq!(event.step_index, 2);
+        assert_eq!(event.step_total, 7);
+        assert!((event.overall_progress() - 2.5 / 7.0).abs() < f32::EPSILON);
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["step"], "extract");
+        assert_eq!(json["layer"], "BATIMENT");
+    }
+
+    #[test]
+    fn test_pipeline_summary_lists_failed_layers() {
+        let mut state = pipeline::PipelineState::new(
+            "test",
+            &["2A".to_string()],
+            &manifest::LayerManifest::bundled(),
+            None,
+            None,
+        );
+        state.layers[1].error = Some("Layer not valid".to_string());
+        let summary = progress::PipelineSummary::from_state(&state);
+        assert!(!summary.completed);
+        assert_eq!(summary.layers_failed, vec!["TERRAIN_DE_SPORT".to_string()]);
+        assert_eq!(summary.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_pipeline_summary_reports_crs_mismatch() {
+        let mut state = pipeline::PipelineState::new(
+            "test",
+            &["974".to_string()],
+            &manifest::LayerManifest::bundled(),
+            None,
+            None,
+        );
+        assert_eq!(state.crs, "EPSG:2975");
+        state.layers[0].source_crs = Some("EPSG:2975".to_string());
+        state.layers[1].source_crs = Some("EPSG:4326".to_string());
+        state.layers[2].source_crs = Some(String::new());
+        let summary = progress::PipelineSummary::from_state(&state);
+        assert_eq!(summary.warnings.len(), 2);
+        assert!(summary.warnings[0].contains("EPSG:4326"));
+    }
+
+    #[test]
+    fn test_bundled_manifest_layers() {
+        let layer_manifest = manifest::LayerManifest::bundled();
+        assert_eq!(
+            layer_manifest.groups(),
+            vec!["combustion/Vegetation", "combustion/BDTOPO"]
+        );
+        let vegetation = &layer_manifest.sources[0].layers[0];
+        assert_eq!(vegetation.name, "FORMATION_VEGETALE");
+        assert_eq!(vegetation.project_layer(), "BDFORET");
+        assert_eq!(layer_manifest.sources[1].layers.len(), 13);
+        assert_eq!(
+            vegetation.style,
+            manifest::LayerStyle::Rules(manifest::LayerManifest::vegetation_symbology())
+        );
+    }
+
+    #[test]
+    fn test_symbology_rules_are_exclusive() {
+        let rules = manifest::LayerManifest::vegetation_symbology()
+            .resolve(&palette::Palette::vulcain())
+            .unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].color, (80, 200, 120));
+        assert_eq!(
+            rules[1].filter.as_deref(),
+            Some("(\"ESSENCE\" IN ('NC', 'NR')) AND NOT (\"ESSENCE\" IN ('Feuillus', 'Châtaignier', 'Chênes sempervirents', 'Chênes décidus', 'Hêtre'))")
+        );
+        assert_eq!(rules[2].filter, None);
+        assert_eq!(rules[2].color, (50, 200, 80));
+    }
+
+    #[test]
+    fn test_vulcain_palette_matches_colour_lists() {
+        let vulcain = palette::Palette::vulcain();
+        assert_eq!(vulcain.colors(), *utils::Vulcain_Colors);
+        assert_eq!(vulcain.parasite_colors(), *utils::Parasite_Colors);
+        assert_eq!(vulcain.color("road"), Some((0, 0, 0)));
+        assert_eq!(
+            palette::Palette::load(palette::DEFAULT_PALETTE).unwrap(),
+            vulcain
+        );
+    }
+
+    #[test]
+    fn test_palette_name_rejects_paths() {
+        assert!(palette::Palette::load("../outside").is_err());
+    }
+
+    #[test]
+    fn test_style_format_from_extension() {
+        assert_eq!(
+            styles::StyleFormat::from_path("styles/BATIMENT.QML").unwrap(),
+            styles::StyleFormat::Qml
+        );
+        assert_eq!(
+            styles::StyleFormat::from_path("route.sld").unwrap(),
+            styles::StyleFormat::Sld
+        );
+        assert!(styles::StyleFormat::from_path("route.json").is_err());
+    }
+
+    #[test]
+    fn test_bundled_styles_cover_topographic_layers() {
+        styles::install_default_styles().unwrap();
+        for layer in &manifest::LayerManifest::bundled().sources[1].layers {
+            assert!(
+                std::path::Path::new(&styles::default_style_path(&layer.name)).exists(),
+                "{} has no bundled style",
+                layer.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_map_export_antialiasing_defaults() {
+        let mut map_export: export::MapExport = serde_json::from_str(
+            r#"{
+                "extent": { "xmin": 0.0, "ymin": 0.0, "xmax": 100.0, "ymax": 100.0 },
+                "crs": "EPSG:2154",
+                "resolution": { "metres_per_pixel": 10.0 },
+                "format": "png",
+                "output_path": "tmp/export.png"
+            }"#,
+        )
+        .unwrap();
+        assert!(map_export.antialiasing());
+        map_export.format = export::ImageFormat::GeoTiff;
+        assert!(!map_export.antialiasing());
+        map_export.antialiasing = Some(true);
+        assert!(map_export.antialiasing());
+    }
+
+    #[test]
+    fn test_map_export_pixel_size() {
+        let mut map_export = export::MapExport {
+            extent: export::Extent {
+                xmin: 1210000.0,
+                ymin: 6066000.0,
+                xmax: 1234000.0,
+                ymax: 6093005.0,
+            },
+            crs: "EPSG:2154".to_string(),
+            resolution: export::Resolution::MetresPerPixel(10.0),
+            dpi: 96.0,
+            layers: Vec::new(),
+            format: export::ImageFormat::Png,
+            output_path: "tmp/export.png".to_string(),
+            antialiasing: Some(true),
+            world_file: false,
+        };
+        assert_eq!(map_export.pixel_size().unwrap(), (2400, 2701));
+        assert_eq!(map_export.pixel_extent().unwrap().ymin, 6065995.0);
+
+        map_export.resolution = export::Resolution::Scale(25000.0);
+        map_export.dpi = 254.0;
+        assert!((map_export.metres_per_pixel().unwrap() - 2.5).abs() < 1e-9);
+
+        map_export.extent.ymax = map_export.extent.ymin;
+        assert_eq!(
+            map_export.pixel_size(),
+            Err(export::ExportError::InvalidExtent)
+        );
+        map_export.extent.ymax = 6093000.0;
+        map_export.resolution = export::Resolution::MetresPerPixel(0.1);
+        assert!(matches!(
+            map_export.pixel_size(),
+            Err(export::ExportError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rendered_map_world_file() {
+        let rendered = export::RenderedMap {
+            path: "tmp/export.png".to_string(),
+            width: 100,
+            height: 50,
+            extent: export::Extent {
+                xmin: 1000.0,
+                ymin: 2000.0,
+                xmax: 2000.0,
+                ymax: 2500.0,
+            },
+            crs: "EPSG:2154".to_string(),
+        };
+        assert_eq!(
+            rendered.geotransform(),
+            [1000.0, 10.0, 0.0, 2500.0, 0.0, -10.0]
+        );
+        assert_eq!(rendered.world_file(), "10\n0\n0\n-10\n1005\n2495\n");
+        assert_eq!(
+            export::world_file_path("tmp/export.png", export::ImageFormat::Png),
+            "tmp/export.pgw"
+        );
+    }
+
+    #[test]
+    fn test_tile_grid_covers_extent() {
+        let grid = slicing::TileGrid {
+            extent: export::Extent {
+                xmin: 1210000.0,
+                ymin: 6066000.0,
+                xmax: 1234000.0,
+                ymax: 6093000.0,
+            },
+            crs: "EPSG:2154".to_string(),
+            tile_size: 1000,
+            metres_per_pixel: 10.0,
+            overlap: 10,
+        };
+        assert_eq!(grid.dimensions().unwrap(), (3, 3));
+        let tiles = grid.tiles(export::ImageFormat::Png).unwrap();
+        assert_eq!(tiles.len(), 9);
+        assert_eq!(
+            tiles[1].extent,
+            export::Extent {
+                xmin: 1219900.0,
+                ymin: 6082900.0,
+                xmax: 1230100.0,
+                ymax: 6093100.0,
+            }
+        );
+        assert_eq!(
+            tiles[1].file,
+            "r000_c001_1219900_6082900_1230100_6093100.png"
+        );
+        assert!(tiles[8].extent.ymin < grid.extent.ymin);
+    }
+
+    #[test]
+    fn test_mosaic_tile_windows() {
+        let grid = slicing::TileGrid {
+            extent: export::Extent {
+                xmin: 0.0,
+                ymin: 0.0,
+                xmax: 200.0,
+                ymax: 100.0,
+            },
+            crs: "EPSG:2154".to_string(),
+            tile_size: 100,
+            metres_per_pixel: 1.0,
+            overlap: 10,
+        };
+        let index = slicing::TileIndex {
+            rows: 1,
+            columns: 2,
+            format: export::ImageFormat::Png,
+            tiles: grid.tiles(export::ImageFormat::Png).unwrap(),
+            grid,
+        };
+        assert_eq!(index.pixel_size(), (200, 100));
+        let window = |column: usize, rule| {
+            let window = index
+                .tile_window(&index.tiles[column], "tiles", rule)
+                .unwrap();
+            (window.source_x, window.target_x, window.width)
+        };
+        assert_eq!(window(1, slicing::OverlapRule::Centre), (10, 100, 100));
+        assert_eq!(window(0, slicing::OverlapRule::First), (10, 0, 110));
+        assert_eq!(window(1, slicing::OverlapRule::First), (20, 110, 90));
+        assert_eq!(window(1, slicing::OverlapRule::Last), (0, 90, 110));
+        let first = index
+            .tile_window(&index.tiles[0], "tiles", slicing::OverlapRule::Centre)
+            .unwrap();
+        assert_eq!((first.source_y, first.target_y, first.height), (10, 0, 100));
+
+        let mut cropped = index.clone();
+        cropped.grid.extent.xmax = 150.0;
+        assert_eq!(cropped.pixel_size(), (150, 100));
+        let last = cropped
+            .tile_window(&cropped.tiles[1], "tiles", slicing::OverlapRule::Last)
+            .unwrap();
+        assert_eq!((last.source_x, last.target_x, last.width), (0, 90, 60));
+    }
+
+    #[test]
+    fn test_parse_tile_uri() {
+        let expected = tiles::TileRequest {
+            project: "Porto Vecchio".to_string(),
+            z: 12,
+            x: 2130,
+            y: 1520,
+        };
+        assert_eq!(
+            tiles::parse_tile_uri("tiles://Porto%20Vecchio/12/2130/1520.png"),
+            Some(expected.clone())
+        );
+        assert_eq!(
+            tiles::parse_tile_uri("https://tiles.localhost/Porto%20Vecchio/12/2130/1520.png"),
+            Some(expected)
+        );
+        assert_eq!(tiles::parse_tile_uri("tiles://../12/2130/1520.png"), None);
+        assert_eq!(tiles::parse_tile_uri("tiles://test/12/2130.png"), None);
+    }
+
+    #[test]
+    fn test_mask_classes_from_palette() {
+        let vulcain = palette::Palette::vulcain();
+        let classes = masks::mask_classes(masks::MaskEncoding::Classes, &vulcain);
+        assert_eq!(classes.len(), 6);
+        assert_eq!(classes[0].color, masks::BACKGROUND_COLOR);
+        assert_eq!(classes[4].name, "resineux");
+
+        let mut image = image::RgbImage::from_pixel(3, 1, image::Rgb([255, 255, 255]));
+        image.put_pixel(1, 0, image::Rgb([50, 200, 80]));
+        image.put_pixel(2, 0, image::Rgb([52, 198, 81]));
+        let mask = masks::classify_image(&image, masks::MaskEncoding::Classes, &classes);
+        assert_eq!(mask.as_raw(), &vec![0, 4, 4]);
+
+        let binary = masks::mask_classes(masks::MaskEncoding::Binary, &vulcain);
+        let mask = masks::classify_image(&image, masks::MaskEncoding::Binary, &binary);
+        assert_eq!(mask.as_raw(), &vec![0, 1, 1]);
+        assert_eq!(
+            masks::mask_file_name("combustion/BDTOPO"),
+            "combustion_BDTOPO.tif"
+        );
+    }
+
+    #[test]
+    fn test_layout_frames_fit_the_page() {
+        let export: layout::LayoutExport = serde_json::from_str(
+            r#"{"title": "Briefing", "extent": {"xmin": 0, "ymin": 0, "xmax": 1000, "ymax": 1000},
+                "crs": "EPSG:2154", "format": "pdf", "output_path": "tmp/briefing.pdf"}"#,
+        )
+        .unwrap();
+        assert_eq!(export.page_dimensions(), (297.0, 210.0));
+        assert_eq!(export.dpi, layout::DEFAULT_LAYOUT_DPI);
+        assert_eq!(export.legend_title, layout::DEFAULT_LEGEND_TITLE);
+
+        let frames = export.frames();
+        let (width, height) = export.page_dimensions();
+        for frame in [
+            frames.title,
+            frames.map,
+            frames.legend,
+            frames.scale_bar,
+            frames.attribution,
+        ] {
+            assert!(frame.x >= 0.0 && frame.x + frame.width <= width);
+            assert!(frame.y >= 0.0 && frame.y + frame.height <= height);
+        }
+        assert!(frames.legend.x >= frames.map.x + frames.map.width);
+        assert!(frames.scale_bar.y >= frames.map.y + frames.map.height);
+
+        let portrait = layout::LayoutExport {
+            orientation: layout::Orientation::Portrait,
+            page_size: layout::PageSize::A3,
+            ..export
+        };
+        assert_eq!(portrait.page_dimensions(), (297.0, 420.0));
+    }
+
+    #[test]
+    fn test_layout_attribution_lists_ign_editions() {
+        let edition = layout::source_edition(
+            "https://data.geopf.fr/telechargement/download/BDTOPO/BDTOPO_3-4_TOUSTHEMES_SHP_LAMB93_D02A_2024-06-15/BDTOPO_3-4_TOUSTHEMES_SHP_LAMB93_D02A_2024-06-15.7z",
+        )
+        .unwrap();
+        assert_eq!(edition.product, "BDTOPO");
+        assert_eq!(edition.version, "3.4");
+        assert_eq!(edition.date, "2024-06-15");
+        assert!(layout::source_edition("https://example.com/archive.7z").is_none());
+
+        let archives: Vec<pipeline::ArchiveState> = ["02A", "02B"]
+            .iter()
+            .map(|code| pipeline::ArchiveState {
+                source: "BDFORET".to_string(),
+                code: code.to_string(),
+                url: format!("https://data.geopf.fr/telechargement/download/BDFORET/BDFORET_2-0__SHP_LAMB93_D{}_2017-05-10.7z", code),
+            })
+            .collect();
+        assert_eq!(
+            layout::attribution(&archives),
+            "Sources: IGN BDFORET 2.0 (2017-05-10)"
+        );
+        assert_eq!(layout::attribution(&[]), "Source: IGN");
+    }
+
+    #[test]
+    fn test_batch_areas_from_json() {
+        let batch: batch::BatchExport = serde_json::from_str(
+            r#"{"areas": {"kind": "communes", "codes": ["2A004", "2A041"]},
+                "preset": "Overview 50 m", "output_folder": "tmp/batch"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            batch.areas,
+            batch::BatchAreas::Communes {
+                codes: vec!["2A004".to_string(), "2A041".to_string()]
+            }
+        );
+        assert_eq!(batch.buffer, 0.0);
+    }
+
+    #[test]
+    fn test_batch_area_folders_are_unique() {
+        let mut folders: Vec<String> = Vec::new();
+        for name in ["Ajaccio", "Porto-Vecchio", "Ajaccio", "L'Île-Rousse", " "] {
+            let folder = batch::area_folder_name(name, &folders);
+            folders.push(folder);
+        }
+        assert_eq!(
+            folders,
+            vec![
+                "Ajaccio",
+                "Porto-Vecchio",
+                "Ajaccio_2",
+                "L_Île-Rousse",
+                "area"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bundled_export_presets() {
+        let vulcain = presets::get_preset("Vulcain 5 m").unwrap();
+        assert_eq!(vulcain.metres_per_pixel, 5.0);
+        assert_eq!(vulcain.tile_size, Some(2048));
+        assert_eq!(vulcain.format, export::ImageFormat::GeoTiff);
+        assert!(vulcain.post_processing.snap_to_palette);
+        assert!(presets::get_preset("Overview 50 m")
+            .unwrap()
+            .tile_size
+            .is_none());
+        assert!(presets::get_preset("Unknown").is_err());
+    }
+
+    #[test]
+    fn test_export_preset_defaults() {
+        let preset: presets::ExportPreset = serde_json::from_str(
+            r#"{"name": "Overview 25 m", "metres_per_pixel": 25.0,
+                "post_processing": {}, "format": "png"}"#,
+        )
+        .unwrap();
+        assert_eq!(preset.tile_size, None);
+        assert_eq!(preset.overlap, 0);
+        assert_eq!(preset.palette, None);
+        assert!(preset.layers.is_empty());
+        assert!(!preset.post_processing.antialiasing);
+    }
+
+    #[test]
+    fn test_class_raster_burns_by_priority() {
+        let extent = export::Extent {
+            xmin: 0.0,
+            ymin: 0.0,
+            xmax: 10.0,
+            ymax: 10.0,
+        };
+        let mut raster = rasterize::ClassRaster::new(extent, 1.0).unwrap();
+        let square = rasterize::Geometry::Polygon(vec![
+            vec![(2.0, 2.0), (8.0, 2.0), (8.0, 8.0), (2.0, 8.0), (2.0, 2.0)],
+            vec![(4.0, 4.0), (6.0, 4.0), (6.0, 6.0), (4.0, 6.0), (4.0, 4.0)],
+        ]);
+        raster.burn(&square, 4, 0, 1.0);
+        assert_eq!(raster.get(2, 2), 4);
+        assert_eq!(raster.get(7, 7), 4);
+        assert_eq!(raster.get(1, 1), 0);
+        assert_eq!(raster.get(5, 5), 0);
+
+        let road = rasterize::Geometry::Lines(vec![vec![(0.0, 2.5), (10.0, 2.5)]]);
+        raster.burn(&road, 1, 80, 1.0);
+        assert_eq!(raster.get(3, 7), 1);
+        let ground = rasterize::Geometry::Polygon(vec![vec![
+            (0.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+        ]]);
+        raster.burn(&ground, 2, 10, 1.0);
+        assert_eq!(raster.get(3, 7), 1);
+        assert_eq!(raster.get(3, 3), 2);
+        assert_eq!(raster.as_raw().iter().filter(|&&id| id == 0).count(), 0);
+    }
+
+    #[test]
+    fn test_class_raster_skips_non_finite_vertices() {
+        let extent = export::Extent {
+            xmin: 0.0,
+            ymin: 0.0,
+            xmax: 10.0,
+            ymax: 10.0,
+        };
+        let mut raster = rasterize::ClassRaster::new(extent, 1.0).unwrap();
+        let square = rasterize::Geometry::Polygon(vec![vec![
+            (2.0, 2.0),
+            (8.0, 2.0),
+            (f64::NAN, 5.0),
+            (8.0, 8.0),
+            (2.0, 8.0),
+            (2.0, 2.0),
+        ]]);
+        raster.burn(&square, 4, 0, 1.0);
+        assert_eq!(raster.get(5, 5), 4);
+        assert_eq!(raster.get(1, 1), 0);
+    }
+
+    #[test]
+    fn test_class_raster_rejects_huge_extents() {
+        let department = export::Extent {
+            xmin: 1160000.0,
+            ymin: 6040000.0,
+            xmax: 1250000.0,
+            ymax: 6180000.0,
+        };
+        let error = rasterize::ClassRaster::new(department, 1.0).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref::<export::ExportError>(),
+            Some(export::ExportError::TooLarge {
+                width: 90000,
+                height: 140000
+            })
+        ));
+        assert!(rasterize::ClassRaster::new(department, 5.0).is_err());
+        let raster = rasterize::ClassRaster::new(department, 20.0).unwrap();
+        assert_eq!((raster.width, raster.height), (4500, 7000));
+    }
+
+    #[test]
+    fn test_symbology_unknown_colour() {
+        let mut symbology = manifest::LayerManifest::vegetation_symbology();
+        symbology.rules[0].color = "lave".to_string();
+        assert!(symbology.resolve(&palette::Palette::vulcain()).is_err());
+    }
+
+    #[test]
+    fn test_manifest_filter_mode_defaults_to_subset() {
+        let layer: manifest::ManifestLayer = serde_json::from_str(
+            r#"{ "name": "TRONCON_DE_ROUTE", "filter": "\"NATURE\" = 'Route à 1 chaussée'", "style": "topographic" }"#,
+        )
+        .unwrap();
+        assert_eq!(layer.filter_mode, manifest::FilterMode::Subset);
+        assert_eq!(layer.project_layer(), "TRONCON_DE_ROUTE");
+        assert_eq!(layer.color(), manifest::DEFAULT_TOPO_COLOR);
+        assert_eq!(layer.width, None);
+        assert_eq!(layer.draw_order, 0);
+    }
+
+    #[test]
+    fn test_bundled_manifest_draw_order() {
+        let layer_manifest = manifest::LayerManifest::bundled();
+        let draw_order = |name: &str| {
+            layer_manifest
+                .sources
+                .iter()
+                .flat_map(|source| source.layers.iter())
+                .find(|layer| layer.name == name)
+                .unwrap()
+                .draw_order
+        };
+        assert!(draw_order("TRONCON_DE_ROUTE") > draw_order("BATIMENT"));
+        assert!(draw_order("BATIMENT") > draw_order("FORMATION_VEGETALE"));
+    }
+
+    #[test]
+    fn test_manifest_profile_name_rejects_paths() {
+        let result = manifest::LayerManifest::bundled().save("../outside");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_bounding_box_geojson_closed_ring() {
+        let geojson =
+            aoi::bounding_box_geojson(1210000.0, 6066000.0, 1234000.0, 6093000.0, "EPSG:2154");
+        let ring = geojson["features"][0]["geometry"]["coordinates"][0]
+            .as_array()
+            .unwrap();
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring[0], ring[4]);
+        assert_eq!(
+            geojson["crs"]["properties"]["name"],
+            "urn:ogc:def:crs:EPSG::2154"
+        );
+    }
+
+    #[test]
+    fn test_departement_crs() {
+        assert_eq!(utils::get_departement_crs("2A"), utils::LAMBERT_93);
+        assert_eq!(utils::get_departement_crs("974").epsg, "EPSG:2975");
+        assert_eq!(
+            utils::get_departement_crs("971"),
+            utils::get_departement_crs("972")
+        );
+    }
+
+    #[test]
+    fn test_clip_area_deserialization() {
+        let clip_area: aoi::ClipArea = serde_json::from_str(
+            r#"{ "area": { "kind": "commune", "query": "2A247" }, "buffer": 500 }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            clip_area.area,
+            aoi::AreaOfInterest::Commune {
+                query: "2A247".to_string()
+            }
+        );
+        assert_eq!(clip_area.buffer, 500.0);
+    }
+
+    #[test]
+    fn test_departement_codes_are_trimmed() {
+        assert_eq!(
+            utils::get_departement_name("58"),
+            Some("Nièvre".to_string())
+        );
+        assert!(utils::DEPARTEMENTS.keys().all(|code| code.trim() == code));
+    }
+
+    #[test]
+    fn test_regions_cover_every_departement() {
+        for code in utils::DEPARTEMENTS.keys() {
+            assert!(
+                territory::get_departement_region(code).is_some(),
+                "{} has no region",
+                code
+            );
+        }
+        assert_eq!(
+            territory::get_departement_region("2A").unwrap().name,
+            "Corse"
+        );
+    }
+
+    #[test]
+    fn test_normalize_name() {
+        assert_eq!(
+            territory::normalize_name("Saint-Étienne-de-Tinée"),
+            "saint etienne de tinee"
+        );
+        assert_eq!(
+            territory::normalize_name("L'Haÿ-les-Roses"),
+            "l hay les roses"
+        );
+    }
+
+    #[test]
+    fn test_search_communes() {
+        let communes: Vec<territory::Commune> = serde_json::from_str(
+            r#"[
+                { "code": "2A004", "nom": "Ajaccio", "codesPostaux": ["20000", "20090"], "codeDepartement": "2A", "codeRegion": "94", "codeEpci": "242010056" },
+                { "code": "42218", "nom": "Saint-Étienne", "codesPostaux": ["42000"], "codeDepartement": "42", "codeRegion": "84" },
+                { "code": "06120", "nom": "Saint-Étienne-de-Tinée", "codesPostaux": ["06660"], "codeDepartement": "06", "codeRegion": "93" }
+            ]"#,
+        )
+        .unwrap();
+        let reference = territory::AdminReference {
+            communes,
+            epcis: vec![],
+        };
+
+        let found = reference.search_communes("saint etienne", 10);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].code, "42218");
+        assert_eq!(reference.search_communes("20090", 10)[0].code, "2A004");
+        assert_eq!(reference.search_communes("2a004", 10)[0].code, "2A004");
+
+        let resolved = reference.resolve_commune(found[1]);
+        assert_eq!(resolved.commune.departement, "06");
+        assert_eq!(
+            resolved.departement_name,
+            Some("Alpes-Maritimes".to_string())
+        );
+        assert_eq!(
+            resolved.aoi.area,
+            aoi::AreaOfInterest::Commune {
+                query: "06120".to_string()
+            }
+        );
+    }
+
     // test IGN
 
     #[tokio::test]
@@ -126,6 +945,16 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_get_departement_shp_topo_overseas_url() {
+        let result = web_request::get_departement_shp_file_url(
+            "974",
+            "https://geoservices.ign.fr/bdtopo#telechargementgpkgreg",
+        )
+        .await;
+        assert!(result.unwrap().contains("_RGR92UTM40S_D974_"));
+    }
+
     #[tokio::test]
     async fn test_get_departement_shp_topo_no_file_found() {
         let result = web_request::get_departement_shp_file_url(
@@ -140,7 +969,7 @@ mod tests {
     #[tokio::test]
     async fn test_download_shp_file_foret_success() {
         let url = "https://data.geopf.fr/telechargement/download/BDFORET/BDFORET_2-0__SHP_LAMB93_D02A_2017-05-10/BDFORET_2-0__SHP_LAMB93_D02A_2017-05-10.7z";
-        match web_request::download_shp_file(url, "2A").await {
+        match web_request::download_shp_file(url, "2A", &CancellationToken::new()).await {
             Ok(_) => {
                 assert!(std::path::Path::new("tmp/BDFORET_2A.7z").exists());
             }
@@ -153,7 +982,7 @@ mod tests {
     #[tokio::test]
     async fn test_download_shp_file_topo_success() {
         let url = "https://data.geopf.fr/telechargement/download/BDTOPO/BDTOPO_3-4_TOUSTHEMES_SHP_LAMB93_D02A_2024-06-15/BDTOPO_3-4_TOUSTHEMES_SHP_LAMB93_D02A_2024-06-15.7z";
-        match web_request::download_shp_file(url, "2A").await {
+        match web_request::download_shp_file(url, "2A", &CancellationToken::new()).await {
             Ok(_) => {
                 assert!(std::path::Path::new("tmp/BDTOPO_2A.7z").exists());
             }
@@ -163,12 +992,38 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_download_commune_contour_success() {
+        let result =
+            web_request::download_commune_contour("Porto-Vecchio", "tmp/porto_vecchio.geojson")
+                .await;
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+        assert!(std::path::Path::new("tmp/porto_vecchio.geojson").exists());
+    }
+
+    #[tokio::test]
+    async fn test_suggest_departements_across_border() {
+        pyo3::prepare_freethreaded_python();
+        // around Solenzara, on the border between Corse-du-Sud and Haute-Corse
+        let clip_area = aoi::ClipArea {
+            area: aoi::AreaOfInterest::BoundingBox {
+                xmin: 1220000.0,
+                ymin: 6110000.0,
+                xmax: 1232000.0,
+                ymax: 6122000.0,
+            },
+            buffer: 0.0,
+        };
+        let result = aoi::suggest_departements("2A", &clip_area).await;
+        assert_eq!(result.unwrap(), vec!["2B"]);
+    }
+
     // test qgis api wrapper
     #[test]
     fn test_qgis_api_create_blank_project_success() {
         // for test purpose, we need to prepare the python environment
         pyo3::prepare_freethreaded_python();
-        let result = qgis_api_wrapper::create_blank_project("test");
+        let result = qgis_api_wrapper::create_blank_project("test", "EPSG:2154");
         assert!(result.is_ok(), "Error: {:?}", result.err());
     }
 
@@ -186,9 +1041,20 @@ mod tests {
 
     #[test]
     fn test_setup_veg_layer_success() {
+        pyo3::prepare_freethreaded_python();
+        let result = qgis_api_wrapper::setup_basic_veg_layer(
+            "resources/QGIS/test/test.qgz",
+            "BDFORET_2A",
+            &palette::Palette::vulcain(),
+        );
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+    }
+
+    #[test]
+    fn test_set_layer_draw_order_success() {
         pyo3::prepare_freethreaded_python();
         let result =
-            qgis_api_wrapper::setup_basic_veg_layer("resources/QGIS/test/test.qgz", "BDFORET_2A");
+            qgis_api_wrapper::set_layer_draw_order("resources/QGIS/test/test.qgz", "BDFORET_2A", 0);
         assert!(result.is_ok(), "Error: {:?}", result.err());
     }
 
@@ -211,6 +1077,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set_layer_filter_success() {
+        pyo3::prepare_freethreaded_python();
+        let result = qgis_api_wrapper::set_layer_filter(
+            "resources/QGIS/test/test.qgz",
+            "BDFORET_2A",
+            "\"ESSENCE\" = 'Feuillus'",
+        );
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+        let filter =
+            qgis_api_wrapper::get_layer_filter("resources/QGIS/test/test.qgz", "BDFORET_2A");
+        assert_eq!(filter.unwrap(), "\"ESSENCE\" = 'Feuillus'");
+        let result =
+            qgis_api_wrapper::set_layer_filter("resources/QGIS/test/test.qgz", "BDFORET_2A", "");
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+    }
+
+    #[test]
+    fn test_update_layer_categories_keeps_other_categories() {
+        pyo3::prepare_freethreaded_python();
+        let before =
+            qgis_api_wrapper::get_layer_renderer("resources/QGIS/test/test.qgz", "BDFORET_2A")
+                .unwrap();
+        let result = qgis_api_wrapper::update_layer_categories(
+            "resources/QGIS/test/test.qgz",
+            "BDFORET_2A",
+            "ESSENCE",
+            &[
+                symbology::CategoryUpdate {
+                    value: "Feuillus".to_string(),
+                    color: Some("255,0,0,255".to_string()),
+                    ..Default::default()
+                },
+                symbology::CategoryUpdate {
+                    value: "NC".to_string(),
+                    visible: Some(false),
+                    ..Default::default()
+                },
+            ],
+        );
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+
+        let after =
+            qgis_api_wrapper::get_layer_renderer("resources/QGIS/test/test.qgz", "BDFORET_2A")
+                .unwrap();
+        assert_eq!(after.kind, "categorizedSymbol");
+        assert_eq!(after.field.as_deref(), Some("ESSENCE"));
+        assert_eq!(after.symbol_type, symbology::SymbolType::Fill);
+        for category in &after.categories {
+            match category.value.as_str() {
+                "Feuillus" => assert_eq!(category.color, "255,0,0,255"),
+                "NC" => assert!(!category.visible),
+                value if before.field.as_deref() == Some("ESSENCE") => {
+                    let previous = before.categories.iter().find(|c| c.value == value);
+                    assert_eq!(previous.map(|c| &c.color), Some(&category.color));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_layer_style() {
+        pyo3::prepare_freethreaded_python();
+        let result = qgis_api_wrapper::save_layer_style(
+            "resources/QGIS/test/test.qgz",
+            "BDFORET_2A",
+            "tmp/BDFORET_2A.qml",
+        );
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+        let result = qgis_api_wrapper::load_layer_style(
+            "resources/QGIS/test/test.qgz",
+            "BDFORET_2A",
+            "tmp/BDFORET_2A.qml",
+        );
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+    }
+
     #[test]
     fn test_edit_layer_field_success() {
         pyo3::prepare_freethreaded_python();
@@ -227,14 +1171,107 @@ mod tests {
     #[test]
     fn test_porto_vecchio_map_success() {
         pyo3::prepare_freethreaded_python();
-        let result = qgis_api_wrapper::export_map_to_jpg(
+        let map_export = export::MapExport {
+            extent: export::Extent {
+                xmin: 1210000.0,
+                ymin: 6066000.0,
+                xmax: 1234000.0,
+                ymax: 6093000.0,
+            },
+            crs: "EPSG:2154".to_string(),
+            resolution: export::Resolution::MetresPerPixel(10.0),
+            dpi: 96.0,
+            layers: Vec::new(),
+            format: export::ImageFormat::Jpeg,
+            output_path: "tmp/POV_veg.jpg".to_string(),
+            antialiasing: Some(true),
+            world_file: false,
+        };
+        let result = qgis_api_wrapper::export_map("resources/QGIS/test/Corse.qgz", &map_export);
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+        let (width, height) = image::image_dimensions("tmp/POV_veg.jpg").unwrap();
+        assert_eq!((width, height), (2400, 2700));
+    }
+
+    #[test]
+    fn test_porto_vecchio_geotiff_success() {
+        pyo3::prepare_freethreaded_python();
+        let map_export = export::MapExport {
+            extent: export::Extent {
+                xmin: 1210000.0,
+                ymin: 6066000.0,
+                xmax: 1234000.0,
+                ymax: 6093000.0,
+            },
+            crs: "EPSG:2154".to_string(),
+            resolution: export::Resolution::MetresPerPixel(20.0),
+            dpi: 96.0,
+            layers: Vec::new(),
+            format: export::ImageFormat::GeoTiff,
+            output_path: "tmp/POV_veg.tif".to_string(),
+            antialiasing: Some(false),
+            world_file: false,
+        };
+        let result = qgis_api_wrapper::export_map("resources/QGIS/test/Corse.qgz", &map_export);
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+        assert!(std::path::Path::new("tmp/POV_veg.tif").exists());
+        assert!(!std::path::Path::new("tmp/POV_veg.tif.render.png").exists());
+    }
+
+    #[test]
+    fn test_porto_vecchio_slicing_success() {
+        pyo3::prepare_freethreaded_python();
+        let slice = slicing::SliceExport {
+            grid: slicing::TileGrid {
+                extent: export::Extent {
+                    xmin: 1210000.0,
+                    ymin: 6066000.0,
+                    xmax: 1234000.0,
+                    ymax: 6093000.0,
+                },
+                crs: "EPSG:2154".to_string(),
+                tile_size: 1024,
+                metres_per_pixel: 20.0,
+                overlap: 0,
+            },
+            layers: Vec::new(),
+            format: export::ImageFormat::Png,
+            antialiasing: false,
+            output_folder: "tmp/POV_tiles".to_string(),
+        };
+        let result = slicing::slice_map(
             "resources/QGIS/test/Corse.qgz",
-            1210000.0,
-            6093000.0,
-            1234000.0,
-            6066000.0,
-            "tmp/POV_veg.jpg",
+            &slice,
+            &CancellationToken::new(),
+            |_, _| {},
         );
         assert!(result.is_ok(), "Error: {:?}", result.err());
+        assert_eq!(result.unwrap().tiles.len(), 4);
+        assert!(std::path::Path::new("tmp/POV_tiles/index.json").exists());
+
+        let result = slicing::mosaic(
+            "tmp/POV_tiles/index.json",
+            None,
+            "tmp/POV_mosaic.tif",
+            slicing::OverlapRule::Centre,
+        );
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+        let mosaic = result.unwrap();
+        assert_eq!((mosaic.width, mosaic.height), (1200, 1350));
+        assert_eq!(mosaic.extent, slice.grid.extent);
+    }
+
+    #[test]
+    fn test_generate_tile_pyramid_success() {
+        pyo3::prepare_freethreaded_python();
+        let pyramid = tiles::TilePyramid {
+            min_zoom: 8,
+            max_zoom: 9,
+            storage: tiles::TileStorage::MbTiles,
+            extent: None,
+        };
+        let result = tiles::generate_pyramid("test", "resources/QGIS/test/test.qgz", &pyramid);
+        assert!(result.is_ok(), "Error: {:?}", result.err());
+        assert!(std::path::Path::new(&tiles::mbtiles_path("test")).exists());
     }
 }
@@ -5,6 +5,7 @@ import HomeScreen from "@/components/HomeScreen/HomeScreen";
 import MainScreen from "@/components/MainScreen";
 import CreationLoaderScreen from "@/components/CreationLoaderScreen";
 import NewProjectScreen from "@/components/NewProjectScreen";
+import { ClipArea } from "@/components/types/map";
 
 type ScreenType = "home" | "newProject" | "main" | "loader";
 
@@ -17,12 +18,27 @@ const screenComponents: Record<ScreenType, React.ComponentType<any>> = {
 
 const App = () => {
   const [currentScreen, setCurrentScreen] = useState<ScreenType>("home");
-  const [department, setDepartment] = useState("");
+  const [departments, setDepartments] = useState<string[]>([]);
   const [projectName, setProjectName] = useState("");
+  const [profile, setProfile] = useState("default");
+  const [palette, setPalette] = useState("vulcain");
+  const [crs, setCrs] = useState("");
+  const [aoi, setAoi] = useState<ClipArea | null>(null);
 
-  const handleLoading = (dept: string, project: string) => {
-    setDepartment(dept);
+  const handleLoading = (
+    depts: string[],
+    project: string,
+    profile: string,
+    palette: string,
+    crs: string,
+    aoi: ClipArea | null
+  ) => {
+    setDepartments(depts);
     setProjectName(project);
+    setProfile(profile);
+    setPalette(palette);
+    setCrs(crs);
+    setAoi(aoi);
     setCurrentScreen("loader");
   };
 
@@ -32,8 +48,13 @@ const App = () => {
     <div className="App">
       {currentScreen === "loader" ? (
         <CreationLoaderScreen
-          department={department}
+          departments={departments}
           projectName={projectName}
+          profile={profile}
+          palette={palette}
+          crs={crs}
+          aoi={aoi}
+          onGoBack={() => setCurrentScreen("home")}
         />
       ) : (
         <ScreenComponent
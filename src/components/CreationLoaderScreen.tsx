@@ -1,31 +1,105 @@
 "use client";
 import { useState, useEffect, useRef } from "react";
-import { Map, Loader2, CheckCircle2 } from "lucide-react";
+import { Map, Loader2, CheckCircle2, X, RotateCw } from "lucide-react";
+import { Button } from "@/components/ui/button";
 import { Card, CardContent } from "@/components/ui/card";
+import { Progress } from "@/components/ui/progress";
 import { invoke } from "@tauri-apps/api/tauri";
 import { listen } from "@tauri-apps/api/event";
+import { ClipArea } from "@/components/types/map";
 
 const steps = [
   "Recherche des fichiers",
   "Téléchargement des données",
+  "Extraction des couches",
   "Initialisation du projet",
-  "Preparation des Couches",
+  "Chargement des couches",
+  "Mise en forme des couches",
   "Finalisation",
 ];
 
+interface ProgressEvent {
+  step: string;
+  step_index: number;
+  step_total: number;
+  sub_progress: number;
+  layer: string | null;
+  severity: "info" | "warning" | "error";
+  message_key: string;
+}
+
+interface PipelineSummary {
+  completed: boolean;
+  warnings: string[];
+}
+
+interface LayerState {
+  project_layer: string;
+  error: string | null;
+}
+
+interface PipelineState {
+  layers: LayerState[];
+}
+
 interface CreationLoaderScreenProps {
-  department: string;
+  departments: string[];
   projectName: string;
+  profile: string;
+  palette: string;
+  crs: string;
+  aoi: ClipArea | null;
+  onGoBack: () => void;
 }
 
 export default function CreationLoaderScreen({
-  department,
+  departments,
   projectName,
+  profile,
+  palette,
+  crs,
+  aoi,
+  onGoBack,
 }: CreationLoaderScreenProps) {
   const [currentStep, setCurrentStep] = useState(-1);
+  const [percentage, setPercentage] = useState(0);
+  const [currentLayer, setCurrentLayer] = useState<string | null>(null);
+  const [warnings, setWarnings] = useState<string[]>([]);
   const [error, setError] = useState<string | null>(null);
+  const [isCancelling, setIsCancelling] = useState(false);
+  const [failedLayers, setFailedLayers] = useState<LayerState[]>([]);
   const isInitialMount = useRef(true);
 
+  const updateFailedLayers = (state: PipelineState | null) => {
+    setFailedLayers(state ? state.layers.filter((layer) => layer.error) : []);
+  };
+
+  const processMapCreation = () => {
+    setError(null);
+    invoke("open_new_project", {
+      codes: departments,
+      name: projectName,
+      profile,
+      palette,
+      crs: crs || null,
+      aoi,
+    })
+      .catch((err) => {
+        setError(err.message);
+      })
+      .finally(() => {
+        invoke("get_pipeline_state", { name: projectName })
+          .then((state) => updateFailedLayers(state as PipelineState | null))
+          .catch((err) => console.error(err));
+      });
+  };
+
+  const handleRetryLayer = (layer: string) => {
+    invoke("retry_layer", { name: projectName, layer })
+      .then((state) => updateFailedLayers(state as PipelineState))
+      .catch((err) => console.error(err));
+  };
+
   useEffect(() => {
     if (!isInitialMount.current) return;
 
@@ -33,25 +107,39 @@ export default function CreationLoaderScreen({
     console.log("Initial Mount");
 
     const handleProgressUpdate = (event: any) => {
-      const step = event.payload as string;
-      console.log("Progress Update", step);
-      const stepIndex = steps.indexOf(step);
-      if (stepIndex !== -1) {
-        setCurrentStep(stepIndex);
-      }
+      const progress = event.payload as ProgressEvent;
+      console.log("Progress Update", progress);
+      setCurrentStep(progress.step_index);
+      setPercentage(
+        ((progress.step_index + progress.sub_progress) / progress.step_total) *
+          100
+      );
+      setCurrentLayer(progress.layer);
     };
 
-    const setupListener = async () => {
-      const unlisten = await listen("progress-update", handleProgressUpdate);
-      return unlisten;
+    const handleProgressSummary = (event: any) => {
+      const summary = event.payload as PipelineSummary;
+      setWarnings(summary.warnings);
+      if (summary.completed) {
+        setCurrentStep(steps.length);
+      }
     };
 
-    const processMapCreation = () => {
-      invoke("open_new_project", { code: department, name: projectName }).catch(
-        (err) => {
-          setError(err.message);
-        }
+    const setupListener = async () => {
+      const unlistenProgress = await listen(
+        "progress-update",
+        handleProgressUpdate
       );
+      const unlistenSummary = await listen(
+        "progress-summary",
+        handleProgressSummary
+      );
+      const unlistenCancelled = await listen("cancelled", () => onGoBack());
+      return () => {
+        unlistenProgress();
+        unlistenSummary();
+        unlistenCancelled();
+      };
     };
 
     let unlisten: () => void;
@@ -66,7 +154,15 @@ export default function CreationLoaderScreen({
         unlisten();
       }
     };
-  }, [department, projectName]);
+  }, [departments, projectName]);
+
+  const handleCancel = () => {
+    setIsCancelling(true);
+    invoke("cancel_project_creation", { name: projectName }).catch((err) => {
+      setIsCancelling(false);
+      setError(err.message);
+    });
+  };
 
   return (
     <div className="flex flex-col items-center justify-center min-h-screen bg-[#2D2D30] text-[#CCCCCC] p-4">
@@ -93,6 +189,10 @@ export default function CreationLoaderScreen({
                 {error ? "Failed to load data." : "Loading data..."}
               </span>
             </div>
+            <Progress value={percentage} />
+            {currentLayer && (
+              <p className="text-sm text-gray-400">{currentLayer}</p>
+            )}
             <div className="space-y-2">
               {steps.map((step, index) => (
                 <div key={index} className="flex items-center space-x-2">
@@ -113,6 +213,57 @@ export default function CreationLoaderScreen({
                 </div>
               ))}
             </div>
+            {warnings.length > 0 && (
+              <div className="space-y-1 text-left">
+                {warnings.map((warning, index) => (
+                  <p key={index} className="text-xs text-yellow-500">
+                    {warning}
+                  </p>
+                ))}
+              </div>
+            )}
+            {failedLayers.length > 0 && (
+              <div className="space-y-2">
+                {failedLayers.map((layer) => (
+                  <div
+                    key={layer.project_layer}
+                    className="flex items-center justify-between"
+                  >
+                    <span className="text-sm text-red-500">
+                      {layer.project_layer}
+                    </span>
+                    <Button
+                      variant="ghost"
+                      size="icon"
+                      onClick={() => handleRetryLayer(layer.project_layer)}
+                    >
+                      <RotateCw className="h-4 w-4" />
+                    </Button>
+                  </div>
+                ))}
+              </div>
+            )}
+            {error && (
+              <Button
+                variant="ghost"
+                onClick={processMapCreation}
+                className="w-full"
+              >
+                <RotateCw className="mr-2 h-4 w-4" />
+                Réessayer
+              </Button>
+            )}
+            {!error && (
+              <Button
+                variant="ghost"
+                onClick={handleCancel}
+                disabled={isCancelling}
+                className="w-full"
+              >
+                <X className="mr-2 h-4 w-4" />
+                {isCancelling ? "Annulation..." : "Annuler"}
+              </Button>
+            )}
           </div>
         </CardContent>
       </Card>
@@ -2,6 +2,7 @@
 import { useState, useEffect } from "react";
 import { Button } from "@/components/ui/button";
 import { invoke } from "@tauri-apps/api/tauri";
+import { open } from "@tauri-apps/api/dialog";
 import { Input } from "@/components/ui/input";
 import {
   Select,
@@ -11,11 +12,33 @@ import {
   SelectValue,
 } from "@/components/ui/select";
 import { Card, CardContent } from "@/components/ui/card";
-import { Folder, ChevronRight, Building2, ArrowLeft } from "lucide-react";
+import {
+  Folder,
+  ChevronRight,
+  Building2,
+  ArrowLeft,
+  Layers,
+  Pencil,
+  Save,
+  Crop,
+  MapPinned,
+  Search,
+  Palette,
+} from "lucide-react";
+import { ClipArea, CommuneMatch } from "@/components/types/map";
+
+type AoiKind = "none" | "bounding_box" | "commune" | "polygon_file";
 
 interface NewProjectScreenProps {
   onGoBack: () => void;
-  onLoading: (department: string, projectName: string) => void;
+  onLoading: (
+    departments: string[],
+    projectName: string,
+    profile: string,
+    palette: string,
+    crs: string,
+    aoi: ClipArea | null
+  ) => void;
 }
 
 const NewProjectScreen: React.FC<NewProjectScreenProps> = ({
@@ -25,6 +48,23 @@ const NewProjectScreen: React.FC<NewProjectScreenProps> = ({
   const [projectName, setProjectName] = useState("");
   const [department, setDepartment] = useState("");
   const [departments, setDepartments] = useState<{ [key: string]: string }>({});
+  const [profile, setProfile] = useState("default");
+  const [profiles, setProfiles] = useState<string[]>([]);
+  const [palette, setPalette] = useState("vulcain");
+  const [palettes, setPalettes] = useState<string[]>([]);
+  const [manifest, setManifest] = useState<string | null>(null);
+  const [manifestError, setManifestError] = useState<string | null>(null);
+  const [aoiKind, setAoiKind] = useState<AoiKind>("none");
+  const [bbox, setBbox] = useState(["", "", "", ""]);
+  const [commune, setCommune] = useState("");
+  const [polygonPath, setPolygonPath] = useState("");
+  const [buffer, setBuffer] = useState("0");
+  const [crs, setCrs] = useState("");
+  const [suggestions, setSuggestions] = useState<{ [key: string]: string }>({});
+  const [extraDepartments, setExtraDepartments] = useState<string[]>([]);
+  const [suggestionError, setSuggestionError] = useState<string | null>(null);
+  const [communeQuery, setCommuneQuery] = useState("");
+  const [communeMatches, setCommuneMatches] = useState<CommuneMatch[]>([]);
 
   useEffect(() => {
     const fetchDepartments = () => {
@@ -35,8 +75,113 @@ const NewProjectScreen: React.FC<NewProjectScreenProps> = ({
         });
     };
     fetchDepartments();
+    invoke("get_manifest_profiles")
+      .then((res) => setProfiles(res as string[]))
+      .catch((err) => {
+        console.error(err);
+      });
+    invoke("get_palettes")
+      .then((res) => setPalettes(res as string[]))
+      .catch((err) => {
+        console.error(err);
+      });
   }, []);
 
+  useEffect(() => {
+    if (communeQuery.trim().length < 2) {
+      setCommuneMatches([]);
+      return;
+    }
+    const timeout = setTimeout(() => {
+      invoke("search_communes", { query: communeQuery })
+        .then((res) => setCommuneMatches(res as CommuneMatch[]))
+        .catch((err) => console.error(err));
+    }, 300);
+    return () => clearTimeout(timeout);
+  }, [communeQuery]);
+
+  const handleSelectCommune = (match: CommuneMatch) => {
+    setDepartment(match.commune.codeDepartement);
+    setSuggestions({});
+    setExtraDepartments([]);
+    setAoiKind("commune");
+    setCommune(match.commune.code);
+    setBuffer(String(match.aoi.buffer));
+    setCommuneQuery("");
+    setCommuneMatches([]);
+  };
+
+  const handleEditManifest = () => {
+    invoke("get_manifest", { profile })
+      .then((res) => {
+        setManifestError(null);
+        setManifest(JSON.stringify(res, null, 2));
+      })
+      .catch((err) => setManifestError(String(err)));
+  };
+
+  const handleSaveManifest = () => {
+    if (manifest === null) return;
+    try {
+      invoke("save_manifest", { profile, manifest: JSON.parse(manifest) })
+        .then(() => setManifest(null))
+        .catch((err) => setManifestError(String(err)));
+    } catch (err) {
+      setManifestError(String(err));
+    }
+  };
+
+  const handlePickPolygonFile = async () => {
+    const selected = await open({
+      multiple: false,
+      filters: [{ name: "Polygones", extensions: ["shp", "geojson", "gpkg", "kml"] }],
+    });
+    if (typeof selected === "string") {
+      setPolygonPath(selected);
+    }
+  };
+
+  const buildClipArea = (): ClipArea | null => {
+    const distance = parseFloat(buffer) || 0;
+    switch (aoiKind) {
+      case "bounding_box": {
+        const [xmin, ymin, xmax, ymax] = bbox.map(parseFloat);
+        return { area: { kind: "bounding_box", xmin, ymin, xmax, ymax }, buffer: distance };
+      }
+      case "commune":
+        return { area: { kind: "commune", query: commune }, buffer: distance };
+      case "polygon_file":
+        return { area: { kind: "polygon_file", path: polygonPath }, buffer: distance };
+      default:
+        return null;
+    }
+  };
+
+  const handleSuggestDepartments = () => {
+    setSuggestionError(null);
+    invoke("suggest_departements", { code: department, aoi: buildClipArea() })
+      .then((res) => {
+        const found = res as { [key: string]: string };
+        setSuggestions(found);
+        setExtraDepartments(Object.keys(found));
+      })
+      .catch((err) => setSuggestionError(String(err)));
+  };
+
+  const toggleExtraDepartment = (code: string) => {
+    setExtraDepartments(
+      extraDepartments.includes(code)
+        ? extraDepartments.filter((c) => c !== code)
+        : [...extraDepartments, code]
+    );
+  };
+
+  const isAoiValid =
+    aoiKind === "none" ||
+    (aoiKind === "bounding_box" && bbox.every((value) => !isNaN(parseFloat(value)))) ||
+    (aoiKind === "commune" && commune.trim() !== "") ||
+    (aoiKind === "polygon_file" && polygonPath !== "");
+
   const sortedDepartmentKeys = Object.keys(departments).sort();
 
   return (
@@ -71,6 +216,44 @@ const NewProjectScreen: React.FC<NewProjectScreenProps> = ({
                 />
               </div>
             </div>
+            <div className="space-y-2">
+              <label
+                htmlFor="commune-search"
+                className="block text-sm font-medium text-gray-400"
+              >
+                Rechercher une commune
+              </label>
+              <div className="relative">
+                <Search
+                  className="absolute left-3 top-1/2 transform -translate-y-1/2 text-blue-400"
+                  size={20}
+                />
+                <Input
+                  id="commune-search"
+                  value={communeQuery}
+                  onChange={(e) => setCommuneQuery(e.target.value)}
+                  placeholder="nom, code INSEE ou code postal"
+                  className="pl-10 bg-[#3E3E42] border-[#3E3E42] text-white rounded-full h-12"
+                />
+              </div>
+              {communeMatches.length > 0 && (
+                <div className="max-h-48 overflow-y-auto rounded-md bg-[#3E3E42]">
+                  {communeMatches.map((match) => (
+                    <button
+                      key={match.commune.code}
+                      onClick={() => handleSelectCommune(match)}
+                      className="w-full px-3 py-2 text-left text-sm hover:bg-[#505055]"
+                    >
+                      {match.commune.nom} ({match.commune.codesPostaux[0] ?? match.commune.code})
+                      <span className="text-gray-400">
+                        {" "}
+                        - {match.departement_name ?? match.commune.codeDepartement}
+                      </span>
+                    </button>
+                  ))}
+                </div>
+              )}
+            </div>
             <div className="space-y-2">
               <label
                 htmlFor="department"
@@ -83,7 +266,14 @@ const NewProjectScreen: React.FC<NewProjectScreenProps> = ({
                   className="absolute left-3 top-1/2 transform -translate-y-1/2 text-blue-400"
                   size={20}
                 />
-                <Select value={department} onValueChange={setDepartment}>
+                <Select
+                  value={department}
+                  onValueChange={(value) => {
+                    setDepartment(value);
+                    setSuggestions({});
+                    setExtraDepartments([]);
+                  }}
+                >
                   <SelectTrigger
                     id="department"
                     className="pl-10 bg-[#3E3E42] border-[#3E3E42] text-white rounded-full h-12"
@@ -100,10 +290,217 @@ const NewProjectScreen: React.FC<NewProjectScreenProps> = ({
                 </Select>
               </div>
             </div>
+            <div className="space-y-2">
+              <label
+                htmlFor="profile"
+                className="block text-sm font-medium text-gray-400"
+              >
+                Profil des couches
+              </label>
+              <div className="flex items-center space-x-2">
+                <div className="relative flex-1">
+                  <Layers
+                    className="absolute left-3 top-1/2 transform -translate-y-1/2 text-blue-400"
+                    size={20}
+                  />
+                  <Select
+                    value={profile}
+                    onValueChange={(value) => {
+                      setProfile(value);
+                      setManifest(null);
+                    }}
+                  >
+                    <SelectTrigger
+                      id="profile"
+                      className="pl-10 bg-[#3E3E42] border-[#3E3E42] text-white rounded-full h-12"
+                    >
+                      <SelectValue placeholder="Selectionez un profil" />
+                    </SelectTrigger>
+                    <SelectContent className="bg-[#3E3E42] border-[#3E3E42] text-white">
+                      {profiles.map((name) => (
+                        <SelectItem key={name} value={name}>
+                          {name}
+                        </SelectItem>
+                      ))}
+                    </SelectContent>
+                  </Select>
+                </div>
+                <Button
+                  variant="ghost"
+                  size="icon"
+                  onClick={manifest === null ? handleEditManifest : handleSaveManifest}
+                >
+                  {manifest === null ? <Pencil size={20} /> : <Save size={20} />}
+                </Button>
+              </div>
+              {manifest !== null && (
+                <textarea
+                  value={manifest}
+                  onChange={(e) => setManifest(e.target.value)}
+                  className="w-full h-64 p-2 bg-[#3E3E42] text-white text-xs font-mono rounded-md"
+                />
+              )}
+              {manifestError && (
+                <p className="text-sm text-red-500">{manifestError}</p>
+              )}
+            </div>
+            <div className="space-y-2">
+              <label
+                htmlFor="palette"
+                className="block text-sm font-medium text-gray-400"
+              >
+                Palette
+              </label>
+              <div className="relative">
+                <Palette
+                  className="absolute left-3 top-1/2 transform -translate-y-1/2 text-blue-400"
+                  size={20}
+                />
+                <Select value={palette} onValueChange={setPalette}>
+                  <SelectTrigger
+                    id="palette"
+                    className="pl-10 bg-[#3E3E42] border-[#3E3E42] text-white rounded-full h-12"
+                  >
+                    <SelectValue placeholder="Selectionez une palette" />
+                  </SelectTrigger>
+                  <SelectContent className="bg-[#3E3E42] border-[#3E3E42] text-white">
+                    {palettes.map((name) => (
+                      <SelectItem key={name} value={name}>
+                        {name}
+                      </SelectItem>
+                    ))}
+                  </SelectContent>
+                </Select>
+              </div>
+            </div>
+            <div className="space-y-2">
+              <label
+                htmlFor="crs"
+                className="block text-sm font-medium text-gray-400"
+              >
+                Système de coordonnées du projet
+              </label>
+              <Input
+                id="crs"
+                value={crs}
+                onChange={(e) => setCrs(e.target.value)}
+                placeholder="CRS officiel du département (ex. EPSG:2154)"
+                className="bg-[#3E3E42] border-[#3E3E42] text-white rounded-full h-12"
+              />
+            </div>
+            <div className="space-y-2">
+              <label
+                htmlFor="aoi"
+                className="block text-sm font-medium text-gray-400"
+              >
+                Zone d'intérêt
+              </label>
+              <div className="relative">
+                <Crop
+                  className="absolute left-3 top-1/2 transform -translate-y-1/2 text-blue-400"
+                  size={20}
+                />
+                <Select
+                  value={aoiKind}
+                  onValueChange={(value) => setAoiKind(value as AoiKind)}
+                >
+                  <SelectTrigger
+                    id="aoi"
+                    className="pl-10 bg-[#3E3E42] border-[#3E3E42] text-white rounded-full h-12"
+                  >
+                    <SelectValue />
+                  </SelectTrigger>
+                  <SelectContent className="bg-[#3E3E42] border-[#3E3E42] text-white">
+                    <SelectItem value="none">Département entier</SelectItem>
+                    <SelectItem value="bounding_box">Emprise (CRS du département)</SelectItem>
+                    <SelectItem value="commune">Commune</SelectItem>
+                    <SelectItem value="polygon_file">Fichier polygone</SelectItem>
+                  </SelectContent>
+                </Select>
+              </div>
+              {aoiKind === "bounding_box" && (
+                <div className="grid grid-cols-2 gap-2">
+                  {["xmin", "ymin", "xmax", "ymax"].map((label, index) => (
+                    <Input
+                      key={label}
+                      value={bbox[index]}
+                      onChange={(e) =>
+                        setBbox(bbox.map((v, i) => (i === index ? e.target.value : v)))
+                      }
+                      placeholder={label}
+                      className="bg-[#3E3E42] border-[#3E3E42] text-white"
+                    />
+                  ))}
+                </div>
+              )}
+              {aoiKind === "commune" && (
+                <Input
+                  value={commune}
+                  onChange={(e) => setCommune(e.target.value)}
+                  placeholder="nom ou code INSEE"
+                  className="bg-[#3E3E42] border-[#3E3E42] text-white"
+                />
+              )}
+              {aoiKind === "polygon_file" && (
+                <Button
+                  variant="secondary"
+                  className="w-full justify-start truncate"
+                  onClick={handlePickPolygonFile}
+                >
+                  {polygonPath || "Choisir un fichier"}
+                </Button>
+              )}
+              {aoiKind !== "none" && (
+                <Input
+                  value={buffer}
+                  onChange={(e) => setBuffer(e.target.value)}
+                  placeholder="marge (m)"
+                  className="bg-[#3E3E42] border-[#3E3E42] text-white"
+                />
+              )}
+              {aoiKind !== "none" && (
+                <Button
+                  variant="ghost"
+                  className="w-full justify-start"
+                  onClick={handleSuggestDepartments}
+                  disabled={!department || !isAoiValid}
+                >
+                  <MapPinned className="mr-2" size={20} />
+                  Suggérer les départements voisins
+                </Button>
+              )}
+              {Object.keys(suggestions).map((code) => (
+                <label key={code} className="flex items-center space-x-2 text-sm">
+                  <input
+                    type="checkbox"
+                    checked={extraDepartments.includes(code)}
+                    onChange={() => toggleExtraDepartment(code)}
+                  />
+                  <span>{suggestions[code]}</span>
+                </label>
+              ))}
+              {suggestionError && (
+                <p className="text-sm text-red-500">{suggestionError}</p>
+              )}
+            </div>
             <Button
-              onClick={() => onLoading(department, projectName)}
+              onClick={() =>
+                onLoading(
+                  [department, ...extraDepartments.filter((c) => c !== department)],
+                  projectName,
+                  profile,
+                  palette,
+                  crs.trim(),
+                  buildClipArea()
+                )
+              }
               className="w-full bg-blue-600 hover:bg-blue-700 rounded-full h-12 mt-6"
-              disabled={!projectName || !department || !departments[department]}
+              disabled={
+                !projectName ||
+                !department ||
+                !departments[department] ||
+                !isAoiValid
+              }
             >
               Suivant
               <ChevronRight className="ml-2" size={20} />
@@ -2,3 +2,57 @@ export interface RecentMap {
   title: string;
   data: { image_path: string; project_path: string }[];
 }
+
+export type AreaOfInterest =
+  | { kind: "bounding_box"; xmin: number; ymin: number; xmax: number; ymax: number }
+  | { kind: "commune"; query: string }
+  | { kind: "polygon_file"; path: string };
+
+export interface ClipArea {
+  area: AreaOfInterest;
+  buffer: number;
+}
+
+export interface Commune {
+  code: string;
+  nom: string;
+  codesPostaux: string[];
+  codeDepartement: string;
+  codeRegion: string;
+  codeEpci: string | null;
+}
+
+export interface CommuneMatch {
+  commune: Commune;
+  departement_name: string | null;
+  region_name: string | null;
+  epci_name: string | null;
+  aoi: ClipArea;
+}
+
+export type SymbolType = "fill" | "line" | "marker";
+
+export interface CategoryStyle {
+  value: string;
+  label: string;
+  color: string;
+  visible: boolean;
+  width: number | null;
+  size: number | null;
+}
+
+export interface LayerRenderer {
+  kind: string;
+  field: string | null;
+  symbol_type: SymbolType;
+  categories: CategoryStyle[];
+}
+
+export interface CategoryUpdate {
+  value: string;
+  color?: string;
+  visible?: boolean;
+  label?: string;
+  width?: number;
+  size?: number;
+}